@@ -0,0 +1,43 @@
+use std::fmt::Write;
+use std::path::Path;
+
+/// Generates a test for every intcode test spec under `specs/`, so that `cargo test` runs them
+/// like any other test.
+fn main() {
+    println!("cargo:rerun-if-changed=specs");
+
+    let mut specs = Vec::new();
+    collect_specs(Path::new("specs"), &mut specs);
+    specs.sort();
+
+    let mut tests = String::new();
+    for spec in specs.iter() {
+        let name = spec.trim_start_matches("specs/")
+            .trim_end_matches(".toml")
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect::<String>();
+        writeln!(tests, "#[test]").unwrap();
+        writeln!(tests, "fn spec_{}() {{", name).unwrap();
+        writeln!(tests, "    crate::intcode::spec::check_spec_file(concat!(env!(\"CARGO_MANIFEST_DIR\"), \"/{}\"));", spec).unwrap();
+        writeln!(tests, "}}").unwrap();
+    }
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    std::fs::write(Path::new(&out_dir).join("spec_tests.rs"), tests).expect("failed to write spec tests");
+}
+
+fn collect_specs(directory: &Path, specs: &mut Vec<String>) {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.map_while(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_specs(&path, specs);
+        } else if path.extension().is_some_and(|extension| extension == "toml") {
+            specs.push(path.to_string_lossy().replace('\\', "/"));
+        }
+    }
+}
@@ -0,0 +1,11 @@
+in 1
+out 0
+out 0
+out 0
+out 0
+out 0
+out 0
+out 0
+out 0
+out 0
+out 15426686
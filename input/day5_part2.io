@@ -0,0 +1,2 @@
+in 5
+out 11430197
@@ -1,21 +1,17 @@
 use std::error::Error;
-use advent_of_code_2019::intcode::{SimpleMemory, Computer, Memory};
+use advent_of_code_2019::intcode::{Binding, Search, SearchSpace, SimpleMemory, Computer, Memory};
 use failure::ResultExt;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let result = run_gravity_assist_with_parameters(12, 2)?;
 
     let goal_result = 19690720;
-    let mut necessary_parameters = None;
-    'outer: for noun in 0..=99 {
-        for verb in 0..=99 {
-            let current_result = run_gravity_assist_with_parameters(noun, verb)?;
-            if current_result == goal_result {
-                necessary_parameters = Some((noun, verb));
-                break 'outer;
-            }
-        }
-    }
+    let program = SimpleMemory::from_memory_file("input/day2.txt")?;
+    let necessary_parameters = Search::new(program.as_slice(), SearchSpace::product(vec![SearchSpace::range(0..=99), SearchSpace::range(0..=99)]))
+        .with_bindings(vec![Binding::Patch(1), Binding::Patch(2)])
+        .find_first(|outcome| outcome.memory.read_slot(0) == Ok(goal_result))
+        .compat()?
+        .map(|found| (found.candidate[0], found.candidate[1]));
 
     println!("initial result in slot 0: {}", result);
     if let Some((noun, verb)) = necessary_parameters {
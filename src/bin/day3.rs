@@ -1,5 +1,6 @@
 use std::error::Error;
 use advent_of_code_2019::load_lines_from;
+use advent_of_code_2019::grid::{manhattan_distance, Direction, Point};
 use failure::{Fail, ResultExt};
 use std::str::FromStr;
 use std::collections::HashSet;
@@ -11,45 +12,37 @@ pub enum WiringError {
     InvalidWiringInstruction,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum WiringDirection {
-    Right,
-    Up,
-    Left,
-    Down,
-}
-
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct WiringInstruction {
-    direction: WiringDirection,
+    direction: Direction,
     distance: i32,
 }
 
 impl WiringInstruction {
     pub fn right(distance: i32) -> WiringInstruction {
         WiringInstruction {
-            direction: WiringDirection::Right,
+            direction: Direction::Right,
             distance,
         }
     }
 
     pub fn up(distance: i32) -> WiringInstruction {
         WiringInstruction {
-            direction: WiringDirection::Up,
+            direction: Direction::Up,
             distance,
         }
     }
 
     pub fn left(distance: i32) -> WiringInstruction {
         WiringInstruction {
-            direction: WiringDirection::Left,
+            direction: Direction::Left,
             distance,
         }
     }
 
     pub fn down(distance: i32) -> WiringInstruction {
         WiringInstruction {
-            direction: WiringDirection::Down,
+            direction: Direction::Down,
             distance,
         }
     }
@@ -65,10 +58,10 @@ impl FromStr for WiringInstruction {
         let direction = match s.chars()
             .nth(0)
             .ok_or(WiringError::InvalidWiringInstruction)? {
-            'u' | 'U' => WiringDirection::Up,
-            'd' | 'D' => WiringDirection::Down,
-            'l' | 'L' => WiringDirection::Left,
-            'r' | 'R' => WiringDirection::Right,
+            'u' | 'U' => Direction::Up,
+            'd' | 'D' => Direction::Down,
+            'l' | 'L' => Direction::Left,
+            'r' | 'R' => Direction::Right,
             _ => return Err(WiringError::InvalidWiringInstruction),
         };
         let distance = s[1..].parse::<i32>()
@@ -92,23 +85,13 @@ impl Wire {
         }
     }
 
-    fn to_coords(&self) -> Vec<(i32, i32)> {
+    fn to_coords(&self) -> Vec<Point> {
         let mut position = (0, 0);
         let mut coords = Vec::new();
         for instruction in self.instructions.iter() {
             for _ in 0..instruction.distance {
-                let offset = match instruction.direction {
-                    WiringDirection::Up => (0, 1),
-                    WiringDirection::Down => (0, -1),
-                    WiringDirection::Left => (-1, 0),
-                    WiringDirection::Right => (1, 0),
-                };
-                let coordinate = (
-                    position.0 + offset.0,
-                    position.1 + offset.1,
-                );
-                position = coordinate;
-                coords.push(coordinate);
+                position = instruction.direction.step(position);
+                coords.push(position);
             }
         }
         coords
@@ -127,11 +110,7 @@ impl FromStr for Wire {
     }
 }
 
-fn manhatten_distance_between(a: (i32, i32), b: (i32, i32)) -> i32 {
-    (b.0 - a.0).abs() + (b.1 - a.1).abs()
-}
-
-fn signal_time_to_point(wire: &[(i32, i32)], point: (i32, i32)) -> Option<usize> {
+fn signal_time_to_point(wire: &[Point], point: Point) -> Option<usize> {
     wire.iter()
         .enumerate()
         .find(|(_, wire_point)| **wire_point == point)
@@ -167,10 +146,10 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 struct AnalysisOutcome {
-    cross_over_signal_times: Vec<((i32, i32), usize)>,
-    nearest_cross_over: (i32, i32),
+    cross_over_signal_times: Vec<(Point, usize)>,
+    nearest_cross_over: Point,
     distance_to_nearest: i32,
-    nearest_cross_over_in_signal_time: ((i32, i32), usize),
+    nearest_cross_over_in_signal_time: (Point, usize),
 }
 
 fn analyse_circuit(wires: Vec<Wire>) -> AnalysisOutcome {
@@ -193,20 +172,20 @@ fn analyse_circuit(wires: Vec<Wire>) -> AnalysisOutcome {
             cross_overs.push(*intersection);
         }
     }
-    if cross_overs.len() < 1 {
+    if cross_overs.is_empty() {
         panic!("expected at least 1 non-origin cross-over!");
     }
     let central_port = (0, 0);
     cross_overs.sort_by(|a, b| -> Ordering {
-        manhatten_distance_between(central_port, *a).cmp(&manhatten_distance_between(central_port, *b))
+        manhattan_distance(central_port, *a).cmp(&manhattan_distance(central_port, *b))
     });
     let mut cross_over_signal_times = cross_overs.iter()
         .map(|cross_over| Some((*cross_over, signal_time_to_point(wire_a_coordinates, *cross_over)? + signal_time_to_point(wire_b_coordinates, *cross_over)?)))
         .collect::<Option<Vec<_>>>()
         .expect("failed to find cross overs in one of the wires");
-    cross_over_signal_times.sort_by(|a, b| a.1.cmp(&b.1));
+    cross_over_signal_times.sort_by_key(|a| a.1);
     let nearest_cross_over = cross_overs[0];
-    let distance_to_nearest = manhatten_distance_between(central_port, nearest_cross_over);
+    let distance_to_nearest = manhattan_distance(central_port, nearest_cross_over);
     let nearest_cross_over_in_signal_time = cross_over_signal_times[0];
     AnalysisOutcome {
         cross_over_signal_times,
@@ -62,6 +62,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use crate::{has_only_two_adjacent_digits, digits_never_decrease, validate_value, SIX_DIGIT_RANGE};
 
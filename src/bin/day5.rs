@@ -1,14 +1,31 @@
 use std::error::Error;
-use advent_of_code_2019::intcode::{SimpleMemory, Computer};
+use advent_of_code_2019::intcode::{load_io_record, replay, save_io_record, SimpleMemory, Computer};
 use failure::ResultExt;
 
+const USAGE: &str = "usage: day5 [--record FILE | --replay FILE]
+
+Runs the diagnostic program interactively. --record saves the session's inputs and outputs so it
+can later be checked with --replay, which feeds the recorded inputs back in and stops at the
+first output that differs.";
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>();
     let mut memory = SimpleMemory::from_memory_file("input/day5.txt")?;
 
     let mut computer = Computer::new(&mut memory);
 
-    while !computer.halted {
-        computer.step().compat()?;
+    match args.as_slice() {
+        [] | ["--record", _] => {
+            while !computer.halted {
+                computer.step().compat()?;
+            }
+        },
+        ["--replay", path] => {
+            replay(&mut computer, &load_io_record(path)?).compat()?;
+            println!("replay matched the recording");
+        },
+        _ => return Err(USAGE.into()),
     }
 
     println!("IO record:");
@@ -16,5 +33,10 @@ fn main() -> Result<(), Box<dyn Error>> {
         println!("  {:?}", event);
     }
 
+    if let ["--record", path] = args.as_slice() {
+        save_io_record(path, &computer.io_record)?;
+        println!("saved session to {}", path);
+    }
+
     Ok(())
 }
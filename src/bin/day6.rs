@@ -58,13 +58,13 @@ impl OrbitData {
     }
 
     fn get(&self, name: &str) -> Result<&Body, OrbitError> {
-        Ok(self.bodies.get(name).ok_or(OrbitError::InvalidBodyName)?)
+        self.bodies.get(name).ok_or(OrbitError::InvalidBodyName)
     }
 
     fn get_parent_of(&self, name: &str) -> Result<Option<&Body>, OrbitError> {
         let body = self.get(name)?;
         if let Some(parent_name) = &body.parent {
-            Ok(Some(self.get(&parent_name)?))
+            Ok(Some(self.get(parent_name)?))
         } else {
             Ok(None)
         }
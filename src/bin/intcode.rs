@@ -0,0 +1,348 @@
+use std::error::Error;
+use std::process::exit;
+use std::sync::Arc;
+use advent_of_code_2019::intcode::{disassemble, Computer, DebugInfo, ExecutionBudget, Fault, Memory, ProtectionMap, RecordedIO, SimpleMemory, TaintTracker, TraceLevel};
+use failure::ResultExt;
+use serde_json::json;
+
+const USAGE: &str = "usage: intcode <program> [options]
+
+Runs an intcode program, given as text or as a binary image. Debug info kept next to the
+program (as <program>.dbg) or in the image is used to show source locations and symbol names in
+faults, traces and disassembly.
+
+options:
+  --input VALUES        comma separated values to feed the program, instead of prompting
+  --input-file FILE     read input values (separated by commas or whitespace) from FILE
+  --patch PATCHES       set memory before running, e.g. 1=12,2=2 (may be repeated)
+  --trace LEVEL         silent (default), io or instructions
+  --max-cycles N        give up after N instructions
+  --protect REGIONS     protect memory, e.g. 0..120:ro,120..200:nx,500..:guard (may be repeated,
+                        and adds to any protection in the image)
+  --format FORMAT       how to print outputs: plain (default), json or ascii
+  --taint MODE          show which inputs each output depends on, following data only
+                        (data) or jump conditions too (control); ascii output reports it on
+                        stderr
+  --dump-memory         print the final contents of memory
+  --debug-info FILE     read debug info from FILE instead
+  --disassemble         print a disassembly listing instead of running
+
+exit codes:
+  0  the program halted
+  1  the program faulted
+  2  the cycle budget ran out
+  3  bad arguments or unreadable program";
+
+const EXIT_HALTED: i32 = 0;
+const EXIT_FAULTED: i32 = 1;
+const EXIT_BUDGET_EXCEEDED: i32 = 2;
+const EXIT_USAGE: i32 = 3;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum OutputFormat {
+    Plain,
+    Json,
+    Ascii,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum TaintMode {
+    Data,
+    Control,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Options {
+    program: String,
+    inputs: Option<Vec<i32>>,
+    patches: Vec<(usize, i32)>,
+    trace_level: TraceLevel,
+    max_cycles: Option<usize>,
+    protection: ProtectionMap,
+    format: OutputFormat,
+    taint: Option<TaintMode>,
+    dump_memory: bool,
+    debug_info: Option<String>,
+    disassemble: bool,
+}
+
+/// Parses values separated by commas or whitespace, ignoring `#` comments.
+fn parse_values(text: &str) -> Result<Vec<i32>, Box<dyn Error>> {
+    let mut values = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        for value in line.split(|c: char| c == ',' || c.is_whitespace()).filter(|value| !value.is_empty()) {
+            values.push(value.parse().map_err(|_| format!("invalid input value '{}'", value))?);
+        }
+    }
+    Ok(values)
+}
+
+fn parse_patches(text: &str) -> Result<Vec<(usize, i32)>, Box<dyn Error>> {
+    text.split(',')
+        .map(|patch| {
+            let mut parts = patch.splitn(2, '=');
+            match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+                (Some(address), Some(value)) => Ok((address.parse()?, value.parse()?)),
+                _ => Err(format!("invalid patch '{}', expected ADDRESS=VALUE", patch).into()),
+            }
+        })
+        .collect()
+}
+
+fn parse_options(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut program = None;
+    let mut inputs: Option<Vec<i32>> = None;
+    let mut patches = Vec::new();
+    let mut trace_level = TraceLevel::Silent;
+    let mut max_cycles = None;
+    let mut protection = ProtectionMap::new();
+    let mut format = OutputFormat::Plain;
+    let mut taint = None;
+    let mut dump_memory = false;
+    let mut debug_info = None;
+    let mut disassemble = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => inputs.get_or_insert_with(Vec::new).extend(parse_values(args.next().ok_or(USAGE)?)?),
+            "--input-file" => {
+                let text = std::fs::read_to_string(args.next().ok_or(USAGE)?)?;
+                inputs.get_or_insert_with(Vec::new).extend(parse_values(&text)?);
+            },
+            "--patch" => patches.extend(parse_patches(args.next().ok_or(USAGE)?)?),
+            "--trace" => trace_level = match args.next().ok_or(USAGE)?.as_str() {
+                "silent" => TraceLevel::Silent,
+                "io" => TraceLevel::Io,
+                "instructions" => TraceLevel::Instructions,
+                other => return Err(format!("unknown trace level '{}'", other).into()),
+            },
+            "--max-cycles" => max_cycles = Some(args.next().ok_or(USAGE)?.parse()?),
+            "--protect" => protection.extend(&args.next().ok_or(USAGE)?.parse::<ProtectionMap>().compat()?),
+            "--format" => format = match args.next().ok_or(USAGE)?.as_str() {
+                "plain" => OutputFormat::Plain,
+                "json" => OutputFormat::Json,
+                "ascii" => OutputFormat::Ascii,
+                other => return Err(format!("unknown output format '{}'", other).into()),
+            },
+            "--taint" => taint = Some(match args.next().ok_or(USAGE)?.as_str() {
+                "data" => TaintMode::Data,
+                "control" => TaintMode::Control,
+                other => return Err(format!("unknown taint mode '{}'", other).into()),
+            }),
+            "--dump-memory" => dump_memory = true,
+            "--debug-info" => debug_info = Some(args.next().ok_or(USAGE)?.clone()),
+            "--disassemble" => disassemble = true,
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if program.is_none() && !arg.starts_with("--") => program = Some(arg.clone()),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    Ok(Options {
+        program: program.ok_or(USAGE)?,
+        inputs,
+        patches,
+        trace_level,
+        max_cycles,
+        protection,
+        format,
+        taint,
+        dump_memory,
+        debug_info,
+        disassemble,
+    })
+}
+
+fn exit_code(result: &Result<(), Fault>) -> i32 {
+    match result {
+        Ok(()) => EXIT_HALTED,
+        Err(fault) if fault.error.is_budget_exceeded() => EXIT_BUDGET_EXCEEDED,
+        Err(_) => EXIT_FAULTED,
+    }
+}
+
+fn format_ascii(outputs: &[i32]) -> String {
+    let mut text = String::new();
+    for value in outputs.iter() {
+        match *value {
+            value @ 0..=127 => text.push(value as u8 as char),
+            // anything outside ASCII (like a final answer) goes on its own line
+            value => {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                text += &format!("{}\n", value);
+            },
+        }
+    }
+    text
+}
+
+fn run(options: &Options) -> Result<i32, Box<dyn Error>> {
+    let (mut memory, entry_point, mut protection) = SimpleMemory::load_program(&options.program)?;
+    protection.extend(&options.protection);
+    let debug_info = match &options.debug_info {
+        Some(path) => Some(DebugInfo::load(path)?),
+        None => DebugInfo::load_for(&options.program)?,
+    }.map(Arc::new);
+    if options.disassemble {
+        let lines = disassemble(&memory, 0, usize::MAX);
+        match debug_info.as_deref() {
+            Some(debug_info) => println!("{}", debug_info.listing(&lines)),
+            None => println!("{}", DebugInfo::new().listing(&lines)),
+        }
+        return Ok(EXIT_HALTED);
+    }
+    for (address, value) in options.patches.iter() {
+        memory.write_slot(*address, *value).compat()?;
+    }
+
+    let mut budget = ExecutionBudget::unlimited();
+    if let Some(max_cycles) = options.max_cycles {
+        budget = budget.with_max_cycles(max_cycles);
+    }
+    let mut tracker = options.taint.map(|mode| match mode {
+        TaintMode::Data => TaintTracker::new(),
+        TaintMode::Control => TaintTracker::new().with_control_dependence(),
+    });
+    let mut computer = Computer::new(&mut memory)
+        .with_entry_point(entry_point)
+        .with_budget(budget)
+        .with_protection(protection)
+        .with_trace_level(options.trace_level);
+    if let Some(inputs) = &options.inputs {
+        computer = computer.with_inputs(inputs.iter().cloned());
+    }
+    if let Some(debug_info) = &debug_info {
+        computer = computer.with_debug_info(debug_info.clone());
+    }
+    if let Some(tracker) = &mut tracker {
+        computer = computer.with_hook(tracker);
+    }
+
+    let result = computer.run_until_halted();
+    let cycles = computer.cycle_count();
+    let outputs = computer.io_record.iter()
+        .filter_map(|event| match event {
+            RecordedIO::Output(value) => Some(*value),
+            RecordedIO::UserInput(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let code = exit_code(&result);
+
+    match options.format {
+        OutputFormat::Json => {
+            let (status, error) = match &result {
+                Ok(()) => ("halted", None),
+                Err(fault) if fault.error.is_budget_exceeded() => ("budget_exceeded", Some(fault.display_with(debug_info.as_deref()).to_string())),
+                Err(fault) => ("faulted", Some(fault.display_with(debug_info.as_deref()).to_string())),
+            };
+            let mut report = json!({
+                "status": status,
+                "cycles": cycles,
+                "outputs": outputs,
+            });
+            if let Some(error) = error {
+                report["error"] = json!(error);
+            }
+            if let Some(tracker) = &tracker {
+                let dependencies = tracker.outputs().iter().map(|output| &output.inputs).collect::<Vec<_>>();
+                report["dependencies"] = json!(dependencies);
+            }
+            if options.dump_memory {
+                report["memory"] = json!(memory.as_slice());
+            }
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        },
+        OutputFormat::Plain | OutputFormat::Ascii => {
+            match (options.format, &tracker) {
+                (OutputFormat::Ascii, tracker) => {
+                    print!("{}", format_ascii(&outputs));
+                    for output in tracker.iter().flat_map(|tracker| tracker.outputs()) {
+                        eprintln!("{}", output);
+                    }
+                },
+                (_, Some(tracker)) => for output in tracker.outputs() {
+                    println!("{}", output);
+                },
+                (_, None) => for value in outputs.iter() {
+                    println!("{}", value);
+                },
+            }
+            if options.dump_memory {
+                println!("{}", memory.to_program_text());
+            }
+            if let Err(fault) = &result {
+                eprintln!("error: {}", fault.display_with(debug_info.as_deref()));
+            }
+        },
+    }
+
+    Ok(code)
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let code = match parse_options(&args).and_then(|options| run(&options)) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("{}", error);
+            EXIT_USAGE
+        },
+    };
+    exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format_ascii, parse_options, parse_patches, parse_values, OutputFormat, TaintMode};
+    use advent_of_code_2019::intcode::TraceLevel;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn can_parse_options() {
+        let options = parse_options(&args("prog.txt --input 1,2 --input 3 --patch 1=12,2=2 --trace io --max-cycles 50 --protect 0..4:ro --protect 10..:guard --format json --taint control --dump-memory --debug-info prog.dbg")).unwrap();
+        assert_eq!(options.program, "prog.txt");
+        assert_eq!(options.inputs, Some(vec![1, 2, 3]));
+        assert_eq!(options.patches, vec![(1, 12), (2, 2)]);
+        assert_eq!(options.trace_level, TraceLevel::Io);
+        assert_eq!(options.max_cycles, Some(50));
+        assert_eq!(options.protection.to_string(), "0..4:ro,10..:guard");
+        assert_eq!(options.format, OutputFormat::Json);
+        assert_eq!(options.taint, Some(TaintMode::Control));
+        assert!(options.dump_memory);
+        assert_eq!(options.debug_info, Some("prog.dbg".to_string()));
+        assert!(!options.disassemble);
+
+        let options = parse_options(&args("prog.txt")).unwrap();
+        assert_eq!(options.inputs, None);
+        assert_eq!(options.format, OutputFormat::Plain);
+        assert_eq!(options.taint, None);
+
+        assert!(parse_options(&args("--input 1")).is_err());
+        assert!(parse_options(&args("prog.txt --format xml")).is_err());
+        assert!(parse_options(&args("prog.txt --protect 0..4:rw")).is_err());
+        assert!(parse_options(&args("prog.txt --taint everything")).is_err());
+        assert!(parse_options(&args("prog.txt other.txt")).is_err());
+    }
+
+    #[test]
+    fn can_parse_values_and_patches() {
+        assert_eq!(parse_values("1, 2\n-3 4 # comment\n").unwrap(), vec![1, 2, -3, 4]);
+        assert!(parse_values("1,x").is_err());
+        assert_eq!(parse_patches("0=-1").unwrap(), vec![(0, -1)]);
+        assert!(parse_patches("0").is_err());
+        assert!(parse_patches("a=1").is_err());
+    }
+
+    #[test]
+    fn formats_ascii_output() {
+        assert_eq!(format_ascii(&[72, 105, 10, 1234]), "Hi\n1234\n");
+        assert_eq!(format_ascii(&[33, 99999]), "!\n99999\n");
+    }
+}
@@ -0,0 +1,57 @@
+use std::error::Error;
+use advent_of_code_2019::intcode::{Analyzer, DebugInfo, SimpleMemory};
+
+const USAGE: &str = "usage: intcode_analyse <program> [--input VALUES]
+
+Statically analyses an intcode program and prints an annotated disassembly: which code is never
+executed, which jumps always go the same way and which code or data is written at runtime.
+--input gives comma separated values to assume the program reads, which lets the analysis
+follow code that depends on them. Labels and source lines from the program's debug info are
+shown alongside the code when it has any.";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (path, inputs) = match args.as_slice() {
+        [path] if !path.starts_with("--") => (path, None),
+        [path, flag, values] if flag == "--input" => {
+            let values = values.split(',')
+                .map(|value| value.trim().parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()?;
+            (path, Some(values))
+        },
+        _ => return Err(USAGE.into()),
+    };
+
+    let debug_info = DebugInfo::load_for(path)?;
+    let (memory, entry_point, _) = SimpleMemory::load_program(path)?;
+
+    let mut analyzer = Analyzer::new(&memory).with_entry_point(entry_point);
+    if let Some(inputs) = inputs {
+        analyzer = analyzer.with_inputs(inputs);
+    }
+    let analysis = analyzer.run();
+
+    for line in analysis.disassemble() {
+        match &debug_info {
+            Some(debug_info) => {
+                if let Some(label) = debug_info.label_at(line.line.address) {
+                    println!("{}:", label);
+                }
+                match debug_info.location(line.line.address) {
+                    Some(location) => println!("{:<80} ; {}", line.to_string(), location),
+                    None => println!("{}", line),
+                }
+            },
+            None => println!("{}", line),
+        }
+    }
+    println!();
+    println!("{} reachable instructions, {} constant cells", analysis.reachable_instructions().len(), analysis.constant_cells().len());
+    for range in analysis.overwritten_code() {
+        println!("code overwritten at runtime: {}..{}", range.start, range.end);
+    }
+    for limitation in analysis.limitations() {
+        println!("incomplete: {}", limitation);
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::process::exit;
+use advent_of_code_2019::intcode::{Compiler, DebugInfo};
+
+const USAGE: &str = "usage: intcode_compile <source> <output> [--text] [--stack-size CELLS]
+
+Compiles a program written in the intcode compiler's structured language. The output is a binary
+image, or comma separated text with --text, and its debug info is written next to it as
+<output>.dbg for the other intcode tools to pick up. --stack-size sets how much memory is left
+for function calls (1024 cells by default).";
+
+struct Options {
+    source: String,
+    output: String,
+    text: bool,
+    stack_size: Option<usize>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut text = false;
+    let mut stack_size = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--text" => text = true,
+            "--stack-size" => stack_size = Some(args.next().ok_or(USAGE)?.parse()?),
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if !arg.starts_with("--") => positional.push(arg.clone()),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err(USAGE.into());
+    }
+
+    Ok(Options {
+        source: positional[0].clone(),
+        output: positional[1].clone(),
+        text,
+        stack_size,
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let options = parse_options(&args)?;
+
+    let source = std::fs::read_to_string(&options.source)?;
+    let mut compiler = Compiler::new(&source).with_file_name(options.source.clone());
+    if let Some(stack_size) = options.stack_size {
+        compiler = compiler.with_stack_size(stack_size);
+    }
+    let program = match compiler.compile() {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}: {}", options.source, error.render(&source));
+            exit(1);
+        },
+    };
+
+    if options.text {
+        std::fs::write(&options.output, program.memory().to_program_text() + "\n")?;
+    } else {
+        program.image.save(&options.output, true)?;
+    }
+    let debug_path = DebugInfo::path_for(&options.output);
+    program.debug_info.save(&debug_path)?;
+
+    println!("wrote {} cells to {}", program.image.memory.len(), options.output);
+    println!("wrote debug info to {}", debug_path.display());
+    Ok(())
+}
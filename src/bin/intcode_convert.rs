@@ -0,0 +1,89 @@
+use std::error::Error;
+use advent_of_code_2019::intcode::{ProgramImage, ProtectionMap, SimpleMemory};
+use failure::ResultExt;
+
+const USAGE: &str = "usage: intcode_convert <input> <output> [--checksum] [--name NAME] [--entry ADDRESS] [--protect REGIONS]
+
+Converts between comma separated intcode programs and binary images. The direction is
+chosen from the input: binary images become text, anything else is parsed as text and
+written out as a binary image. --protect stores memory protection in the image for the
+intcode runner to apply, e.g. 0..120:ro,120..200:nx,500..:guard.";
+
+struct Options {
+    input: String,
+    output: String,
+    checksum: bool,
+    name: Option<String>,
+    entry_point: Option<usize>,
+    protection: ProtectionMap,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut checksum = false;
+    let mut name = None;
+    let mut entry_point = None;
+    let mut protection = ProtectionMap::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--checksum" => checksum = true,
+            "--name" => name = Some(args.next().ok_or(USAGE)?.clone()),
+            "--entry" => entry_point = Some(args.next().ok_or(USAGE)?.parse()?),
+            "--protect" => protection.extend(&args.next().ok_or(USAGE)?.parse::<ProtectionMap>().compat()?),
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err(USAGE.into());
+    }
+
+    Ok(Options {
+        input: positional[0].clone(),
+        output: positional[1].clone(),
+        checksum,
+        name,
+        entry_point,
+        protection,
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let options = parse_options(&args)?;
+
+    let bytes = std::fs::read(&options.input)?;
+    if ProgramImage::is_image(&bytes) {
+        let image = ProgramImage::decode(&bytes).compat()?;
+        let memory = SimpleMemory::from_image(&image);
+        std::fs::write(&options.output, memory.to_program_text() + "\n")?;
+
+        println!("wrote {} values as text to {}", image.memory.len(), options.output);
+        if let Some(name) = &image.name {
+            println!("  name: {}", name);
+        }
+        if image.entry_point != 0 {
+            println!("  entry point {} is not representable in text", image.entry_point);
+        }
+        if !image.symbols.is_empty() || image.line_info.is_some() {
+            println!("  symbols and line info are not representable in text");
+        }
+        if !image.protection.is_empty() {
+            println!("  protection {} is not representable in text", image.protection);
+        }
+    } else {
+        let memory = String::from_utf8(bytes)?.parse::<SimpleMemory>().compat()?;
+        let mut image = memory.to_image();
+        image.name = options.name;
+        image.entry_point = options.entry_point.unwrap_or(0);
+        image.protection = options.protection;
+        image.save(&options.output, options.checksum)?;
+
+        println!("wrote {} values as a binary image to {}", image.memory.len(), options.output);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::error::Error;
+use advent_of_code_2019::intcode::{diff_memory, Computer, ExecutionBudget, ProtectionMap, SimpleMemory, TraceLevel};
+
+const USAGE: &str = "usage: intcode_diff <before> <after>
+       intcode_diff <program> --run [INPUTS] [--max-cycles N]
+       intcode_diff <program> --runs INPUTS INPUTS [--max-cycles N]
+
+Shows which memory cells differ, grouped into ranges, along with the instructions they belong
+to. The first form compares two memory files. --run compares a program with its memory after
+running it on the given comma separated inputs, and --runs compares the final memories of two
+runs with different inputs. --max-cycles stops each run after N instructions.";
+
+fn parse_inputs(text: &str) -> Result<Vec<i32>, Box<dyn Error>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().map_err(|_| format!("invalid input value '{}'", value).into()))
+        .collect()
+}
+
+/// A program to run, as loaded from a file, along with how long to let each run go for.
+struct Program {
+    memory: SimpleMemory,
+    entry_point: usize,
+    protection: ProtectionMap,
+    max_cycles: Option<usize>,
+}
+
+impl Program {
+    fn load(path: &str, max_cycles: Option<usize>) -> Result<Program, Box<dyn Error>> {
+        let (memory, entry_point, protection) = SimpleMemory::load_program(path)?;
+        Ok(Program { memory, entry_point, protection, max_cycles })
+    }
+
+    fn run(&self, inputs: &str) -> Result<SimpleMemory, Box<dyn Error>> {
+        let mut budget = ExecutionBudget::unlimited();
+        if let Some(max_cycles) = self.max_cycles {
+            budget = budget.with_max_cycles(max_cycles);
+        }
+        let mut memory = SimpleMemory::from_literal(self.memory.as_slice());
+        let result = Computer::new(&mut memory)
+            .with_entry_point(self.entry_point)
+            .with_protection(self.protection.clone())
+            .with_budget(budget)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(parse_inputs(inputs)?)
+            .run_until_halted();
+        if let Err(fault) = result {
+            eprintln!("warning: run with inputs [{}] stopped early: {}", inputs, fault);
+        }
+        Ok(memory)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut max_cycles = None;
+    if let Some(index) = args.iter().position(|arg| arg == "--max-cycles") {
+        let value = args.get(index + 1).ok_or(USAGE)?;
+        max_cycles = Some(value.parse()?);
+        args.drain(index..=index + 1);
+    }
+    let args = args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>();
+    let (before, after) = match args.as_slice() {
+        [program, "--run"] => {
+            let program = Program::load(program, max_cycles)?;
+            let after = program.run("")?;
+            (program.memory, after)
+        },
+        [program, "--run", inputs] => {
+            let program = Program::load(program, max_cycles)?;
+            let after = program.run(inputs)?;
+            (program.memory, after)
+        },
+        [program, "--runs", first, second] => {
+            let program = Program::load(program, max_cycles)?;
+            (program.run(first)?, program.run(second)?)
+        },
+        [before, after] if max_cycles.is_none() && !before.starts_with("--") && !after.starts_with("--") => {
+            (SimpleMemory::load_program(before)?.0, SimpleMemory::load_program(after)?.0)
+        },
+        _ => return Err(USAGE.into()),
+    };
+
+    println!("{}", diff_memory(&before, &after));
+    Ok(())
+}
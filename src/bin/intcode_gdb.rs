@@ -0,0 +1,33 @@
+use std::error::Error;
+use advent_of_code_2019::intcode::{Computer, GdbStub, SimpleMemory, TraceLevel};
+use failure::ResultExt;
+
+const USAGE: &str = "usage: intcode_gdb <program> [--port PORT]
+
+Loads an intcode program (text or binary image) and waits for a debugger to connect using the
+GDB remote serial protocol on localhost, by default on port 1234.";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut program = None;
+    let mut port = 1234u16;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => port = args.next().ok_or(USAGE)?.parse()?,
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if program.is_none() => program = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let program = program.ok_or(USAGE)?;
+
+    let (mut memory, entry_point, protection) = SimpleMemory::load_program(&program)?;
+    let mut computer = Computer::new(&mut memory)
+        .with_entry_point(entry_point)
+        .with_protection(protection)
+        .with_trace_level(TraceLevel::Silent);
+    println!("waiting for a debugger on 127.0.0.1:{}", port);
+    GdbStub::new(&mut computer).listen(("127.0.0.1", port)).compat()?;
+    Ok(())
+}
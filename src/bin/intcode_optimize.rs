@@ -0,0 +1,33 @@
+use std::error::Error;
+use advent_of_code_2019::intcode::{optimize, ProgramImage, SimpleMemory};
+use failure::ResultExt;
+
+const USAGE: &str = "usage: intcode_optimize <input> <output>
+
+Rewrites wasteful instructions in an intcode program and prints a report of what changed. The
+output is written in the same format as the input: binary images stay images, keeping their name
+and entry point.";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (input, output) = match args.as_slice() {
+        [input, output] if !input.starts_with("--") && !output.starts_with("--") => (input, output),
+        _ => return Err(USAGE.into()),
+    };
+
+    let bytes = std::fs::read(input)?;
+    if ProgramImage::is_image(&bytes) {
+        let mut image = ProgramImage::decode(&bytes).compat()?;
+        let (optimized, report) = optimize(&image.memory, image.entry_point);
+        print!("{}", report);
+        image.memory = optimized;
+        image.save(output, false)?;
+    } else {
+        let memory = String::from_utf8(bytes)?.parse::<SimpleMemory>().compat()?;
+        let (optimized, report) = optimize(memory.as_slice(), 0);
+        print!("{}", report);
+        std::fs::write(output, SimpleMemory::from_literal(&optimized).to_program_text() + "\n")?;
+    }
+    println!("wrote {}", output);
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use advent_of_code_2019::intcode::{Analyzer, Computer, ExecutionBudget, SimpleMemory, Statistics, TraceLevel};
+use serde_json::json;
+
+const USAGE: &str = "usage: intcode_stats <program> [--input VALUES] [--max-cycles N] [--static] [--json]
+
+Counts the instructions in an intcode program by opcode and parameter modes, both statically
+(each instruction reachable from the entry point once) and dynamically (every instruction a run
+executes). Runs also report the fraction of conditional jumps taken and IO operations per
+thousand cycles. --input gives comma separated values for the run to read, which the static
+analysis assumes too, --static skips the run and --json prints the statistics as JSON instead
+of tables.";
+
+struct Options {
+    program: String,
+    inputs: Vec<i32>,
+    max_cycles: Option<usize>,
+    static_only: bool,
+    json: bool,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut program = None;
+    let mut inputs = Vec::new();
+    let mut max_cycles = None;
+    let mut static_only = false;
+    let mut json = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => for value in args.next().ok_or(USAGE)?.split(',') {
+                inputs.push(value.trim().parse()?);
+            },
+            "--max-cycles" => max_cycles = Some(args.next().ok_or(USAGE)?.parse()?),
+            "--static" => static_only = true,
+            "--json" => json = true,
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if program.is_none() && !arg.starts_with("--") => program = Some(arg.clone()),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    Ok(Options {
+        program: program.ok_or(USAGE)?,
+        inputs,
+        max_cycles,
+        static_only,
+        json,
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let options = parse_options(&args)?;
+
+    let (mut memory, entry_point, protection) = SimpleMemory::load_program(&options.program)?;
+
+    let analysis = Analyzer::new(&memory)
+        .with_entry_point(entry_point)
+        .with_inputs(options.inputs.iter().cloned())
+        .run();
+    for limitation in analysis.limitations() {
+        eprintln!("static counts are incomplete: {}", limitation);
+    }
+    let static_statistics = Statistics::from_analysis(&analysis);
+    let mut dynamic_statistics = None;
+    if !options.static_only {
+        let mut budget = ExecutionBudget::unlimited();
+        if let Some(max_cycles) = options.max_cycles {
+            budget = budget.with_max_cycles(max_cycles);
+        }
+        let mut statistics = Statistics::new();
+        let result = Computer::new(&mut memory)
+            .with_entry_point(entry_point)
+            .with_protection(protection)
+            .with_budget(budget)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(options.inputs.iter().cloned())
+            .with_hook(&mut statistics)
+            .run_until_halted();
+        // the statistics up to a fault are still worth having
+        if let Err(fault) = result {
+            eprintln!("run stopped early: {}", fault);
+        }
+        dynamic_statistics = Some(statistics);
+    }
+
+    if options.json {
+        let mut report = json!({ "static": static_statistics.to_json() });
+        if let Some(statistics) = &dynamic_statistics {
+            report["dynamic"] = statistics.to_json();
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("static:");
+        print!("{}", static_statistics);
+        if let Some(statistics) = &dynamic_statistics {
+            println!();
+            println!("dynamic:");
+            print!("{}", statistics);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use std::error::Error;
+use advent_of_code_2019::intcode::{Computer, SimpleMemory, TraceLevel, Visualizer, WriteTracker};
+
+const USAGE: &str = "usage: intcode_tui <program> [INPUT...]
+
+Runs an intcode program (text or binary image) in a full screen viewer. Any inputs given are
+queued up for the program; when they run out the viewer asks for more.";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (program, inputs) = match args.split_first() {
+        Some((program, _)) if program == "-h" || program == "--help" => return Err(USAGE.into()),
+        Some((program, inputs)) => (program, inputs),
+        None => return Err(USAGE.into()),
+    };
+    let inputs = inputs.iter()
+        .map(|input| input.parse())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (memory, entry_point, protection) = SimpleMemory::load_program(program)?;
+    let mut memory = WriteTracker::new(memory);
+    let mut computer = Computer::new(&mut memory)
+        .with_entry_point(entry_point)
+        .with_protection(protection)
+        .with_trace_level(TraceLevel::Silent)
+        .with_inputs(inputs);
+    Visualizer::new(&mut computer).run()
+}
@@ -0,0 +1,65 @@
+/// A position on an unbounded grid, with `y` increasing upwards.
+pub type Point = (i32, i32);
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+    Right,
+    Up,
+    Left,
+    Down,
+}
+
+impl Direction {
+    pub fn offset(&self) -> Point {
+        match self {
+            Direction::Up => (0, 1),
+            Direction::Down => (0, -1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        }
+    }
+
+    pub fn step(&self, from: Point) -> Point {
+        let offset = self.offset();
+        (from.0 + offset.0, from.1 + offset.1)
+    }
+
+    /// The direction a quarter turn anticlockwise from this one.
+    pub fn turn_left(&self) -> Direction {
+        match self {
+            Direction::Right => Direction::Up,
+            Direction::Up => Direction::Left,
+            Direction::Left => Direction::Down,
+            Direction::Down => Direction::Right,
+        }
+    }
+
+    /// The direction a quarter turn clockwise from this one.
+    pub fn turn_right(&self) -> Direction {
+        match self {
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+            Direction::Up => Direction::Right,
+        }
+    }
+}
+
+pub fn manhattan_distance(a: Point, b: Point) -> i32 {
+    (b.0 - a.0).abs() + (b.1 - a.1).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::{manhattan_distance, Direction};
+
+    #[test]
+    fn can_turn_and_step() {
+        assert_eq!(Direction::Up.turn_left(), Direction::Left);
+        assert_eq!(Direction::Up.turn_right(), Direction::Right);
+        assert_eq!(Direction::Down.turn_left().turn_left(), Direction::Up);
+        assert_eq!(Direction::Left.step((2, 3)), (1, 3));
+        assert_eq!(Direction::Up.step((2, 3)), (2, 4));
+        assert_eq!(manhattan_distance((1, 1), (-2, 3)), 5);
+    }
+}
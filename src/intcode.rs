@@ -1,32 +1,131 @@
-use failure::Fail;
+use failure::{Fail, ResultExt};
 use std::path::Path;
 use crate::load_file;
 use std::error::Error;
-use std::io::{stdin, stdout, Write};
+use std::io::{stdin, stdout, Read, Write};
+use std::str::FromStr;
+use std::time::Instant;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+pub mod parse;
+pub mod image;
+pub mod devices;
+pub mod budget;
+pub mod disassemble;
+pub mod fault;
+pub mod encode;
+pub mod registry;
+pub mod gdb;
+pub mod tui;
+pub mod framebuffer;
+pub mod robot;
+pub mod replay;
+pub mod spec;
+pub mod optimize;
+pub mod analysis;
+pub mod search;
+pub mod diff;
+pub mod breakpoint;
+pub mod debug_info;
+pub mod compiler;
+pub mod hooks;
+pub mod protection;
+pub mod stats;
+pub mod taint;
+
+pub use self::parse::{ProgramParseError, ProgramParseErrorKind};
+pub use self::image::{ProgramImage, ImageError};
+pub use self::budget::{ExecutionBudget, LoopDetector, LoopReport};
+pub use self::disassemble::{disassemble, disassemble_with, DisassemblyLine};
+pub use self::fault::{Fault, FaultDisplay};
+pub use self::encode::{encode_program, EncodeError};
+pub use self::registry::{Machine, OpcodeRegistry, OpcodeSpec, RegistryError};
+pub use self::gdb::{GdbError, GdbStub};
+pub use self::tui::{Visualizer, WriteTracker};
+pub use self::framebuffer::{run_display, Framebuffer, Palette};
+pub use self::robot::{Hull, HullRobot, RobotError, TurnConvention};
+pub use self::replay::{load_io_record, replay, save_io_record, Divergence, ReplayError};
+pub use self::spec::{Mismatch, SpecError, TestSpec};
+pub use self::optimize::{optimize, Optimization, OptimizationReport, Refusal};
+pub use self::analysis::{AbstractValue, AnnotatedLine, Analyzer, BranchOutcome, Limitation, StaticAnalysis};
+pub use self::search::{Binding, Cancellation, Outcome, Search, SearchError, SearchMatch, SearchSpace};
+pub use self::diff::{diff_memory, ChangedRange, MemoryDiff};
+pub use self::breakpoint::{Condition, ConditionContext, ConditionError};
+pub use self::compiler::{CompileError, CompileErrorKind, CompiledProgram, Compiler};
+pub use self::debug_info::{DebugInfo, DebugInfoError, LineEntry, SourceLocation, Storage, Variable};
+pub use self::hooks::{ExecutionHook, HookAction, StepState};
+pub use self::protection::{ProtectedRegion, Protection, ProtectionMap, ProtectionParseError};
+pub use self::stats::{InstructionForm, RunStatistics, Statistics};
+pub use self::taint::{Taint, TaintTracker, TaintedOutput};
 
 type Address = usize;
 type MemoryValue = i32;
 
-#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
 pub enum ComputerError {
-    #[fail(display = "unknown opcode")]
-    UnknownOpcode,
+    #[fail(display = "unknown opcode in word {}", _0)]
+    UnknownOpcode(MemoryValue),
     #[fail(display = "instruction decode failed")]
     InstructionDecodeFailed,
-    #[fail(display = "attempted to interact with memory with an invalid address")]
-    MemoryOperationOutOfBounds,
-    #[fail(display = "unknown parameter mode")]
-    UnknownParameterMode,
+    #[fail(display = "attempted to interact with memory with an invalid address ({})", _0)]
+    MemoryOperationOutOfBounds(Address),
+    #[fail(display = "unknown parameter mode {}", _0)]
+    UnknownParameterMode(MemoryValue),
     #[fail(display = "parameter specifying a destination address was flagged as immediate mode")]
     WriteParameterCannotBeImmediateMode,
+    #[fail(display = "parameter refers to negative address {}", _0)]
+    NegativeAddress(i64),
+    #[fail(display = "adjusting the relative base {} by {} overflowed", _0, _1)]
+    RelativeBaseOverflow(MemoryValue, MemoryValue),
     #[fail(display = "IO error while attempting to read from input")]
     FailedToGetInput,
+    #[fail(display = "no input available")]
+    NoInputAvailable,
+    #[fail(display = "memory mapped device failed to complete an operation")]
+    DeviceFault,
+    #[fail(display = "the device mapped at {} cannot be read without side effects", _0)]
+    DeviceCannotBePeeked(Address),
+    #[fail(display = "exceeded the cycle budget")]
+    CycleBudgetExceeded,
+    #[fail(display = "exceeded the time budget")]
+    TimeBudgetExceeded,
+    #[fail(display = "attempted to write beyond the memory budget")]
+    MemoryBudgetExceeded,
+    #[fail(display = "infinite loop detected: {}", _0)]
+    InfiniteLoopDetected(Box<LoopReport>),
+    #[fail(display = "stopped by a hook")]
+    StoppedByHook,
+    #[fail(display = "attempted to write to read-only memory at {}", _0)]
+    WriteToReadOnlyMemory(Address),
+    #[fail(display = "attempted to execute no-execute memory at {}", _0)]
+    ExecuteFromNoExecuteMemory(Address),
+    #[fail(display = "attempted to access guard region at {}", _0)]
+    GuardRegionAccessed(Address),
 }
 
 pub trait Memory {
     fn read_slot(&self, slot: Address) -> Result<MemoryValue, ComputerError>;
     fn write_slot(&mut self, slot: Address, value: MemoryValue) -> Result<(), ComputerError>;
     fn read_stream_from<'a>(&'a self, slot: Address) -> Result<Box<dyn Iterator<Item=MemoryValue> + 'a>, ComputerError>;
+
+    /// Reads a slot without any side effects, for debuggers, fault reports and analysis that look
+    /// at memory without running anything. Only memory whose reads do something, such as a
+    /// [`MappedMemory`](devices::MappedMemory) with devices, needs to override this.
+    fn peek_slot(&self, slot: Address) -> Result<MemoryValue, ComputerError> {
+        self.read_slot(slot)
+    }
+
+    /// Like [`Memory::read_stream_from`], but reading each slot with [`Memory::peek_slot`].
+    fn peek_stream_from<'a>(&'a self, slot: Address) -> Result<Box<dyn Iterator<Item=MemoryValue> + 'a>, ComputerError> {
+        self.read_stream_from(slot)
+    }
+
+    /// Whether accesses to a slot go to a device rather than plain memory, so that reading it can
+    /// give a different value each time and writing it is seen outside the machine.
+    fn is_device(&self, _slot: Address) -> bool {
+        false
+    }
 }
 
 pub struct SimpleMemory {
@@ -42,23 +141,71 @@ impl SimpleMemory {
 
     pub fn from_memory_file<P: AsRef<Path>>(path: P) -> Result<SimpleMemory, Box<dyn Error>> {
         let memory_file_contents = load_file(path)?;
+        Ok(memory_file_contents.parse::<SimpleMemory>().compat()?)
+    }
 
-        Ok(SimpleMemory {
-            memory: memory_file_contents.split(",")
-                .filter_map(|value| value.parse::<MemoryValue>().ok())
-                .collect(),
-        })
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<SimpleMemory, Box<dyn Error>> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        Ok(contents.parse::<SimpleMemory>().compat()?)
+    }
+
+    pub fn from_stdin() -> Result<SimpleMemory, Box<dyn Error>> {
+        SimpleMemory::from_reader(stdin().lock())
+    }
+
+    pub fn from_image(image: &ProgramImage) -> SimpleMemory {
+        SimpleMemory::from_literal(&image.memory)
+    }
+
+    /// Loads either a binary image or a comma separated program, depending on the file contents,
+    /// along with the entry point and protection to run it with. Text programs start at 0 with no
+    /// protection.
+    pub fn load_program<P: AsRef<Path>>(path: P) -> Result<(SimpleMemory, Address, ProtectionMap), Box<dyn Error>> {
+        let bytes = std::fs::read(path)?;
+        if ProgramImage::is_image(&bytes) {
+            let image = ProgramImage::decode(&bytes).compat()?;
+            Ok((SimpleMemory::from_image(&image), image.entry_point, image.protection))
+        } else {
+            Ok((String::from_utf8(bytes)?.parse::<SimpleMemory>().compat()?, 0, ProtectionMap::new()))
+        }
+    }
+
+    pub fn to_image(&self) -> ProgramImage {
+        ProgramImage::new(self.memory.clone())
+    }
+
+    pub fn write_image_file<P: AsRef<Path>>(&self, path: P, checksum: bool) -> Result<(), Box<dyn Error>> {
+        self.to_image().save(path, checksum)
+    }
+
+    pub fn to_program_text(&self) -> String {
+        parse::format_program(&self.memory)
+    }
+
+    pub fn as_slice(&self) -> &[MemoryValue] {
+        &self.memory
     }
 
     pub fn validate_slot(&self, slot: Address) -> Result<(), ComputerError> {
         if slot >= self.memory.len() {
-            Err(ComputerError::MemoryOperationOutOfBounds)
+            Err(ComputerError::MemoryOperationOutOfBounds(slot))
         } else {
             Ok(())
         }
     }
 }
 
+impl FromStr for SimpleMemory {
+    type Err = ProgramParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SimpleMemory {
+            memory: parse::parse_program(s)?,
+        })
+    }
+}
+
 impl Memory for SimpleMemory {
     fn read_slot(&self, slot: Address) -> Result<MemoryValue, ComputerError> {
         self.validate_slot(slot)?;
@@ -68,7 +215,6 @@ impl Memory for SimpleMemory {
     fn write_slot(&mut self, slot: Address, value: MemoryValue) -> Result<(), ComputerError> {
         self.validate_slot(slot)?;
         self.memory[slot] = value;
-        println!("  write [{}] = {}", slot, value);
         Ok(())
     }
 
@@ -78,10 +224,11 @@ impl Memory for SimpleMemory {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum ParameterMode {
     Position,
     Immediate,
+    Relative,
 }
 
 impl ParameterMode {
@@ -89,10 +236,22 @@ impl ParameterMode {
         match raw % 10 {
             0 => Ok(ParameterMode::Position),
             1 => Ok(ParameterMode::Immediate),
-            _ => Err(ComputerError::UnknownParameterMode),
+            2 => Ok(ParameterMode::Relative),
+            mode => Err(ComputerError::UnknownParameterMode(mode)),
         }
     }
 
+    /// Wraps a decoded operand in this mode. Negative positions are refused here rather than
+    /// turning into huge addresses, which no instruction could be encoded back from.
+    pub fn wrap(&self, value: MemoryValue) -> Result<Parameter, ComputerError> {
+        Ok(match self {
+            ParameterMode::Position if value < 0 => return Err(ComputerError::NegativeAddress(value as i64)),
+            ParameterMode::Position => Parameter::Position(value as Address),
+            ParameterMode::Immediate => Parameter::Immediate(value),
+            ParameterMode::Relative => Parameter::Relative(value),
+        })
+    }
+
     pub fn decode_all(mut raw: MemoryValue) -> Result<Vec<ParameterMode>, ComputerError> {
         let mut modes = Vec::new();
         while raw > 0 {
@@ -113,6 +272,7 @@ pub enum Opcode {
     JumpIfFalse,
     LessThan,
     Equal,
+    AdjustRelativeBase,
     Halt,
 }
 
@@ -127,8 +287,27 @@ impl Opcode {
             6 => Ok(Opcode::JumpIfFalse),
             7 => Ok(Opcode::LessThan),
             8 => Ok(Opcode::Equal),
+            9 => Ok(Opcode::AdjustRelativeBase),
             99 => Ok(Opcode::Halt),
-            _ => Err(ComputerError::UnknownOpcode),
+            _ => Err(ComputerError::UnknownOpcode(raw)),
+        }
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equal => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::AdjustRelativeBase => 1,
+            Opcode::Halt => 0,
+        }
+    }
+
+    /// Indices of the parameters that name a destination to write to, and so must be addresses.
+    pub fn write_parameters(&self) -> &'static [usize] {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equal => &[2],
+            Opcode::Input => &[0],
+            Opcode::Output | Opcode::JumpIfTrue | Opcode::JumpIfFalse | Opcode::AdjustRelativeBase | Opcode::Halt => &[],
         }
     }
 }
@@ -148,22 +327,19 @@ impl InstructionHeader {
     }
 
     pub fn get_mode_of_parameter(&self, n: usize) -> ParameterMode {
-        *self.parameter_modes.iter().nth(n).unwrap_or(&ParameterMode::Position)
+        *self.parameter_modes.get(n).unwrap_or(&ParameterMode::Position)
     }
 
-    pub fn wrap_parameter(&self, n: usize, value: MemoryValue) -> Parameter {
-        match self.get_mode_of_parameter(n) {
-            ParameterMode::Position => Parameter::Position(value as Address),
-            ParameterMode::Immediate => Parameter::Immediate(value),
-        }
+    pub fn wrap_parameter(&self, n: usize, value: MemoryValue) -> Result<Parameter, ComputerError> {
+        self.get_mode_of_parameter(n).wrap(value)
     }
 
     pub fn wrap_parameters<I: Iterator<Item=MemoryValue>>(&self, stream: &mut I, n: usize) -> Result<Vec<Parameter>, ComputerError> {
-        Ok(Instruction::next_n_values(stream, n)?
+        Instruction::next_n_values(stream, n)?
             .into_iter()
             .enumerate()
             .map(|(index, value)| self.wrap_parameter(index, value))
-            .collect())
+            .collect()
     }
 }
 
@@ -171,9 +347,11 @@ impl InstructionHeader {
 pub enum Parameter {
     Position(Address),
     Immediate(MemoryValue),
+    /// An offset from the computer's relative base.
+    Relative(MemoryValue),
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Instruction {
     Add(Parameter, Parameter, Parameter),
     Multiply(Parameter, Parameter, Parameter),
@@ -183,84 +361,39 @@ pub enum Instruction {
     JumpIfFalse(Parameter, Parameter),
     LessThan(Parameter, Parameter, Parameter),
     Equal(Parameter, Parameter, Parameter),
+    AdjustRelativeBase(Parameter),
     Halt,
+    /// An opcode added through an [`OpcodeRegistry`].
+    Custom(Arc<OpcodeSpec>, Vec<Parameter>),
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ExecuteResult {
     AdvanceBy(Address),
     JumpTo(Address),
 }
 
 impl Instruction {
+    /// Decodes the built-in instruction set. Use [`OpcodeRegistry::decode`] to include custom
+    /// opcodes.
     pub fn decode<I: Iterator<Item=MemoryValue>>(instruction_stream: &mut I) -> Result<Instruction, ComputerError> {
-        let header = match instruction_stream.next() {
-            Some(raw_opcode) => InstructionHeader::decode(raw_opcode)?,
-            None => return Err(ComputerError::InstructionDecodeFailed),
-        };
-
-        let instruction = match header {
-            InstructionHeader { opcode: Opcode::Add, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 3)?;
-                Instruction::Add(
-                    parameters[0],
-                    parameters[1],
-                    parameters[2]
-                )
-            },
-            InstructionHeader { opcode: Opcode::Multiply, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 3)?;
-                Instruction::Multiply(
-                    parameters[0],
-                    parameters[1],
-                    parameters[2]
-                )
-            },
-            InstructionHeader { opcode: Opcode::Input, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 1)?;
-                Instruction::Input(
-                    parameters[0]
-                )
-            },
-            InstructionHeader { opcode: Opcode::Output, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 1)?;
-                Instruction::Output(
-                    parameters[0]
-                )
-            },
-            InstructionHeader { opcode: Opcode::JumpIfTrue, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 2)?;
-                Instruction::JumpIfTrue(
-                    parameters[0],
-                    parameters[1]
-                )
-            },
-            InstructionHeader { opcode: Opcode::JumpIfFalse, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 2)?;
-                Instruction::JumpIfFalse(
-                    parameters[0],
-                    parameters[1]
-                )
-            },
-            InstructionHeader { opcode: Opcode::LessThan, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 3)?;
-                Instruction::LessThan(
-                    parameters[0],
-                    parameters[1],
-                    parameters[2]
-                )
-            },
-            InstructionHeader { opcode: Opcode::Equal, .. } => {
-                let parameters = header.wrap_parameters(instruction_stream, 3)?;
-                Instruction::Equal(
-                    parameters[0],
-                    parameters[1],
-                    parameters[2]
-                )
-            },
-            InstructionHeader { opcode: Opcode::Halt, .. } => Instruction::Halt,
-        };
+        OpcodeRegistry::builtin().decode(instruction_stream)
+    }
 
-        Ok(instruction)
+    pub fn from_parts(opcode: Opcode, parameters: &[Parameter]) -> Instruction {
+        let p = parameters;
+        match opcode {
+            Opcode::Add => Instruction::Add(p[0], p[1], p[2]),
+            Opcode::Multiply => Instruction::Multiply(p[0], p[1], p[2]),
+            Opcode::Input => Instruction::Input(p[0]),
+            Opcode::Output => Instruction::Output(p[0]),
+            Opcode::JumpIfTrue => Instruction::JumpIfTrue(p[0], p[1]),
+            Opcode::JumpIfFalse => Instruction::JumpIfFalse(p[0], p[1]),
+            Opcode::LessThan => Instruction::LessThan(p[0], p[1], p[2]),
+            Opcode::Equal => Instruction::Equal(p[0], p[1], p[2]),
+            Opcode::AdjustRelativeBase => Instruction::AdjustRelativeBase(p[0]),
+            Opcode::Halt => Instruction::Halt,
+        }
     }
 
     fn next_n_values<I: Iterator<Item=MemoryValue>>(stream: &mut I, n: usize) -> Result<Vec<MemoryValue>, ComputerError> {
@@ -279,144 +412,443 @@ pub enum RecordedIO {
     Output(MemoryValue),
 }
 
+/// How much a [`Computer`] prints about what it is doing as it runs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum TraceLevel {
+    Silent,
+    /// Input prompts and output values only.
+    Io,
+    /// Every instruction executed and every memory write, as well as IO.
+    Instructions,
+}
+
 pub struct Computer<'a, M: Memory> {
     instruction_pointer: Address,
+    relative_base: MemoryValue,
     cycle_count: usize,
     pub halted: bool,
     memory: &'a mut M,
     pub io_record: Vec<RecordedIO>,
+    budget: ExecutionBudget,
+    protection: ProtectionMap,
+    started_at: Option<Instant>,
+    loop_detector: Option<LoopDetector>,
+    recent_instructions: VecDeque<Address>,
+    registry: Arc<OpcodeRegistry>,
+    trace_level: TraceLevel,
+    inputs: VecDeque<MemoryValue>,
+    interactive_input: bool,
+    debug_info: Option<Arc<DebugInfo>>,
+    hook: Option<&'a mut dyn ExecutionHook>,
 }
 
+/// How many previously executed instructions a [`Fault`] shows ahead of the faulting one, and
+/// how many instructions from the fault onwards.
+const FAULT_WINDOW_BEFORE: usize = 3;
+const FAULT_WINDOW_AFTER: usize = 4;
+
 impl<'a, M: Memory> Computer<'a, M> {
     pub fn new(memory: &'a mut M) -> Computer<'a, M> {
         Computer {
             instruction_pointer: 0,
+            relative_base: 0,
             cycle_count: 0,
             halted: false,
             memory,
             io_record: Vec::new(),
+            budget: ExecutionBudget::unlimited(),
+            protection: ProtectionMap::new(),
+            started_at: None,
+            loop_detector: None,
+            recent_instructions: VecDeque::with_capacity(FAULT_WINDOW_BEFORE + 1),
+            registry: OpcodeRegistry::builtin(),
+            trace_level: TraceLevel::Instructions,
+            inputs: VecDeque::new(),
+            interactive_input: true,
+            debug_info: None,
+            hook: None,
         }
     }
 
-    pub fn run_until_halted(&mut self) -> Result<(), ComputerError> {
+    pub fn with_entry_point(mut self, address: Address) -> Computer<'a, M> {
+        self.instruction_pointer = address;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: ExecutionBudget) -> Computer<'a, M> {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_protection(mut self, protection: ProtectionMap) -> Computer<'a, M> {
+        self.protection = protection;
+        self
+    }
+
+    pub fn with_registry(mut self, registry: Arc<OpcodeRegistry>) -> Computer<'a, M> {
+        self.registry = registry;
+        self
+    }
+
+    pub fn with_loop_detection(mut self) -> Computer<'a, M> {
+        self.loop_detector = Some(LoopDetector::new());
+        self
+    }
+
+    /// Names addresses after the source they came from in traces. Faults can be shown with it
+    /// too, using [`Fault::display_with`].
+    pub fn with_debug_info(mut self, debug_info: Arc<DebugInfo>) -> Computer<'a, M> {
+        self.debug_info = Some(debug_info);
+        self
+    }
+
+    /// Calls `hook` as the program runs. Several hooks can be combined into one with a `Vec`.
+    pub fn with_hook(mut self, hook: &'a mut dyn ExecutionHook) -> Computer<'a, M> {
+        self.hook = Some(hook);
+        self
+    }
+
+    pub fn set_hook(&mut self, hook: Option<&'a mut dyn ExecutionHook>) {
+        self.hook = hook;
+    }
+
+    pub fn with_trace_level(mut self, trace_level: TraceLevel) -> Computer<'a, M> {
+        self.trace_level = trace_level;
+        self
+    }
+
+    /// Queues up values for `Input` instructions to consume instead of prompting on stdin. Once
+    /// they run out, `Input` fails with [`ComputerError::NoInputAvailable`] without changing the
+    /// machine's state, so more can be supplied with [`Computer::push_input`] and the step retried.
+    pub fn with_inputs<I: IntoIterator<Item=MemoryValue>>(mut self, inputs: I) -> Computer<'a, M> {
+        self.inputs.extend(inputs);
+        self.interactive_input = false;
+        self
+    }
+
+    pub fn push_input(&mut self, value: MemoryValue) {
+        self.inputs.push_back(value);
+        self.interactive_input = false;
+    }
+
+    /// Whether `Input` prompts on stdin once the queued input runs out.
+    pub fn set_interactive_input(&mut self, interactive: bool) {
+        self.interactive_input = interactive;
+    }
+
+    pub fn instruction_pointer(&self) -> Address {
+        self.instruction_pointer
+    }
+
+    pub fn set_instruction_pointer(&mut self, address: Address) {
+        self.instruction_pointer = address;
+    }
+
+    pub fn relative_base(&self) -> MemoryValue {
+        self.relative_base
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: MemoryValue) {
+        self.relative_base = relative_base;
+    }
+
+    pub fn cycle_count(&self) -> usize {
+        self.cycle_count
+    }
+
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_deref()
+    }
+
+    pub fn registry(&self) -> &OpcodeRegistry {
+        &self.registry
+    }
+
+    pub fn memory(&self) -> &M {
+        self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        self.memory
+    }
+
+    pub fn run_until_halted(&mut self) -> Result<(), Fault> {
         while !self.halted {
             self.step()?;
         }
         Ok(())
     }
 
-    pub fn step(&mut self) -> Result<(), ComputerError> {
-        println!("[{}] executing at slot {}", self.cycle_count, self.instruction_pointer);
+    pub fn step(&mut self) -> Result<(), Fault> {
+        let mut decoded = None;
+        let cycle = self.cycle_count;
+        let io_recorded = self.io_record.len();
+        self.try_step(&mut decoded)
+            .map_err(|error| {
+                if error == ComputerError::StoppedByHook && self.cycle_count == cycle {
+                    self.restore_inputs(io_recorded);
+                }
+                self.fault(error, decoded)
+            })
+    }
+
+    /// Puts back the input consumed by a step that a hook stopped part way through, so the step
+    /// can be retried.
+    fn restore_inputs(&mut self, io_recorded: usize) {
+        while self.io_record.len() > io_recorded {
+            if let Some(RecordedIO::UserInput(value)) = self.io_record.pop() {
+                self.inputs.push_front(value);
+            }
+        }
+    }
+
+    fn step_state(&self) -> StepState {
+        StepState {
+            instruction_pointer: self.instruction_pointer,
+            relative_base: self.relative_base,
+            cycle: self.cycle_count,
+        }
+    }
+
+    /// Tells the hook about an event, if there is one. Without a hook this is a single check.
+    fn notify_hook<F>(&mut self, event: F) -> Result<(), ComputerError>
+        where F: FnOnce(&mut dyn ExecutionHook) -> HookAction
+    {
+        match self.hook.as_mut().map(|hook| event(&mut **hook)) {
+            Some(HookAction::Stop) => Err(ComputerError::StoppedByHook),
+            Some(HookAction::Continue) | None => Ok(()),
+        }
+    }
+
+    fn try_step(&mut self, decoded: &mut Option<Instruction>) -> Result<(), ComputerError> {
+        self.check_budget()?;
+        if let Some(detector) = &mut self.loop_detector {
+            if let Some(report) = detector.on_step(self.instruction_pointer, self.relative_base, self.cycle_count) {
+                return Err(ComputerError::InfiniteLoopDetected(Box::new(report)));
+            }
+        }
+
+        if !self.protection.is_empty() {
+            self.protection.check_execute(self.instruction_pointer)?;
+        }
+
+        self.trace(TraceLevel::Instructions, || match self.debug_info.as_ref().and_then(|info| info.describe(self.instruction_pointer)) {
+            Some(description) => format!("[{}] executing at slot {} ({})", self.cycle_count, self.instruction_pointer, description),
+            None => format!("[{}] executing at slot {}", self.cycle_count, self.instruction_pointer),
+        });
+        let state = self.step_state();
+        if let Some(hook) = &mut self.hook {
+            let memory: &M = self.memory;
+            if hook.before_decode(state, memory) == HookAction::Stop {
+                return Err(ComputerError::StoppedByHook);
+            }
+        }
         let instruction = {
             let mut memory_at_instruction_pointer = self.memory.read_stream_from(self.instruction_pointer)?;
-            Instruction::decode(&mut memory_at_instruction_pointer)?
+            self.registry.decode(&mut memory_at_instruction_pointer)?
         };
-        println!("  decoded: {:?}", instruction);
-        let result = self.execute(instruction)?;
+        self.trace(TraceLevel::Instructions, || match &self.debug_info {
+            Some(info) => format!("  decoded: {}", info.format_instruction(self.instruction_pointer, &instruction)),
+            None => format!("  decoded: {}", instruction),
+        });
+        if let Some(detector) = &mut self.loop_detector {
+            let memory: &M = self.memory;
+            let mut words = self.instruction_pointer..self.instruction_pointer + instruction.length();
+            if words.any(|address| memory.is_device(address)) {
+                detector.on_io();
+            }
+        }
+        *decoded = Some(instruction.clone());
+        self.notify_hook(|hook| hook.after_decode(state, &instruction))?;
+        let executed_at = self.instruction_pointer;
+        let registry = self.registry.clone();
+        let result = registry.execute(self, &instruction)?;
         match result {
             ExecuteResult::AdvanceBy(amount) => {
-                println!("  advancing by {}", amount);
+                self.trace(TraceLevel::Instructions, || format!("  advancing by {}", amount));
                 self.instruction_pointer += amount;
             },
             ExecuteResult::JumpTo(address) => {
-                println!("  jumping to {}", address);
+                self.trace(TraceLevel::Instructions, || format!("  jumping to {}", address));
                 self.instruction_pointer = address;
             },
         }
         self.cycle_count += 1;
+        if self.recent_instructions.len() == FAULT_WINDOW_BEFORE {
+            self.recent_instructions.pop_front();
+        }
+        self.recent_instructions.push_back(executed_at);
+        self.notify_hook(|hook| hook.after_execute(state, &instruction, &result))?;
         Ok(())
     }
 
-    fn execute(&mut self, instruction: Instruction) -> Result<ExecuteResult, ComputerError> {
-        Ok(match instruction {
-            Instruction::Add(a, b, result) => {
-                self.perform_write(result, self.perform_read(a)? + self.perform_read(b)?)?;
-                ExecuteResult::AdvanceBy(4)
-            },
-            Instruction::Multiply(a, b, result) => {
-                self.perform_write(result, self.perform_read(a)? * self.perform_read(b)?)?;
-                ExecuteResult::AdvanceBy(4)
-            },
+    fn fault(&self, error: ComputerError, instruction: Option<Instruction>) -> Fault {
+        let memory: &M = self.memory;
+        let mut window = self.recent_instructions.iter()
+            .filter_map(|address| DisassemblyLine::decode_at_with(&self.registry, memory, *address))
+            .collect::<Vec<_>>();
+        window.extend(disassemble_with(&self.registry, memory, self.instruction_pointer, FAULT_WINDOW_AFTER));
+        Fault {
+            error,
+            instruction_pointer: self.instruction_pointer,
+            cycle: self.cycle_count,
+            word: memory.peek_slot(self.instruction_pointer).ok(),
+            instruction,
+            window,
+        }
+    }
+
+    fn trace<F: FnOnce() -> String>(&self, level: TraceLevel, message: F) {
+        if self.trace_level >= level {
+            println!("{}", message());
+        }
+    }
+
+    fn check_budget(&mut self) -> Result<(), ComputerError> {
+        if let Some(max_cycles) = self.budget.max_cycles {
+            if self.cycle_count >= max_cycles {
+                return Err(ComputerError::CycleBudgetExceeded);
+            }
+        }
+        if let Some(max_duration) = self.budget.max_duration {
+            let started_at = *self.started_at.get_or_insert_with(Instant::now);
+            if started_at.elapsed() > max_duration {
+                return Err(ComputerError::TimeBudgetExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_relative(&self, offset: MemoryValue) -> Result<Address, ComputerError> {
+        let address = self.relative_base as i64 + offset as i64;
+        if address < 0 {
+            Err(ComputerError::NegativeAddress(address))
+        } else {
+            Ok(address as Address)
+        }
+    }
+
+    fn perform_read(&mut self, source: Parameter) -> Result<MemoryValue, ComputerError> {
+        let address = match source {
+            Parameter::Position(address) => address,
+            Parameter::Immediate(value) => return Ok(value),
+            Parameter::Relative(offset) => self.resolve_relative(offset)?,
+        };
+        if !self.protection.is_empty() {
+            self.protection.check_read(address)?;
+        }
+        let value = self.memory.read_slot(address)?;
+        self.notify_hook(|hook| hook.on_read(address, value))?;
+        if let Some(detector) = &mut self.loop_detector {
+            if self.memory.is_device(address) {
+                detector.on_io();
+            }
+        }
+        Ok(value)
+    }
+
+    fn perform_write(&mut self, destination: Parameter, value: MemoryValue) -> Result<(), ComputerError> {
+        let address = match destination {
+            Parameter::Position(address) => address,
+            Parameter::Relative(offset) => self.resolve_relative(offset)?,
+            Parameter::Immediate(_) => return Err(ComputerError::WriteParameterCannotBeImmediateMode),
+        };
+        if !self.protection.is_empty() {
+            self.protection.check_write(address)?;
+        }
+        if let Some(limit) = self.budget.memory_limit {
+            if address >= limit {
+                return Err(ComputerError::MemoryBudgetExceeded);
+            }
+        }
+        self.notify_hook(|hook| hook.on_write(address, value))?;
+        if let Some(detector) = &mut self.loop_detector {
+            if self.memory.is_device(address) {
+                detector.on_io();
+            } else {
+                let old_value = self.memory.peek_slot(address)?;
+                detector.on_write(address, old_value, value);
+            }
+        }
+        self.memory.write_slot(address, value)?;
+        self.trace(TraceLevel::Instructions, || format!("  write [{}] = {}", address, value));
+        Ok(())
+    }
+}
+
+impl<'a, M: Memory> Machine for Computer<'a, M> {
+    fn read(&mut self, parameter: Parameter) -> Result<MemoryValue, ComputerError> {
+        self.perform_read(parameter)
+    }
 
-            Instruction::Input(destination) => {
+    fn write(&mut self, parameter: Parameter, value: MemoryValue) -> Result<(), ComputerError> {
+        self.perform_write(parameter, value)
+    }
+
+    fn input(&mut self) -> Result<MemoryValue, ComputerError> {
+        let value = match self.inputs.pop_front() {
+            Some(value) => value,
+            None if self.interactive_input => {
                 let mut user_input = String::new();
-                print!("  INPUT> ");
-                stdout().flush().map_err(|_| ComputerError::FailedToGetInput)?;
+                if self.trace_level >= TraceLevel::Io {
+                    print!("  INPUT> ");
+                    stdout().flush().map_err(|_| ComputerError::FailedToGetInput)?;
+                }
                 stdin().read_line(&mut user_input)
                     .map_err(|_| ComputerError::FailedToGetInput)?;
                 user_input = user_input.trim().into();
-                let value = user_input.parse::<MemoryValue>()
-                    .map_err(|_| ComputerError::FailedToGetInput)?;
-                self.io_record.push(RecordedIO::UserInput(value));
-                self.perform_write(destination, value)?;
-                ExecuteResult::AdvanceBy(2)
-            },
-            Instruction::Output(source) => {
-                let value = self.perform_read(source)?;
-                self.io_record.push(RecordedIO::Output(value));
-                println!("  OUTPUT VALUE: {}", value);
-                ExecuteResult::AdvanceBy(2)
+                user_input.parse::<MemoryValue>()
+                    .map_err(|_| ComputerError::FailedToGetInput)?
             },
+            None => return Err(ComputerError::NoInputAvailable),
+        };
+        if let Err(error) = self.notify_hook(|hook| hook.on_io(RecordedIO::UserInput(value))) {
+            self.inputs.push_front(value);
+            return Err(error);
+        }
+        self.io_record.push(RecordedIO::UserInput(value));
+        if let Some(detector) = &mut self.loop_detector {
+            detector.on_io();
+        }
+        Ok(value)
+    }
 
-            Instruction::JumpIfTrue(condition, destination) => {
-                if self.perform_read(condition)? != 0 {
-                    ExecuteResult::JumpTo(self.perform_read(destination)? as Address)
-                } else {
-                    ExecuteResult::AdvanceBy(3)
-                }
-            },
-            Instruction::JumpIfFalse(condition, destination) => {
-                if self.perform_read(condition)? == 0 {
-                    ExecuteResult::JumpTo(self.perform_read(destination)? as Address)
-                } else {
-                    ExecuteResult::AdvanceBy(3)
-                }
-            },
+    fn output(&mut self, value: MemoryValue) -> Result<(), ComputerError> {
+        self.notify_hook(|hook| hook.on_io(RecordedIO::Output(value)))?;
+        self.io_record.push(RecordedIO::Output(value));
+        if let Some(detector) = &mut self.loop_detector {
+            detector.on_io();
+        }
+        self.trace(TraceLevel::Io, || format!("  OUTPUT VALUE: {}", value));
+        Ok(())
+    }
 
-            Instruction::LessThan(a, b, result) => {
-                let outcome = if self.perform_read(a)? < self.perform_read(b)? {
-                    1
-                } else {
-                    0
-                };
-                self.perform_write(result, outcome)?;
-                ExecuteResult::AdvanceBy(4)
-            },
-            Instruction::Equal(a, b, result) => {
-                let outcome = if self.perform_read(a)? == self.perform_read(b)? {
-                    1
-                } else {
-                    0
-                };
-                self.perform_write(result, outcome)?;
-                ExecuteResult::AdvanceBy(4)
-            },
+    fn halt(&mut self) {
+        self.halted = true;
+    }
 
-            Instruction::Halt => {
-                self.halted = true;
-                ExecuteResult::AdvanceBy(1)
-            },
-        })
+    fn relative_base(&self) -> MemoryValue {
+        self.relative_base
     }
 
-    fn perform_read(&self, source: Parameter) -> Result<MemoryValue, ComputerError> {
-        match source {
-            Parameter::Position(address) => self.memory.read_slot(address),
-            Parameter::Immediate(value) => Ok(value),
-        }
+    fn adjust_relative_base(&mut self, delta: MemoryValue) -> Result<(), ComputerError> {
+        self.relative_base = self.relative_base.checked_add(delta)
+            .ok_or(ComputerError::RelativeBaseOverflow(self.relative_base, delta))?;
+        Ok(())
     }
 
-    fn perform_write(&mut self, destination: Parameter, value: MemoryValue) -> Result<(), ComputerError> {
-        match destination {
-            Parameter::Position(address) => self.memory.write_slot(address, value),
-            Parameter::Immediate(_) => Err(ComputerError::WriteParameterCannotBeImmediateMode),
-        }
+    fn instruction_pointer(&self) -> Address {
+        self.instruction_pointer
+    }
+
+    fn cycle_count(&self) -> usize {
+        self.cycle_count
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::intcode::{Computer, SimpleMemory, Memory, Instruction, ComputerError, Parameter};
+    use crate::intcode::{Computer, SimpleMemory, Memory, Instruction, ComputerError, Parameter, ProgramImage, ProtectionMap, RecordedIO, TraceLevel};
 
     #[test]
     fn can_read_memory() {
@@ -424,7 +856,56 @@ mod tests {
         assert_eq!(memory.read_slot(0), Ok(1));
         assert_eq!(memory.read_slot(1), Ok(2));
         assert_eq!(memory.read_slot(2), Ok(3));
-        assert_eq!(memory.read_slot(3), Err(ComputerError::MemoryOperationOutOfBounds));
+        assert_eq!(memory.read_slot(3), Err(ComputerError::MemoryOperationOutOfBounds(3)));
+    }
+
+    #[test]
+    fn can_load_memory_from_text() {
+        let memory = "1,2,\n3 # comment\n".parse::<SimpleMemory>().unwrap();
+        assert_eq!(memory.read_stream_from(0).unwrap().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let memory = SimpleMemory::from_reader("4,5,6".as_bytes()).unwrap();
+        assert_eq!(memory.read_stream_from(0).unwrap().collect::<Vec<_>>(), vec![4, 5, 6]);
+
+        assert!("1,2,three".parse::<SimpleMemory>().is_err());
+        assert!(SimpleMemory::from_reader("1,,2".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn can_convert_memory_to_and_from_images() {
+        let memory = SimpleMemory::from_literal(&[1101, -5, 7, 0, 99]);
+        let image = ProgramImage::decode(&memory.to_image().encode(true)).unwrap();
+        let loaded = SimpleMemory::from_image(&image);
+        assert_eq!(loaded.as_slice(), &[1101, -5, 7, 0, 99]);
+        assert_eq!(loaded.to_program_text(), "1101,-5,7,0,99");
+    }
+
+    #[test]
+    fn loads_programs_with_their_entry_point_and_protection() {
+        let directory = std::env::temp_dir().join(format!("intcode-load-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let protection = "0..2:ro".parse::<ProtectionMap>().unwrap();
+        let mut image = ProgramImage::new(vec![0, 0, 1101, 2, 3, 0, 99]);
+        image.entry_point = 2;
+        image.protection = protection.clone();
+        image.save(directory.join("program.img"), true).unwrap();
+        std::fs::write(directory.join("program.txt"), "1101,2,3,0,99").unwrap();
+
+        let (memory, entry_point, loaded_protection) = SimpleMemory::load_program(directory.join("program.img")).unwrap();
+        assert_eq!(memory.as_slice(), image.memory.as_slice());
+        assert_eq!((entry_point, loaded_protection), (2, protection));
+        let (memory, entry_point, loaded_protection) = SimpleMemory::load_program(directory.join("program.txt")).unwrap();
+        assert_eq!(memory.as_slice(), &[1101, 2, 3, 0, 99]);
+        assert_eq!((entry_point, loaded_protection), (0, ProtectionMap::new()));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn can_start_at_entry_point() {
+        let mut memory = SimpleMemory::from_literal(&[0, 0, 1101, 2, 3, 0, 99]);
+        let mut computer = Computer::new(&mut memory).with_entry_point(2);
+        computer.run_until_halted().unwrap();
+        assert_eq!(memory.read_slot(0), Ok(5));
     }
 
     #[test]
@@ -441,7 +922,7 @@ mod tests {
         let mut memory = SimpleMemory::from_literal(&[4, 6, 8]);
         memory.write_slot(1, 12).unwrap();
         assert_eq!(memory.read_slot(1), Ok(12));
-        assert_eq!(memory.write_slot(3, 10), Err(ComputerError::MemoryOperationOutOfBounds));
+        assert_eq!(memory.write_slot(3, 10), Err(ComputerError::MemoryOperationOutOfBounds(3)));
     }
 
     #[test]
@@ -659,7 +1140,7 @@ mod tests {
     fn can_execute_halt() {
         let mut memory = SimpleMemory::from_literal(&[99]);
         let mut computer = Computer::new(&mut memory);
-        assert_eq!(computer.halted, false);
+        assert!(!computer.halted);
         computer.step().expect("failed to step computer");
         assert_eq!(computer.instruction_pointer, 1);
         assert!(computer.halted);
@@ -685,6 +1166,45 @@ mod tests {
         assert!(computer.halted);
     }
 
+    #[test]
+    fn can_use_relative_parameters() {
+        let mut memory = SimpleMemory::from_literal(&[109, 6, 204, -1, 99, 42]);
+        let mut computer = Computer::new(&mut memory);
+        computer.run_until_halted().expect("failed to run computer");
+        assert_eq!(computer.relative_base(), 6);
+        assert_eq!(computer.io_record, vec![RecordedIO::Output(42)]);
+
+        let mut memory = SimpleMemory::from_literal(&[109, 3, 21101, 2, 3, 4, 99, 0]);
+        Computer::new(&mut memory).run_until_halted().expect("failed to run computer");
+        assert_eq!(memory.read_slot(7), Ok(5));
+
+        let mut memory = SimpleMemory::from_literal(&[204, -1, 99]);
+        let fault = Computer::new(&mut memory).run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::NegativeAddress(-1));
+
+        let mut memory = SimpleMemory::from_literal(&[109, 2147483647, 109, 1, 99]);
+        let mut computer = Computer::new(&mut memory);
+        let fault = computer.run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::RelativeBaseOverflow(2147483647, 1));
+        assert_eq!(computer.relative_base(), 2147483647);
+    }
+
+    #[test]
+    fn can_take_queued_input() {
+        let mut memory = SimpleMemory::from_literal(&[3, 9, 3, 10, 1, 9, 10, 11, 99, 0, 0, 0]);
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![40]);
+        let fault = computer.run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::NoInputAvailable);
+        assert_eq!(computer.instruction_pointer(), 2);
+
+        computer.push_input(2);
+        computer.run_until_halted().expect("failed to run computer");
+        assert_eq!(computer.io_record, vec![RecordedIO::UserInput(40), RecordedIO::UserInput(2)]);
+        assert_eq!(memory.read_slot(11), Ok(42));
+    }
+
     #[test]
     fn verify_example_programs() {
         fn run_until_halted(computer: &Computer<SimpleMemory>) -> bool {
@@ -0,0 +1,544 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::ops::Range;
+use super::{Address, ComputerError, Instruction, Memory, MemoryValue, Parameter, SimpleMemory};
+use super::disassemble::DisassemblyLine;
+
+/// What the analyser knows about a value: either exactly what it is on every path, or nothing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AbstractValue {
+    Known(MemoryValue),
+    Unknown,
+}
+
+impl AbstractValue {
+    pub fn known(&self) -> Option<MemoryValue> {
+        match self {
+            AbstractValue::Known(value) => Some(*value),
+            AbstractValue::Unknown => None,
+        }
+    }
+
+    fn join(self, other: AbstractValue) -> AbstractValue {
+        if self == other { self } else { AbstractValue::Unknown }
+    }
+
+    fn combine<F: Fn(MemoryValue, MemoryValue) -> MemoryValue>(self, other: AbstractValue, f: F) -> AbstractValue {
+        match (self, other) {
+            (AbstractValue::Known(a), AbstractValue::Known(b)) => AbstractValue::Known(f(a, b)),
+            _ => AbstractValue::Unknown,
+        }
+    }
+}
+
+/// Which ways a reachable conditional jump can go.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BranchOutcome {
+    AlwaysTaken,
+    NeverTaken,
+    Either,
+}
+
+/// Places where the analyser couldn't follow the program. Past these, reachability is a guess:
+/// code only reached through them shows up as unreachable, and writes made there are missed.
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Limitation {
+    /// The instruction's words depend on input or on code that was overwritten.
+    UnknownInstruction(Address),
+    /// The jump can be taken but where it goes depends on input.
+    UnknownJumpTarget(Address),
+}
+
+impl fmt::Display for Limitation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limitation::UnknownInstruction(address) => write!(f, "the instruction at {} isn't known until runtime", address),
+            Limitation::UnknownJumpTarget(address) => write!(f, "the jump at {} has a target that isn't known until runtime", address),
+        }
+    }
+}
+
+/// The abstract machine state at one program point.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct State {
+    memory: Vec<AbstractValue>,
+    relative_base: AbstractValue,
+    /// How many inputs every path here has read, if they all agree.
+    inputs_read: Option<usize>,
+}
+
+impl State {
+    /// Merges `other` into this state, returning whether anything changed.
+    fn join(&mut self, other: &State) -> bool {
+        let before = (self.relative_base, self.inputs_read);
+        let mut changed = false;
+        for (cell, other) in self.memory.iter_mut().zip(other.memory.iter()) {
+            let joined = cell.join(*other);
+            changed |= joined != *cell;
+            *cell = joined;
+        }
+        self.relative_base = self.relative_base.join(other.relative_base);
+        if self.inputs_read != other.inputs_read {
+            self.inputs_read = None;
+        }
+        changed || before != (self.relative_base, self.inputs_read)
+    }
+
+    fn address_of(&self, parameter: Parameter) -> Option<Result<Address, ()>> {
+        let address = match parameter {
+            Parameter::Position(address) => address as i64,
+            Parameter::Relative(offset) => self.relative_base.known()? as i64 + offset as i64,
+            Parameter::Immediate(_) => return Some(Err(())),
+        };
+        if address < 0 || address as usize >= self.memory.len() {
+            Some(Err(()))
+        } else {
+            Some(Ok(address as Address))
+        }
+    }
+}
+
+/// A path through the program stopped: it halted, faulted or ran into a [`Limitation`].
+struct Stop;
+
+/// Runs a constant-propagation analysis over an intcode program: every path from the entry point
+/// is followed with each memory cell, the relative base and the number of inputs read tracked as
+/// either a known value or unknown, merging states wherever paths meet.
+///
+/// Memory is treated as fixed in size, like [`SimpleMemory`], and custom opcodes are treated as
+/// faults.
+pub struct Analyzer {
+    memory: Vec<MemoryValue>,
+    entry_point: Address,
+    inputs: Option<Vec<MemoryValue>>,
+}
+
+impl Analyzer {
+    pub fn new<M: Memory + ?Sized>(memory: &M) -> Analyzer {
+        Analyzer {
+            memory: memory.peek_stream_from(0).map(|stream| stream.collect()).unwrap_or_default(),
+            entry_point: 0,
+            inputs: None,
+        }
+    }
+
+    pub fn with_entry_point(mut self, address: Address) -> Analyzer {
+        self.entry_point = address;
+        self
+    }
+
+    /// Assumes the program will be given these inputs, in order, so that code depending on them
+    /// can be followed. Inputs past the end are unknown.
+    pub fn with_inputs<I: IntoIterator<Item=MemoryValue>>(mut self, inputs: I) -> Analyzer {
+        self.inputs = Some(inputs.into_iter().collect());
+        self
+    }
+
+    pub fn run(&self) -> StaticAnalysis {
+        let mut analysis = StaticAnalysis {
+            initial: SimpleMemory::from_literal(&self.memory),
+            instructions: BTreeMap::new(),
+            written: vec![false; self.memory.len()],
+            taken: BTreeMap::new(),
+            limitations: BTreeSet::new(),
+        };
+
+        let mut states = HashMap::new();
+        states.insert(self.entry_point, State {
+            memory: self.memory.iter().map(|value| AbstractValue::Known(*value)).collect(),
+            relative_base: AbstractValue::Known(0),
+            inputs_read: Some(0),
+        });
+        let mut pending = VecDeque::from(vec![self.entry_point]);
+        let mut queued = BTreeSet::from([self.entry_point]);
+
+        while let Some(address) = pending.pop_front() {
+            queued.remove(&address);
+            let state = states[&address].clone();
+            for (successor, state) in self.step(&mut analysis, address, state) {
+                let changed = match states.get_mut(&successor) {
+                    Some(existing) => existing.join(&state),
+                    None => {
+                        states.insert(successor, state);
+                        true
+                    },
+                };
+                if changed && queued.insert(successor) {
+                    pending.push_back(successor);
+                }
+            }
+        }
+        analysis
+    }
+
+    fn decode(&self, analysis: &mut StaticAnalysis, address: Address, state: &State) -> Result<Instruction, Stop> {
+        let known = state.memory.get(address..).ok_or(Stop)?
+            .iter()
+            .map_while(AbstractValue::known)
+            .collect::<Vec<_>>();
+        match Instruction::decode(&mut known.iter().cloned()) {
+            Ok(instruction) => Ok(instruction),
+            Err(ComputerError::InstructionDecodeFailed) if address + known.len() < state.memory.len() => {
+                analysis.limitations.insert(Limitation::UnknownInstruction(address));
+                Err(Stop)
+            },
+            Err(_) => Err(Stop),
+        }
+    }
+
+    fn read(state: &State, parameter: Parameter) -> Result<AbstractValue, Stop> {
+        match (parameter, state.address_of(parameter)) {
+            (Parameter::Immediate(value), _) => Ok(AbstractValue::Known(value)),
+            (_, Some(Ok(address))) => Ok(state.memory[address]),
+            (_, Some(Err(()))) => Err(Stop),
+            (_, None) => Ok(AbstractValue::Unknown),
+        }
+    }
+
+    fn write(analysis: &mut StaticAnalysis, state: &mut State, parameter: Parameter, value: AbstractValue) -> Result<(), Stop> {
+        match state.address_of(parameter) {
+            Some(Ok(address)) => {
+                state.memory[address] = value;
+                analysis.written[address] = true;
+            },
+            Some(Err(())) => return Err(Stop),
+            // the relative base isn't known, so this could be any cell
+            None => {
+                for (cell, written) in state.memory.iter_mut().zip(analysis.written.iter_mut()) {
+                    *cell = cell.join(value);
+                    *written = true;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Executes the instruction at `address` abstractly, returning each state it can lead to.
+    fn step(&self, analysis: &mut StaticAnalysis, address: Address, mut state: State) -> Vec<(Address, State)> {
+        let result = (|| {
+            let instruction = self.decode(analysis, address, &state)?;
+            let next = address + instruction.length();
+            analysis.instructions.entry(address).or_insert_with(|| instruction.clone());
+
+            match instruction {
+                Instruction::Add(a, b, destination) |
+                Instruction::Multiply(a, b, destination) |
+                Instruction::LessThan(a, b, destination) |
+                Instruction::Equal(a, b, destination) => {
+                    let (a_value, b_value) = (Analyzer::read(&state, a)?, Analyzer::read(&state, b)?);
+                    let value = match instruction {
+                        Instruction::Add(..) => a_value.combine(b_value, MemoryValue::wrapping_add),
+                        Instruction::Multiply(..) if a_value == AbstractValue::Known(0) || b_value == AbstractValue::Known(0) => AbstractValue::Known(0),
+                        Instruction::Multiply(..) => a_value.combine(b_value, MemoryValue::wrapping_mul),
+                        Instruction::LessThan(..) => a_value.combine(b_value, |a, b| (a < b) as MemoryValue),
+                        _ => a_value.combine(b_value, |a, b| (a == b) as MemoryValue),
+                    };
+                    Analyzer::write(analysis, &mut state, destination, value)?;
+                    Ok(vec![(next, state)])
+                },
+                Instruction::Input(destination) => {
+                    let value = match (&self.inputs, state.inputs_read) {
+                        (Some(inputs), Some(read)) => inputs.get(read).map_or(AbstractValue::Unknown, |value| AbstractValue::Known(*value)),
+                        _ => AbstractValue::Unknown,
+                    };
+                    state.inputs_read = state.inputs_read.map(|read| read + 1);
+                    Analyzer::write(analysis, &mut state, destination, value)?;
+                    Ok(vec![(next, state)])
+                },
+                Instruction::Output(value) => {
+                    Analyzer::read(&state, value)?;
+                    Ok(vec![(next, state)])
+                },
+                Instruction::JumpIfTrue(condition, target) | Instruction::JumpIfFalse(condition, target) => {
+                    let jump_if = matches!(instruction, Instruction::JumpIfTrue(..));
+                    let (can_take, can_skip) = match Analyzer::read(&state, condition)? {
+                        AbstractValue::Known(value) => ((value != 0) == jump_if, (value != 0) != jump_if),
+                        AbstractValue::Unknown => (true, true),
+                    };
+                    let outcomes = analysis.taken.entry(address).or_insert((false, false));
+                    outcomes.0 |= can_take;
+                    outcomes.1 |= can_skip;
+
+                    let mut successors = Vec::new();
+                    if can_take {
+                        match Analyzer::read(&state, target)? {
+                            AbstractValue::Known(target) if target >= 0 => successors.push((target as Address, state.clone())),
+                            AbstractValue::Known(_) => {},
+                            AbstractValue::Unknown => {
+                                analysis.limitations.insert(Limitation::UnknownJumpTarget(address));
+                            },
+                        }
+                    }
+                    if can_skip {
+                        successors.push((next, state));
+                    }
+                    Ok(successors)
+                },
+                Instruction::AdjustRelativeBase(delta) => {
+                    state.relative_base = state.relative_base.combine(Analyzer::read(&state, delta)?, MemoryValue::wrapping_add);
+                    Ok(vec![(next, state)])
+                },
+                Instruction::Halt | Instruction::Custom(..) => Err(Stop),
+            }
+        })();
+        result.unwrap_or_default()
+    }
+}
+
+/// The results of an [`Analyzer`] run. Everything here covers all executions when
+/// [`is_complete`](StaticAnalysis::is_complete), and otherwise only the paths that could be
+/// followed.
+pub struct StaticAnalysis {
+    initial: SimpleMemory,
+    instructions: BTreeMap<Address, Instruction>,
+    written: Vec<bool>,
+    /// Whether each reachable conditional jump can be taken, and whether it can fall through.
+    taken: BTreeMap<Address, (bool, bool)>,
+    limitations: BTreeSet<Limitation>,
+}
+
+/// Groups addresses into contiguous ranges.
+fn ranges<I: Iterator<Item=Address>>(addresses: I) -> Vec<Range<Address>> {
+    let mut ranges: Vec<Range<Address>> = Vec::new();
+    for address in addresses {
+        match ranges.last_mut() {
+            Some(range) if range.end == address => range.end += 1,
+            _ => ranges.push(address..address + 1),
+        }
+    }
+    ranges
+}
+
+impl StaticAnalysis {
+    pub fn is_complete(&self) -> bool {
+        self.limitations.is_empty()
+    }
+
+    pub fn limitations(&self) -> Vec<Limitation> {
+        self.limitations.iter().cloned().collect()
+    }
+
+    /// The instructions that can be reached, as first decoded at each address.
+    pub fn reachable_instructions(&self) -> &BTreeMap<Address, Instruction> {
+        &self.instructions
+    }
+
+    pub fn is_reachable(&self, address: Address) -> bool {
+        self.instructions.contains_key(&address)
+    }
+
+    /// Whether the cell keeps its initial value for the whole run.
+    pub fn is_constant(&self, address: Address) -> bool {
+        !self.written.get(address).cloned().unwrap_or(true)
+    }
+
+    pub fn constant_cells(&self) -> Vec<Address> {
+        (0..self.written.len()).filter(|address| self.is_constant(*address)).collect()
+    }
+
+    /// Cells of reachable instructions, including the first word of any that couldn't be decoded.
+    fn executed_cells(&self) -> BTreeSet<Address> {
+        let undecoded = self.limitations.iter().filter_map(|limitation| match limitation {
+            Limitation::UnknownInstruction(address) => Some(*address),
+            Limitation::UnknownJumpTarget(_) => None,
+        });
+        self.instructions.iter()
+            .flat_map(|(address, instruction)| *address..*address + instruction.length())
+            .chain(undecoded)
+            .collect()
+    }
+
+    /// Ranges of memory that no reachable instruction occupies: dead code, or data.
+    pub fn unreachable_ranges(&self) -> Vec<Range<Address>> {
+        let executed = self.executed_cells();
+        ranges((0..self.written.len()).filter(|address| !executed.contains(address)))
+    }
+
+    /// Ranges of reachable code that may be overwritten while the program runs.
+    pub fn overwritten_code(&self) -> Vec<Range<Address>> {
+        ranges(self.executed_cells().into_iter().filter(|address| self.written[*address]))
+    }
+
+    pub fn branch(&self, address: Address) -> Option<BranchOutcome> {
+        self.taken.get(&address).map(|outcome| match outcome {
+            (true, false) => BranchOutcome::AlwaysTaken,
+            (false, true) => BranchOutcome::NeverTaken,
+            _ => BranchOutcome::Either,
+        })
+    }
+
+    /// Reachable conditional jumps that always go the same way.
+    pub fn constant_branches(&self) -> Vec<(Address, BranchOutcome)> {
+        self.taken.keys()
+            .filter_map(|address| self.branch(*address).map(|outcome| (*address, outcome)))
+            .filter(|(_, outcome)| *outcome != BranchOutcome::Either)
+            .collect()
+    }
+
+    fn annotations(&self, line: &DisassemblyLine) -> Vec<Annotation> {
+        let cells = line.address..line.address + line.length();
+        let mut annotations = Vec::new();
+        if !self.is_reachable(line.address) {
+            annotations.push(Annotation::NeverExecuted);
+        }
+        match self.branch(line.address) {
+            Some(BranchOutcome::AlwaysTaken) => annotations.push(Annotation::AlwaysTaken),
+            Some(BranchOutcome::NeverTaken) => annotations.push(Annotation::NeverTaken),
+            _ => {},
+        }
+        if cells.clone().any(|address| self.written[address]) {
+            annotations.push(if self.is_reachable(line.address) { Annotation::Overwritten } else { Annotation::Written });
+        }
+        annotations
+    }
+
+    /// Disassembles the whole of memory, following reachable instructions where there are some
+    /// and showing everything else linearly, with notes on what the analysis found. Reachable
+    /// instructions are shown as the analysis decoded them, which for overwritten code may not
+    /// match the initial words.
+    pub fn disassemble(&self) -> Vec<AnnotatedLine> {
+        let starts = self.instructions.keys().cloned().collect::<BTreeSet<_>>();
+        let mut lines = Vec::new();
+        let mut address = 0;
+        while let Some(mut line) = DisassemblyLine::decode_at(&self.initial, address) {
+            // overwritten code is shown as it was when it ran
+            if let Some(instruction) = self.instructions.get(&address) {
+                line.words = self.initial.as_slice()[address..address + instruction.length()].to_vec();
+                line.instruction = Ok(instruction.clone());
+            }
+            // don't let a linear decode swallow the start of a reachable instruction
+            let next_start = starts.range(address + 1..).next().cloned();
+            if next_start.is_some_and(|start| start < address + line.length()) {
+                line.words.truncate(1);
+                line.instruction = Err(ComputerError::InstructionDecodeFailed);
+            }
+            address += line.length();
+            let annotations = self.annotations(&line);
+            lines.push(AnnotatedLine { line, annotations });
+        }
+        lines
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Annotation {
+    NeverExecuted,
+    AlwaysTaken,
+    NeverTaken,
+    /// Reachable code that may be overwritten at runtime.
+    Overwritten,
+    /// Memory outside reachable code that is written at runtime.
+    Written,
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Annotation::NeverExecuted => "never executed",
+            Annotation::AlwaysTaken => "always taken",
+            Annotation::NeverTaken => "never taken",
+            Annotation::Overwritten => "overwritten at runtime",
+            Annotation::Written => "written at runtime",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AnnotatedLine {
+    pub line: DisassemblyLine,
+    pub annotations: Vec<Annotation>,
+}
+
+impl fmt::Display for AnnotatedLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.annotations.is_empty() {
+            return write!(f, "{}", self.line);
+        }
+        let notes = self.annotations.iter()
+            .map(|annotation| annotation.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{:<56} ; {}", self.line.to_string(), notes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::SimpleMemory;
+    use crate::intcode::analysis::{Analyzer, Annotation, BranchOutcome, Limitation};
+
+    #[test]
+    fn finds_constants_and_unreachable_code() {
+        // add 2, 3, [11]; out [11]; hlt; then data
+        let memory = SimpleMemory::from_literal(&[1101, 2, 3, 11, 4, 11, 99, 5, 6, 7, 0, 0]);
+        let analysis = Analyzer::new(&memory).run();
+        assert!(analysis.is_complete());
+        assert_eq!(analysis.reachable_instructions().keys().cloned().collect::<Vec<_>>(), vec![0, 4, 6]);
+        assert_eq!(analysis.unreachable_ranges(), vec![7..12]);
+        assert_eq!(analysis.constant_cells(), (0..11).collect::<Vec<_>>());
+        assert!(!analysis.is_constant(11));
+        assert!(analysis.overwritten_code().is_empty());
+    }
+
+    #[test]
+    fn decides_constant_branches() {
+        // lt 3, 5, [17]; jt [17], 11; out 0; hlt; (11) in [17]; jf [17], 7; hlt
+        let memory = SimpleMemory::from_literal(&[1107, 3, 5, 17, 1005, 17, 11, 104, 0, 99, 0, 3, 17, 1006, 17, 7, 99, 0]);
+        let analysis = Analyzer::new(&memory).run();
+        assert_eq!(analysis.branch(4), Some(BranchOutcome::AlwaysTaken));
+        assert_eq!(analysis.branch(13), Some(BranchOutcome::Either));
+        assert_eq!(analysis.unreachable_ranges(), vec![10..11, 17..18]);
+
+        let analysis = Analyzer::new(&memory).with_inputs(vec![1]).run();
+        assert_eq!(analysis.constant_branches(), vec![(4, BranchOutcome::AlwaysTaken), (13, BranchOutcome::NeverTaken)]);
+        assert_eq!(analysis.unreachable_ranges(), vec![7..11, 17..18]);
+
+        let analysis = Analyzer::new(&memory).with_inputs(vec![0]).run();
+        assert_eq!(analysis.branch(13), Some(BranchOutcome::AlwaysTaken));
+        assert_eq!(analysis.unreachable_ranges(), vec![10..11, 16..18]);
+    }
+
+    #[test]
+    fn follows_the_relative_base() {
+        // arb 9; add 1, 2, [rb+0]; out [rb+0]; hlt
+        let memory = SimpleMemory::from_literal(&[109, 9, 21101, 1, 2, 0, 204, 0, 99, 0]);
+        let analysis = Analyzer::new(&memory).run();
+        assert!(analysis.is_complete());
+        assert!(!analysis.is_constant(9));
+        assert_eq!(analysis.constant_cells(), (0..9).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn flags_self_modifying_code() {
+        let memory = SimpleMemory::from_memory_file("input/day5.txt").unwrap();
+        let analysis = Analyzer::new(&memory).run();
+        assert_eq!(analysis.limitations(), vec![Limitation::UnknownInstruction(6)]);
+        assert_eq!(analysis.overwritten_code(), vec![6..7]);
+
+        // knowing the input lets the analysis decode the rewritten instruction and carry on
+        let analysis = Analyzer::new(&memory).with_inputs(vec![1]).run();
+        assert!(analysis.is_complete());
+        assert!(analysis.overwritten_code().contains(&(6..7)));
+        assert!(analysis.reachable_instructions().len() > 50);
+    }
+
+    #[test]
+    fn annotates_disassembly() {
+        // jt 1, 5; out 0; in [8]; hlt; then 8 as data
+        let memory = SimpleMemory::from_literal(&[1105, 1, 5, 104, 0, 3, 8, 99, 0]);
+        let analysis = Analyzer::new(&memory).run();
+        let lines = analysis.disassemble();
+        let notes = lines.iter()
+            .map(|line| (line.line.address, line.annotations.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(notes, vec![
+            (0, vec![Annotation::AlwaysTaken]),
+            (3, vec![Annotation::NeverExecuted]),
+            (5, vec![]),
+            (7, vec![]),
+            (8, vec![Annotation::NeverExecuted, Annotation::Written]),
+        ]);
+        assert!(lines[0].to_string().contains("jt 1, 5"));
+        assert!(lines[0].to_string().ends_with(" ; always taken"));
+    }
+}
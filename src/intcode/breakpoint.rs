@@ -0,0 +1,433 @@
+use failure::Fail;
+use std::fmt;
+use std::str::FromStr;
+use super::{Address, Computer, Fault, Instruction, Memory, MemoryValue, Opcode, Parameter};
+
+/// The machine state a [`Condition`] is evaluated against, taken just before a step.
+pub struct ConditionContext<'m> {
+    pub instruction_pointer: Address,
+    pub relative_base: MemoryValue,
+    pub cycle: usize,
+    pub memory: &'m dyn Memory,
+    /// The instruction about to run, if it decodes. Only filled in for conditions that need it.
+    pub instruction: Option<Instruction>,
+}
+
+impl ConditionContext<'_> {
+    /// The cells the instruction about to run will write to (`writes` true) or read from.
+    fn accesses(&self, writes: bool) -> impl Iterator<Item=i64> + '_ {
+        let instruction = self.instruction.as_ref();
+        let write_parameters = instruction.map_or(&[][..], |instruction| instruction.write_parameters());
+        instruction.map(|instruction| instruction.parameters()).unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .filter(move |(index, _)| write_parameters.contains(index) == writes)
+            .filter_map(move |(_, parameter)| match parameter {
+                Parameter::Position(address) => Some(address as i64),
+                Parameter::Relative(offset) => Some(self.relative_base as i64 + offset as i64),
+                Parameter::Immediate(_) => None,
+            })
+    }
+}
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum ConditionError {
+    #[fail(display = "unexpected character {:?} at column {}", _1, _0)]
+    UnexpectedCharacter(usize, char),
+    #[fail(display = "expected {} at column {}, found {}", expected, column, found)]
+    Unexpected { column: usize, expected: &'static str, found: String },
+    #[fail(display = "unknown name `{}` at column {}", _1, _0)]
+    UnknownName(usize, String),
+    #[fail(display = "number at column {} is too large", _0)]
+    NumberTooLarge(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "`{}`", value),
+            Token::Name(name) => write!(f, "`{}`", name),
+            Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+            Token::End => write!(f, "the end of the condition"),
+        }
+    }
+}
+
+/// Symbols, longest first so that `<=` isn't read as `<` then `=`.
+const SYMBOLS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=",
+    "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", "[", "]",
+];
+
+/// Splits a condition into tokens, each paired with its 1-based column.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let mut tokens = Vec::new();
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let mut index = 0;
+    while index < chars.len() {
+        let (offset, c) = chars[index];
+        let column = index + 1;
+        if c.is_whitespace() {
+            index += 1;
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some((_, c)) = chars.get(index).filter(|(_, c)| c.is_ascii_digit() || *c == '_') {
+                if *c != '_' {
+                    digits.push(*c);
+                }
+                index += 1;
+            }
+            let value = digits.parse().map_err(|_| ConditionError::NumberTooLarge(column))?;
+            tokens.push((column, Token::Number(value)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some((_, c)) = chars.get(index).filter(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                name.push(*c);
+                index += 1;
+            }
+            tokens.push((column, Token::Name(name)));
+        } else {
+            let symbol = SYMBOLS.iter()
+                .find(|symbol| text[offset..].starts_with(**symbol))
+                .ok_or(ConditionError::UnexpectedCharacter(column, c))?;
+            tokens.push((column, Token::Symbol(symbol)));
+            index += symbol.len();
+        }
+    }
+    tokens.push((chars.len() + 1, Token::End));
+    Ok(tokens)
+}
+
+type Evaluator = Box<dyn Fn(&ConditionContext) -> Option<i64> + Send + Sync>;
+
+/// A recursive descent parser that builds the evaluator as it goes, so nothing is left to
+/// interpret per step except the closures themselves.
+struct Compiler {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    needs_instruction: bool,
+}
+
+impl Compiler {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].1
+    }
+
+    fn next(&mut self) -> (usize, Token) {
+        let token = self.tokens[self.position].clone();
+        if token.1 != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, symbol: &'static str, expected: &'static str) -> Result<(), ConditionError> {
+        match self.next() {
+            (_, Token::Symbol(found)) if found == symbol => Ok(()),
+            (column, found) => Err(ConditionError::Unexpected { column, expected, found: found.to_string() }),
+        }
+    }
+
+    /// Parses one level of left-associative binary operators.
+    fn binary<F>(&mut self, operators: &[&'static str], operand: F) -> Result<Evaluator, ConditionError>
+        where F: Fn(&mut Compiler) -> Result<Evaluator, ConditionError>
+    {
+        let mut left = operand(self)?;
+        while let Token::Symbol(symbol) = *self.peek() {
+            if !operators.contains(&symbol) {
+                break;
+            }
+            self.next();
+            let right = operand(self)?;
+            left = combine(symbol, left, right);
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Evaluator, ConditionError> {
+        self.binary(&["||"], Compiler::and)
+    }
+
+    fn and(&mut self) -> Result<Evaluator, ConditionError> {
+        self.binary(&["&&"], Compiler::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Evaluator, ConditionError> {
+        self.binary(&["==", "!=", "<", "<=", ">", ">="], Compiler::sum)
+    }
+
+    fn sum(&mut self) -> Result<Evaluator, ConditionError> {
+        self.binary(&["+", "-"], Compiler::product)
+    }
+
+    fn product(&mut self) -> Result<Evaluator, ConditionError> {
+        self.binary(&["*", "/", "%"], Compiler::unary)
+    }
+
+    fn unary(&mut self) -> Result<Evaluator, ConditionError> {
+        match *self.peek() {
+            Token::Symbol("!") => {
+                self.next();
+                let operand = self.unary()?;
+                Ok(Box::new(move |context| operand(context).map(|value| (value == 0) as i64)))
+            },
+            Token::Symbol("-") => {
+                self.next();
+                let operand = self.unary()?;
+                Ok(Box::new(move |context| operand(context).map(i64::wrapping_neg)))
+            },
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Evaluator, ConditionError> {
+        match self.next() {
+            (_, Token::Number(value)) => Ok(Box::new(move |_| Some(value))),
+            (_, Token::Symbol("(")) => {
+                let inner = self.or()?;
+                self.expect(")", "`)`")?;
+                Ok(inner)
+            },
+            (column, Token::Name(name)) => self.name(column, &name),
+            (column, found) => Err(ConditionError::Unexpected { column, expected: "a value", found: found.to_string() }),
+        }
+    }
+
+    fn name(&mut self, column: usize, name: &str) -> Result<Evaluator, ConditionError> {
+        let opcode = match name {
+            "ip" => return Ok(Box::new(|context| Some(context.instruction_pointer as i64))),
+            "rb" => return Ok(Box::new(|context| Some(context.relative_base as i64))),
+            "cycle" => return Ok(Box::new(|context| Some(context.cycle as i64))),
+            "true" => return Ok(Box::new(|_| Some(1))),
+            "false" => return Ok(Box::new(|_| Some(0))),
+            "opcode" => {
+                self.needs_instruction = true;
+                return Ok(Box::new(|context| context.instruction.as_ref().map(|instruction| instruction.opcode_number() as i64)));
+            },
+            "mem" => {
+                self.expect("[", "`[` after `mem`")?;
+                let address = self.or()?;
+                self.expect("]", "`]`")?;
+                return Ok(Box::new(move |context| {
+                    let address = address(context)?;
+                    if address < 0 {
+                        return None;
+                    }
+                    context.memory.peek_slot(address as Address).ok().map(|value| value as i64)
+                }));
+            },
+            "write_to" | "read_from" => {
+                let writes = name == "write_to";
+                self.needs_instruction = true;
+                self.expect("(", "`(`")?;
+                let address = self.or()?;
+                self.expect(")", "`)`")?;
+                return Ok(Box::new(move |context| {
+                    let address = address(context)?;
+                    Some(context.accesses(writes).any(|accessed| accessed == address) as i64)
+                }));
+            },
+            "Add" => Opcode::Add,
+            "Multiply" => Opcode::Multiply,
+            "Input" => Opcode::Input,
+            "Output" => Opcode::Output,
+            "JumpIfTrue" => Opcode::JumpIfTrue,
+            "JumpIfFalse" => Opcode::JumpIfFalse,
+            "LessThan" => Opcode::LessThan,
+            "Equal" => Opcode::Equal,
+            "AdjustRelativeBase" => Opcode::AdjustRelativeBase,
+            "Halt" => Opcode::Halt,
+            _ => return Err(ConditionError::UnknownName(column, name.into())),
+        };
+        let number = opcode.encode() as i64;
+        Ok(Box::new(move |_| Some(number)))
+    }
+}
+
+fn combine(symbol: &'static str, left: Evaluator, right: Evaluator) -> Evaluator {
+    let arithmetic = |f: fn(i64, i64) -> Option<i64>, left: Evaluator, right: Evaluator| -> Evaluator {
+        Box::new(move |context| f(left(context)?, right(context)?))
+    };
+    match symbol {
+        "&&" => Box::new(move |context| Some((left(context)? != 0 && right(context)? != 0) as i64)),
+        "||" => Box::new(move |context| Some((left(context)? != 0 || right(context)? != 0) as i64)),
+        "==" => arithmetic(|a, b| Some((a == b) as i64), left, right),
+        "!=" => arithmetic(|a, b| Some((a != b) as i64), left, right),
+        "<" => arithmetic(|a, b| Some((a < b) as i64), left, right),
+        "<=" => arithmetic(|a, b| Some((a <= b) as i64), left, right),
+        ">" => arithmetic(|a, b| Some((a > b) as i64), left, right),
+        ">=" => arithmetic(|a, b| Some((a >= b) as i64), left, right),
+        "+" => arithmetic(|a, b| Some(a.wrapping_add(b)), left, right),
+        "-" => arithmetic(|a, b| Some(a.wrapping_sub(b)), left, right),
+        "*" => arithmetic(|a, b| Some(a.wrapping_mul(b)), left, right),
+        "/" => arithmetic(i64::checked_div, left, right),
+        "%" => arithmetic(i64::checked_rem, left, right),
+        _ => unreachable!("unhandled operator {}", symbol),
+    }
+}
+
+/// A compiled breakpoint condition, such as `ip == 42 && mem[100] > 5` or `opcode == Output`.
+///
+/// Conditions are C-like expressions over 64 bit integers, where comparisons and `&&`, `||` and
+/// `!` give 1 or 0 and anything non-zero is true. They can use:
+///
+/// - `ip`, `rb` and `cycle`;
+/// - `mem[ADDRESS]`, the value of a memory cell;
+/// - `opcode`, the opcode of the instruction about to run, to compare against `Add`, `Multiply`,
+///   `Input`, `Output`, `JumpIfTrue`, `JumpIfFalse`, `LessThan`, `Equal`, `AdjustRelativeBase`
+///   or `Halt`;
+/// - `write_to(ADDRESS)` and `read_from(ADDRESS)`, whether the instruction about to run writes to
+///   or reads from a cell;
+/// - numbers, which may contain `_` separators like `1_000_000`.
+///
+/// Anything that can't be worked out, such as reading outside memory or dividing by zero, makes
+/// the whole condition false.
+pub struct Condition {
+    source: String,
+    evaluator: Evaluator,
+    needs_instruction: bool,
+}
+
+impl fmt::Debug for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Condition").field(&self.source).finish()
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Condition::compile(s)
+    }
+}
+
+impl Condition {
+    pub fn compile(text: &str) -> Result<Condition, ConditionError> {
+        let mut compiler = Compiler {
+            tokens: tokenize(text)?,
+            position: 0,
+            needs_instruction: false,
+        };
+        let evaluator = compiler.or()?;
+        match compiler.next() {
+            (_, Token::End) => Ok(Condition {
+                source: text.trim().into(),
+                evaluator,
+                needs_instruction: compiler.needs_instruction,
+            }),
+            (column, found) => Err(ConditionError::Unexpected { column, expected: "an operator or the end", found: found.to_string() }),
+        }
+    }
+
+    pub fn evaluate_in(&self, context: &ConditionContext) -> bool {
+        (self.evaluator)(context).is_some_and(|value| value != 0)
+    }
+
+    /// Whether the condition holds for the computer as it is now, before its next step. The
+    /// instruction is only decoded for conditions that look at it.
+    pub fn evaluate<M: Memory>(&self, computer: &Computer<M>) -> bool {
+        let instruction_pointer = computer.instruction_pointer();
+        let memory = computer.memory();
+        let instruction = if self.needs_instruction {
+            memory.peek_stream_from(instruction_pointer)
+                .and_then(|mut stream| computer.registry().decode(&mut stream))
+                .ok()
+        } else {
+            None
+        };
+        self.evaluate_in(&ConditionContext {
+            instruction_pointer,
+            relative_base: computer.relative_base(),
+            cycle: computer.cycle_count(),
+            memory,
+            instruction,
+        })
+    }
+}
+
+impl<'a, M: Memory> Computer<'a, M> {
+    /// Steps until `condition` holds before a step or the program halts, returning whether the
+    /// condition was hit. The condition isn't checked before the first step, so calling this
+    /// again after a hit carries on rather than stopping straight away.
+    pub fn run_until(&mut self, condition: &Condition) -> Result<bool, Fault> {
+        let mut first = true;
+        while !self.halted {
+            if !first && condition.evaluate(self) {
+                return Ok(true);
+            }
+            first = false;
+            self.step()?;
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, SimpleMemory, TraceLevel};
+    use crate::intcode::breakpoint::{Condition, ConditionError};
+
+    #[test]
+    fn compiles_and_reports_errors() {
+        for text in &["ip == 42 && mem[100] > 5", "cycle > 1_000_000", "opcode == Output", "write_to(7)", "!(rb < -3) || (1 + 2 * 3) % 4 == 3"] {
+            assert!(Condition::compile(text).is_ok(), "{}", text);
+        }
+        assert_eq!(Condition::compile("ip = 4").unwrap_err(), ConditionError::UnexpectedCharacter(4, '='));
+        assert_eq!(Condition::compile("pc == 4").unwrap_err(), ConditionError::UnknownName(1, "pc".into()));
+        assert_eq!(Condition::compile("ip ==").unwrap_err().to_string(), "expected a value at column 6, found the end of the condition");
+        assert_eq!(Condition::compile("(ip == 4").unwrap_err().to_string(), "expected `)` at column 9, found the end of the condition");
+        assert_eq!(Condition::compile("ip 4").unwrap_err().to_string(), "expected an operator or the end at column 4, found `4`");
+        assert_eq!(Condition::compile("99999999999999999999").unwrap_err(), ConditionError::NumberTooLarge(1));
+    }
+
+    #[test]
+    fn evaluates_against_the_machine() {
+        let mut memory = SimpleMemory::from_literal(&[1101, 2, 3, 7, 104, 9, 99, 0]);
+        let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let holds = |computer: &Computer<SimpleMemory>, text: &str| Condition::compile(text).unwrap().evaluate(computer);
+
+        assert!(holds(&computer, "ip == 0 && cycle == 0 && opcode == Add"));
+        assert!(holds(&computer, "write_to(7) && !write_to(3) && !read_from(2)"));
+        assert!(holds(&computer, "mem[0] / 100 == 11"));
+        assert!(!holds(&computer, "mem[8] == 0 || mem[8] != 0"));
+        assert!(!holds(&computer, "1 / 0 == 0"));
+
+        computer.step().unwrap();
+        assert!(holds(&computer, "ip == 4 && mem[7] == 5 && opcode == Output && !write_to(7)"));
+    }
+
+    #[test]
+    fn runs_until_a_condition_holds() {
+        // out [15]; add [15], 1, [15]; lt [15], 5, [14]; jt [14], 0; hlt
+        let mut memory = SimpleMemory::from_literal(&[4, 15, 1001, 15, 1, 15, 1007, 15, 5, 14, 1005, 14, 0, 99, 0, 0]);
+        let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+
+        let condition = Condition::compile("opcode == Output && mem[15] >= 2").unwrap();
+        assert_eq!(computer.run_until(&condition), Ok(true));
+        assert_eq!(computer.instruction_pointer(), 0);
+        assert_eq!(computer.memory().as_slice()[15], 2);
+
+        // running again carries on to the next hit
+        assert_eq!(computer.run_until(&condition), Ok(true));
+        assert_eq!(computer.memory().as_slice()[15], 3);
+
+        let condition = Condition::compile("write_to(15) && cycle > 100").unwrap();
+        assert_eq!(computer.run_until(&condition), Ok(false));
+        assert!(computer.halted);
+    }
+}
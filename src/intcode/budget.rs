@@ -0,0 +1,273 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::time::Duration;
+use super::{Address, ComputerError, MemoryValue};
+
+/// Limits on how much work a [`Computer`](super::Computer) may do before giving up. Each limit is
+/// optional and reported with its own [`ComputerError`](super::ComputerError) when exceeded.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExecutionBudget {
+    pub max_cycles: Option<usize>,
+    pub max_duration: Option<Duration>,
+    /// Writes at or above this address are refused. Memory doesn't grow as it is written, so this
+    /// bounds which addresses a program may write rather than how much memory it takes up, and
+    /// reads are not limited.
+    pub memory_limit: Option<Address>,
+}
+
+impl ExecutionBudget {
+    pub fn unlimited() -> ExecutionBudget {
+        ExecutionBudget::default()
+    }
+
+    pub fn with_max_cycles(mut self, cycles: usize) -> ExecutionBudget {
+        self.max_cycles = Some(cycles);
+        self
+    }
+
+    pub fn with_max_duration(mut self, duration: Duration) -> ExecutionBudget {
+        self.max_duration = Some(duration);
+        self
+    }
+
+    pub fn with_memory_limit(mut self, limit: Address) -> ExecutionBudget {
+        self.memory_limit = Some(limit);
+        self
+    }
+}
+
+impl ComputerError {
+    /// Whether the error means a budget ran out, rather than the program doing something wrong.
+    pub fn is_budget_exceeded(&self) -> bool {
+        matches!(self, ComputerError::CycleBudgetExceeded | ComputerError::TimeBudgetExceeded | ComputerError::MemoryBudgetExceeded)
+    }
+}
+
+/// A loop the machine can never leave: it returned to `entry` with memory exactly as it was the
+/// last time it was there, without performing any IO in between.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoopReport {
+    pub entry: Address,
+    pub period: usize,
+    pub instructions: Vec<Address>,
+    pub memory_cells: Vec<Address>,
+}
+
+impl fmt::Display for LoopReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "loop at {} repeats every {} cycles through instructions {:?}", self.entry, self.period, self.instructions)?;
+        if !self.memory_cells.is_empty() {
+            write!(f, " touching memory {:?}", self.memory_cells)?;
+        }
+        Ok(())
+    }
+}
+
+struct Checkpoint {
+    instruction_pointer: Address,
+    relative_base: MemoryValue,
+    cycle: usize,
+}
+
+/// Detects non-terminating loops by periodically checkpointing the registers and then tracking
+/// the original value of every cell written since. Rather than hashing, the number of cells that
+/// currently differ from the checkpoint is maintained on every write, so recognising a repeated
+/// state is an exact comparison costing nothing more than the register check. Checkpoints are
+/// spaced at doubling intervals (as in Brent's cycle detection) so loops of any period are
+/// eventually caught. Any IO discards the checkpoint, as a loop that interacts with the outside
+/// world is making progress.
+///
+/// Reading or writing a [device](super::devices::Device) counts as IO too, as a device can give
+/// a different value every time it is read, like a clock being polled.
+pub struct LoopDetector {
+    checkpoint: Option<Checkpoint>,
+    interval: usize,
+    original_values: HashMap<Address, MemoryValue>,
+    differing_cells: usize,
+    visited: BTreeSet<Address>,
+}
+
+impl LoopDetector {
+    pub fn new() -> LoopDetector {
+        LoopDetector {
+            checkpoint: None,
+            interval: 1,
+            original_values: HashMap::new(),
+            differing_cells: 0,
+            visited: BTreeSet::new(),
+        }
+    }
+
+    pub fn on_step(&mut self, instruction_pointer: Address, relative_base: MemoryValue, cycle: usize) -> Option<LoopReport> {
+        let checkpoint = match &self.checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => {
+                self.take_checkpoint(instruction_pointer, relative_base, cycle);
+                return None;
+            },
+        };
+
+        // a step retried after failing (for want of input, say) isn't a loop
+        if cycle == checkpoint.cycle {
+            return None;
+        }
+
+        if checkpoint.instruction_pointer == instruction_pointer
+            && checkpoint.relative_base == relative_base
+            && self.differing_cells == 0 {
+            return Some(LoopReport {
+                entry: instruction_pointer,
+                period: cycle - checkpoint.cycle,
+                instructions: self.visited.iter().cloned().collect(),
+                memory_cells: {
+                    let mut cells = self.original_values.keys().cloned().collect::<Vec<_>>();
+                    cells.sort();
+                    cells
+                },
+            });
+        }
+
+        let since_checkpoint = cycle - checkpoint.cycle;
+        self.visited.insert(instruction_pointer);
+        if since_checkpoint >= self.interval {
+            self.interval *= 2;
+            self.take_checkpoint(instruction_pointer, relative_base, cycle);
+        }
+        None
+    }
+
+    pub fn on_write(&mut self, address: Address, old_value: MemoryValue, new_value: MemoryValue) {
+        let original = *self.original_values.entry(address).or_insert(old_value);
+        match (old_value != original, new_value != original) {
+            (false, true) => self.differing_cells += 1,
+            (true, false) => self.differing_cells -= 1,
+            _ => {},
+        }
+    }
+
+    pub fn on_io(&mut self) {
+        self.checkpoint = None;
+    }
+
+    fn take_checkpoint(&mut self, instruction_pointer: Address, relative_base: MemoryValue, cycle: usize) {
+        self.checkpoint = Some(Checkpoint {
+            instruction_pointer,
+            relative_base,
+            cycle,
+        });
+        self.original_values.clear();
+        self.differing_cells = 0;
+        self.visited.clear();
+        self.visited.insert(instruction_pointer);
+    }
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        LoopDetector::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, ComputerError, RecordedIO, SimpleMemory, TraceLevel};
+    use crate::intcode::devices::{MappedMemory, MillisecondClock};
+    use crate::intcode::budget::{ExecutionBudget, LoopReport};
+    use std::time::Duration;
+
+    fn run_with_loop_detection(program: &[i32]) -> Result<(), ComputerError> {
+        let mut memory = SimpleMemory::from_literal(program);
+        let mut computer = Computer::new(&mut memory).with_loop_detection();
+        computer.run_until_halted().map_err(|fault| fault.error)
+    }
+
+    #[test]
+    fn cycle_budget_stops_infinite_loops() {
+        let mut memory = SimpleMemory::from_literal(&[1105, 1, 0]);
+        let mut computer = Computer::new(&mut memory)
+            .with_budget(ExecutionBudget::unlimited().with_max_cycles(100));
+        assert_eq!(computer.run_until_halted().map_err(|fault| fault.error), Err(ComputerError::CycleBudgetExceeded));
+        assert_eq!(computer.cycle_count(), 100);
+    }
+
+    #[test]
+    fn time_budget_stops_infinite_loops() {
+        let mut memory = SimpleMemory::from_literal(&[1105, 1, 0]);
+        let mut computer = Computer::new(&mut memory)
+            .with_budget(ExecutionBudget::unlimited().with_max_duration(Duration::from_millis(20)));
+        assert_eq!(computer.run_until_halted().map_err(|fault| fault.error), Err(ComputerError::TimeBudgetExceeded));
+    }
+
+    #[test]
+    fn memory_budget_refuses_distant_writes() {
+        let mut memory = SimpleMemory::from_literal(&[1101, 1, 1, 9, 1101, 1, 1, 10, 99, 0, 0]);
+        let mut computer = Computer::new(&mut memory)
+            .with_budget(ExecutionBudget::unlimited().with_memory_limit(10));
+        assert_eq!(computer.run_until_halted().map_err(|fault| fault.error), Err(ComputerError::MemoryBudgetExceeded));
+        assert_eq!(computer.instruction_pointer(), 4);
+    }
+
+    #[test]
+    fn detects_jump_to_self() {
+        assert_eq!(run_with_loop_detection(&[1105, 1, 0]), Err(ComputerError::InfiniteLoopDetected(Box::new(LoopReport {
+            entry: 0,
+            period: 1,
+            instructions: vec![0],
+            memory_cells: vec![],
+        }))));
+    }
+
+    #[test]
+    fn detects_loops_that_restore_memory() {
+        // toggles slot 13 between 0 and 1 forever
+        let program = [
+            1008, 13, 0, 13,
+            1001, 12, 0, 12,
+            1105, 1, 0,
+            99, 0, 0,
+        ];
+        match run_with_loop_detection(&program) {
+            Err(ComputerError::InfiniteLoopDetected(report)) => {
+                assert_eq!(report.period % 6, 0);
+                assert_eq!(report.instructions, vec![0, 4, 8]);
+                assert_eq!(report.memory_cells, vec![12, 13]);
+            },
+            other => panic!("expected an infinite loop, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ignores_loops_that_make_progress() {
+        // counts down from 50 before halting
+        let program = [
+            1001, 9, -1, 9,
+            1005, 9, 0,
+            99, 0, 50,
+        ];
+        assert_eq!(run_with_loop_detection(&program), Ok(()));
+    }
+
+    #[test]
+    fn retried_steps_are_not_loops() {
+        let mut memory = SimpleMemory::from_literal(&[3, 5, 4, 5, 99, 0]);
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![])
+            .with_loop_detection();
+        assert_eq!(computer.run_until_halted().unwrap_err().error, ComputerError::NoInputAvailable);
+        computer.push_input(7);
+        assert_eq!(computer.run_until_halted().map_err(|fault| fault.error), Ok(()));
+        assert_eq!(computer.io_record, vec![RecordedIO::UserInput(7), RecordedIO::Output(7)]);
+    }
+
+    #[test]
+    fn polling_a_device_is_not_a_loop() {
+        // waits for the clock to reach 5ms
+        let mut memory = MappedMemory::new(SimpleMemory::from_literal(&[1007, 100, 5, 8, 1005, 8, 0, 99, 0]));
+        memory.map_device(100..101, MillisecondClock::new()).unwrap();
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_loop_detection();
+        assert_eq!(computer.run_until_halted().map_err(|fault| fault.error), Ok(()));
+    }
+}
@@ -0,0 +1,1515 @@
+use failure::Fail;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use super::{Address, Instruction, MemoryValue, Opcode, Parameter, ProgramImage, SimpleMemory};
+use super::debug_info::{DebugInfo, Storage};
+use super::image::LineInfo;
+use super::protection::{Protection, ProtectionMap};
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum CompileErrorKind {
+    #[fail(display = "unexpected character '{}'", _0)]
+    UnexpectedCharacter(char),
+    #[fail(display = "number {} does not fit in a memory slot", _0)]
+    NumberTooLarge(String),
+    #[fail(display = "expected {}, found {}", expected, found)]
+    Unexpected { expected: String, found: String },
+    #[fail(display = "'{}' is not defined", _0)]
+    UndefinedVariable(String),
+    #[fail(display = "function '{}' is not defined", _0)]
+    UndefinedFunction(String),
+    #[fail(display = "'{}' is already defined", _0)]
+    Redefinition(String),
+    #[fail(display = "names starting with '__' are reserved, so '{}' can't be used", _0)]
+    ReservedName(String),
+    #[fail(display = "'{}' takes {} argument(s) but was given {}", name, expected, found)]
+    WrongArgumentCount { name: String, expected: usize, found: usize },
+    #[fail(display = "'{}' is an array, so needs an index", _0)]
+    ArrayWithoutIndex(String),
+    #[fail(display = "'{}' is not an array", _0)]
+    NotAnArray(String),
+    #[fail(display = "index {} is out of bounds for '{}', which has length {}", index, name, length)]
+    IndexOutOfBounds { name: String, index: MemoryValue, length: usize },
+    #[fail(display = "arrays can only be declared outside of functions")]
+    LocalArray,
+    #[fail(display = "array lengths must be positive")]
+    InvalidArrayLength,
+    #[fail(display = "global variables can only be initialised with a constant")]
+    NonConstantInitializer,
+    #[fail(display = "'{}' outside of a loop", _0)]
+    OutsideLoop(&'static str),
+    #[fail(display = "program has no 'main' function")]
+    MissingMain,
+    #[fail(display = "'main' can't take any parameters")]
+    MainWithParameters,
+}
+
+/// A program that failed to compile, and where in the source the problem is. `line` and
+/// `column` are 1-based.
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+#[fail(display = "{} at line {}, column {}", kind, line, column)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl CompileError {
+    fn new(kind: CompileErrorKind, position: Position) -> CompileError {
+        CompileError {
+            kind,
+            line: position.line,
+            column: position.column,
+        }
+    }
+
+    /// The error along with the offending line of `source`, and a caret under the column.
+    pub fn render(&self, source: &str) -> String {
+        let mut text = format!("error: {}\n --> line {}, column {}", self.kind, self.line, self.column);
+        if let Some(line) = source.lines().nth(self.line.saturating_sub(1)) {
+            let number = self.line.to_string();
+            let gutter = " ".repeat(number.len());
+            // keep tabs so the caret lines up however wide they are displayed
+            let indent = line.chars()
+                .take(self.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            text += &format!("\n{} |\n{} | {}\n{} | {}^", gutter, number, line, gutter, indent);
+        }
+        text
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+const KEYWORDS: [&str; 8] = ["var", "fn", "if", "else", "while", "return", "break", "continue"];
+
+/// Two character symbols come first so that they are matched in preference to their prefixes.
+const SYMBOLS: [&str; 23] = [
+    "&&", "||", "==", "!=", "<=", ">=",
+    "(", ")", "{", "}", "[", "]", ",", ";", "=", "+", "-", "*", "/", "%", "<", ">", "!",
+];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Number(MemoryValue),
+    Identifier(String),
+    Keyword(&'static str),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Identifier(name) => write!(f, "'{}'", name),
+            Token::Keyword(keyword) => write!(f, "'{}'", keyword),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+            Token::End => write!(f, "end of input"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, Position)>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    let mut position = Position { line: 1, column: 1 };
+    let advance = |offset: &mut usize, position: &mut Position, c: char| {
+        *offset += c.len_utf8();
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    };
+
+    while let Some(c) = source[offset..].chars().next() {
+        let (start, rest) = (position, &source[offset..]);
+        if c.is_whitespace() {
+            advance(&mut offset, &mut position, c);
+        } else if rest.starts_with("//") {
+            for c in rest.chars().take_while(|c| *c != '\n') {
+                advance(&mut offset, &mut position, c);
+            }
+        } else if c.is_ascii_digit() {
+            let digits = rest.chars().take_while(char::is_ascii_digit).collect::<String>();
+            let value = digits.parse().map_err(|_| CompileError::new(CompileErrorKind::NumberTooLarge(digits.clone()), start))?;
+            for c in digits.chars() {
+                advance(&mut offset, &mut position, c);
+            }
+            tokens.push((Token::Number(value), start));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let word = rest.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').collect::<String>();
+            for c in word.chars() {
+                advance(&mut offset, &mut position, c);
+            }
+            let token = match KEYWORDS.iter().find(|keyword| **keyword == word) {
+                Some(keyword) => Token::Keyword(keyword),
+                None => Token::Identifier(word),
+            };
+            tokens.push((token, start));
+        } else {
+            let symbol = SYMBOLS.iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(|| CompileError::new(CompileErrorKind::UnexpectedCharacter(c), start))?;
+            for c in symbol.chars() {
+                advance(&mut offset, &mut position, c);
+            }
+            tokens.push((Token::Symbol(symbol), start));
+        }
+    }
+    tokens.push((Token::End, position));
+    Ok(tokens)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug)]
+enum ExpressionKind {
+    Number(MemoryValue),
+    Variable(String),
+    Index(String, Box<Expression>),
+    Call(String, Vec<Expression>),
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Clone, Debug)]
+struct Expression {
+    kind: ExpressionKind,
+    position: Position,
+}
+
+impl Expression {
+    /// The value of the expression, if it can be worked out without running it.
+    fn constant_value(&self) -> Option<MemoryValue> {
+        match &self.kind {
+            ExpressionKind::Number(value) => Some(*value),
+            ExpressionKind::Unary(UnaryOperator::Negate, operand) => operand.constant_value()?.checked_neg(),
+            ExpressionKind::Unary(UnaryOperator::Not, operand) => Some((operand.constant_value()? == 0) as MemoryValue),
+            ExpressionKind::Binary(operator, left, right) => {
+                let (left, right) = (left.constant_value()?, right.constant_value()?);
+                match operator {
+                    BinaryOperator::Add => left.checked_add(right),
+                    BinaryOperator::Subtract => left.checked_sub(right),
+                    BinaryOperator::Multiply => left.checked_mul(right),
+                    BinaryOperator::Divide => left.checked_div(right),
+                    BinaryOperator::Remainder => left.checked_rem(right),
+                    BinaryOperator::Less => Some((left < right) as MemoryValue),
+                    BinaryOperator::LessOrEqual => Some((left <= right) as MemoryValue),
+                    BinaryOperator::Greater => Some((left > right) as MemoryValue),
+                    BinaryOperator::GreaterOrEqual => Some((left >= right) as MemoryValue),
+                    BinaryOperator::Equal => Some((left == right) as MemoryValue),
+                    BinaryOperator::NotEqual => Some((left != right) as MemoryValue),
+                    BinaryOperator::And => Some((left != 0 && right != 0) as MemoryValue),
+                    BinaryOperator::Or => Some((left != 0 || right != 0) as MemoryValue),
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum StatementKind {
+    Var(String, Option<Expression>),
+    Assign(Expression, Expression),
+    If(Expression, Vec<Statement>, Vec<Statement>),
+    While(Expression, Vec<Statement>),
+    Return(Option<Expression>),
+    Break,
+    Continue,
+    Expression(Expression),
+}
+
+#[derive(Clone, Debug)]
+struct Statement {
+    kind: StatementKind,
+    position: Position,
+}
+
+#[derive(Clone, Debug)]
+struct Function {
+    name: String,
+    parameters: Vec<(String, Position)>,
+    body: Vec<Statement>,
+    position: Position,
+}
+
+#[derive(Clone, Debug)]
+enum Item {
+    Global {
+        name: String,
+        length: Option<MemoryValue>,
+        initializer: Option<Expression>,
+        position: Position,
+    },
+    Function(Function),
+}
+
+struct Parser {
+    tokens: Vec<(Token, Position)>,
+    next: usize,
+    /// Whether names starting with `__` can be used, as they can in the runtime library.
+    allow_reserved: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].0
+    }
+
+    fn position(&self) -> Position {
+        self.tokens[self.next].1
+    }
+
+    fn advance(&mut self) -> (Token, Position) {
+        let token = self.tokens[self.next].clone();
+        if self.next + 1 < self.tokens.len() {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn at(&self, text: &str) -> bool {
+        match self.peek() {
+            Token::Symbol(symbol) | Token::Keyword(symbol) => *symbol == text,
+            _ => false,
+        }
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.at(text);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, CompileError> {
+        Err(CompileError::new(CompileErrorKind::Unexpected {
+            expected: expected.to_string(),
+            found: self.peek().to_string(),
+        }, self.position()))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Position, CompileError> {
+        let position = self.position();
+        if self.eat(text) {
+            Ok(position)
+        } else {
+            self.unexpected(&format!("'{}'", text))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<(String, Position), CompileError> {
+        let position = self.position();
+        match self.peek().clone() {
+            Token::Identifier(name) if name.starts_with("__") && !self.allow_reserved => Err(CompileError::new(CompileErrorKind::ReservedName(name), position)),
+            Token::Identifier(name) => {
+                self.advance();
+                Ok((name, position))
+            },
+            _ => self.unexpected("a name"),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Item>, CompileError> {
+        let mut items = Vec::new();
+        while *self.peek() != Token::End {
+            if self.eat("fn") {
+                items.push(Item::Function(self.function()?));
+            } else if self.eat("var") {
+                let (name, position) = self.identifier()?;
+                let length = if self.eat("[") {
+                    let length = self.expression()?;
+                    self.expect("]")?;
+                    match length.constant_value() {
+                        Some(length) if length > 0 => Some(length),
+                        _ => return Err(CompileError::new(CompileErrorKind::InvalidArrayLength, length.position)),
+                    }
+                } else {
+                    None
+                };
+                let initializer = if self.eat("=") { Some(self.expression()?) } else { None };
+                self.expect(";")?;
+                items.push(Item::Global { name, length, initializer, position });
+            } else {
+                return self.unexpected("'fn' or 'var'");
+            }
+        }
+        Ok(items)
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        let (name, position) = self.identifier()?;
+        self.expect("(")?;
+        let mut parameters = Vec::new();
+        if !self.at(")") {
+            loop {
+                parameters.push(self.identifier()?);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+        let body = self.block()?;
+        Ok(Function { name, parameters, body, position })
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, CompileError> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, CompileError> {
+        let position = self.position();
+        let kind = if self.eat("var") {
+            let (name, _) = self.identifier()?;
+            if self.at("[") {
+                return Err(CompileError::new(CompileErrorKind::LocalArray, self.position()));
+            }
+            let initializer = if self.eat("=") { Some(self.expression()?) } else { None };
+            self.expect(";")?;
+            StatementKind::Var(name, initializer)
+        } else if self.eat("if") {
+            return self.if_statement(position);
+        } else if self.eat("while") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            StatementKind::While(condition, self.block()?)
+        } else if self.eat("return") {
+            let value = if self.at(";") { None } else { Some(self.expression()?) };
+            self.expect(";")?;
+            StatementKind::Return(value)
+        } else if self.eat("break") {
+            self.expect(";")?;
+            StatementKind::Break
+        } else if self.eat("continue") {
+            self.expect(";")?;
+            StatementKind::Continue
+        } else {
+            let expression = self.expression()?;
+            if self.eat("=") {
+                match expression.kind {
+                    ExpressionKind::Variable(_) | ExpressionKind::Index(..) => {},
+                    _ => return Err(CompileError::new(CompileErrorKind::Unexpected {
+                        expected: "a variable to assign to".into(),
+                        found: "an expression".into(),
+                    }, expression.position)),
+                }
+                let value = self.expression()?;
+                self.expect(";")?;
+                StatementKind::Assign(expression, value)
+            } else {
+                self.expect(";")?;
+                StatementKind::Expression(expression)
+            }
+        };
+        Ok(Statement { kind, position })
+    }
+
+    fn if_statement(&mut self, position: Position) -> Result<Statement, CompileError> {
+        self.expect("(")?;
+        let condition = self.expression()?;
+        self.expect(")")?;
+        let then = self.block()?;
+        let otherwise = if self.at("else") {
+            self.advance();
+            let position = self.position();
+            if self.eat("if") {
+                vec![self.if_statement(position)?]
+            } else {
+                self.block()?
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(Statement { kind: StatementKind::If(condition, then, otherwise), position })
+    }
+
+    fn expression(&mut self) -> Result<Expression, CompileError> {
+        self.binary(0)
+    }
+
+    /// Parses binary operators by precedence climbing, loosest binding first.
+    fn binary(&mut self, level: usize) -> Result<Expression, CompileError> {
+        const LEVELS: [&[(&str, BinaryOperator)]; 5] = [
+            &[("||", BinaryOperator::Or)],
+            &[("&&", BinaryOperator::And)],
+            &[
+                ("==", BinaryOperator::Equal), ("!=", BinaryOperator::NotEqual),
+                ("<", BinaryOperator::Less), ("<=", BinaryOperator::LessOrEqual),
+                (">", BinaryOperator::Greater), (">=", BinaryOperator::GreaterOrEqual),
+            ],
+            &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+            &[("*", BinaryOperator::Multiply), ("/", BinaryOperator::Divide), ("%", BinaryOperator::Remainder)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some((_, operator)) = LEVELS[level].iter().find(|(symbol, _)| self.at(symbol)) {
+            let position = self.position();
+            self.advance();
+            let right = self.binary(level + 1)?;
+            left = Expression {
+                kind: ExpressionKind::Binary(*operator, Box::new(left), Box::new(right)),
+                position,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, CompileError> {
+        let position = self.position();
+        let operator = if self.eat("-") {
+            UnaryOperator::Negate
+        } else if self.eat("!") {
+            UnaryOperator::Not
+        } else {
+            return self.primary();
+        };
+        let operand = self.unary()?;
+        Ok(Expression { kind: ExpressionKind::Unary(operator, Box::new(operand)), position })
+    }
+
+    fn primary(&mut self) -> Result<Expression, CompileError> {
+        let position = self.position();
+        let kind = match self.peek().clone() {
+            Token::Number(value) => {
+                self.advance();
+                ExpressionKind::Number(value)
+            },
+            Token::Identifier(name) => {
+                self.advance();
+                if self.eat("(") {
+                    let mut arguments = Vec::new();
+                    if !self.at(")") {
+                        loop {
+                            arguments.push(self.expression()?);
+                            if !self.eat(",") {
+                                break;
+                            }
+                        }
+                    }
+                    self.expect(")")?;
+                    ExpressionKind::Call(name, arguments)
+                } else if self.eat("[") {
+                    let index = self.expression()?;
+                    self.expect("]")?;
+                    ExpressionKind::Index(name, Box::new(index))
+                } else {
+                    ExpressionKind::Variable(name)
+                }
+            },
+            Token::Symbol("(") => {
+                self.advance();
+                let expression = self.expression()?;
+                self.expect(")")?;
+                return Ok(expression);
+            },
+            _ => return self.unexpected("an expression"),
+        };
+        Ok(Expression { kind, position })
+    }
+}
+
+fn parse(source: &str, allow_reserved: bool) -> Result<Vec<Item>, CompileError> {
+    Parser { tokens: tokenize(source)?, next: 0, allow_reserved }.program()
+}
+
+/// Division and remainder, which intcode has no instructions for, written in the language
+/// itself. They are only compiled into programs that use `/` or `%`.
+const RUNTIME: &str = "\
+fn __div(a, b) {
+    if (b == 0) {
+        abort();
+    }
+    // works with the operands negated rather than made positive, as i32::MIN has no positive
+    // counterpart
+    var negative = 0;
+    if (a > 0) {
+        a = -a;
+        negative = !negative;
+    }
+    if (b > 0) {
+        b = -b;
+        negative = !negative;
+    }
+    // long division, subtracting the largest power of two multiple of b that fits each time
+    var quotient = 0;
+    while (a <= b) {
+        var divisor = b;
+        var multiple = -1;
+        while (divisor >= a - divisor) {
+            divisor = divisor + divisor;
+            multiple = multiple + multiple;
+        }
+        a = a - divisor;
+        quotient = quotient + multiple;
+    }
+    if (negative) {
+        return quotient;
+    }
+    return -quotient;
+}
+
+fn __mod(a, b) {
+    return a - b * __div(a, b);
+}
+";
+
+/// Where a value lives while the generated code runs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Operand {
+    Constant(MemoryValue),
+    /// An offset from the relative base, into the current function's frame.
+    Local(MemoryValue),
+    /// A cell in the data area, which follows the code.
+    Data(usize),
+    /// The address of a cell in the data area, as an immediate value.
+    DataAddress(usize),
+    Absolute(Address),
+    Label(usize),
+    /// An offset from the end of the current function's frame, where a callee's frame starts.
+    Frame(MemoryValue),
+    /// The size of the current function's frame, multiplied by the given sign.
+    FrameSize(MemoryValue),
+    StackBase,
+}
+
+/// An operand word that can only be filled in once all the code has been generated.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Fixup {
+    Label(usize),
+    Data(usize),
+    Frame(usize, MemoryValue),
+    FrameSize(usize, MemoryValue),
+    StackBase,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Global {
+    Scalar(usize),
+    Array(usize, usize),
+}
+
+struct FunctionInfo {
+    index: usize,
+    label: usize,
+    parameters: usize,
+    runtime: bool,
+}
+
+/// The data area slot the return value of the last call is left in.
+const RETURN_VALUE: usize = 0;
+
+struct Generator {
+    words: Vec<MemoryValue>,
+    fixups: Vec<(Address, Fixup)>,
+    labels: Vec<Option<Address>>,
+    data: Vec<MemoryValue>,
+    globals: HashMap<String, Global>,
+    functions: HashMap<String, FunctionInfo>,
+    frame_sizes: Vec<MemoryValue>,
+    runtime_calls: BTreeSet<String>,
+    debug_info: DebugInfo,
+    file: usize,
+
+    // state for the function being generated
+    function: usize,
+    scopes: Vec<HashMap<String, MemoryValue>>,
+    /// The locals in scope, with their frame offset, the address they come into scope from and
+    /// the depth of the scope they belong to.
+    locals: Vec<(String, MemoryValue, Address, usize)>,
+    next_local: MemoryValue,
+    temporaries: MemoryValue,
+    loops: Vec<(usize, usize)>,
+}
+
+impl Generator {
+    fn emit(&mut self, opcode: Opcode, operands: &[Operand]) {
+        let address = self.words.len();
+        let mut parameters = Vec::new();
+        for (index, operand) in operands.iter().enumerate() {
+            let (parameter, fixup) = match *operand {
+                Operand::Constant(value) => (Parameter::Immediate(value), None),
+                Operand::Local(offset) => (Parameter::Relative(offset), None),
+                Operand::Data(slot) => (Parameter::Position(0), Some(Fixup::Data(slot))),
+                Operand::DataAddress(slot) => (Parameter::Immediate(0), Some(Fixup::Data(slot))),
+                Operand::Absolute(address) => (Parameter::Position(address), None),
+                Operand::Label(label) => (Parameter::Immediate(0), Some(Fixup::Label(label))),
+                Operand::Frame(offset) => (Parameter::Relative(0), Some(Fixup::Frame(self.function, offset))),
+                Operand::FrameSize(sign) => (Parameter::Immediate(0), Some(Fixup::FrameSize(self.function, sign))),
+                Operand::StackBase => (Parameter::Immediate(0), Some(Fixup::StackBase)),
+            };
+            if let Some(fixup) = fixup {
+                self.fixups.push((address + 1 + index, fixup));
+            }
+            parameters.push(parameter);
+        }
+        let words = Instruction::from_parts(opcode, &parameters).encode()
+            .expect("the code generator never writes to an immediate operand");
+        self.words.extend(words);
+    }
+
+    fn copy(&mut self, from: Operand, to: Operand) {
+        if from != to {
+            self.emit(Opcode::Add, &[from, Operand::Constant(0), to]);
+        }
+    }
+
+    fn jump(&mut self, label: usize) {
+        self.emit(Opcode::JumpIfTrue, &[Operand::Constant(1), Operand::Label(label)]);
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place_label(&mut self, label: usize) {
+        self.labels[label] = Some(self.words.len());
+    }
+
+    /// Records that the code from here on comes from `line`, replacing anything recorded for
+    /// the same address, like the line of a function whose first statement has no code before it.
+    fn mark_line(&mut self, line: usize) {
+        let address = self.words.len();
+        let lines = &mut self.debug_info.lines;
+        if lines.last().is_some_and(|entry| entry.address == address) {
+            lines.pop();
+        }
+        self.debug_info.add_line(address, self.file, line);
+    }
+
+    fn temporary(&mut self) -> Operand {
+        let offset = self.next_local + self.temporaries;
+        self.temporaries += 1;
+        self.grow_frame(offset + 1);
+        Operand::Local(offset)
+    }
+
+    fn grow_frame(&mut self, size: MemoryValue) {
+        let frame_size = &mut self.frame_sizes[self.function];
+        *frame_size = (*frame_size).max(size);
+    }
+
+    fn declare_local(&mut self, name: &str, position: Position) -> Result<MemoryValue, CompileError> {
+        let scope = self.scopes.last_mut().expect("locals are only declared inside functions");
+        if scope.contains_key(name) {
+            return Err(CompileError::new(CompileErrorKind::Redefinition(name.to_string()), position));
+        }
+        let offset = self.next_local;
+        scope.insert(name.to_string(), offset);
+        self.locals.push((name.to_string(), offset, self.words.len(), self.scopes.len()));
+        self.next_local += 1;
+        self.grow_frame(self.next_local);
+        Ok(offset)
+    }
+
+    fn scalar(&self, name: &str, position: Position) -> Result<Operand, CompileError> {
+        if let Some(offset) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Ok(Operand::Local(*offset));
+        }
+        match self.globals.get(name) {
+            Some(Global::Scalar(slot)) => Ok(Operand::Data(*slot)),
+            Some(Global::Array(..)) => Err(CompileError::new(CompileErrorKind::ArrayWithoutIndex(name.to_string()), position)),
+            None => Err(CompileError::new(CompileErrorKind::UndefinedVariable(name.to_string()), position)),
+        }
+    }
+
+    fn array(&self, name: &str, position: Position) -> Result<(usize, usize), CompileError> {
+        let is_local = self.scopes.iter().any(|scope| scope.contains_key(name));
+        match self.globals.get(name) {
+            Some(Global::Array(slot, length)) if !is_local => Ok((*slot, *length)),
+            Some(_) => Err(CompileError::new(CompileErrorKind::NotAnArray(name.to_string()), position)),
+            None if is_local => Err(CompileError::new(CompileErrorKind::NotAnArray(name.to_string()), position)),
+            None => Err(CompileError::new(CompileErrorKind::UndefinedVariable(name.to_string()), position)),
+        }
+    }
+
+    /// The operand an element of an array is at, for constant indices. Any other index needs the
+    /// address worked out at runtime.
+    fn constant_element(&self, name: &str, index: &Expression) -> Result<Option<Operand>, CompileError> {
+        let (slot, length) = self.array(name, index.position)?;
+        match index.constant_value() {
+            Some(value) if value < 0 || value as usize >= length => Err(CompileError::new(CompileErrorKind::IndexOutOfBounds {
+                name: name.to_string(),
+                index: value,
+                length,
+            }, index.position)),
+            Some(value) => Ok(Some(Operand::Data(slot + value as usize))),
+            None => Ok(None),
+        }
+    }
+
+    /// Emits an `add` that stores the address of an array element into operand `parameter` of
+    /// the instruction that follows it, which then reads or writes the element.
+    fn patch_element_address(&mut self, name: &str, index: Operand, position: Position, parameter: usize) -> Result<(), CompileError> {
+        let (slot, _) = self.array(name, position)?;
+        let target = self.words.len() + 4 + 1 + parameter;
+        self.emit(Opcode::Add, &[Operand::DataAddress(slot), index, Operand::Absolute(target)]);
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<Operand, CompileError> {
+        if let Some(value) = expression.constant_value() {
+            return Ok(Operand::Constant(value));
+        }
+        let position = expression.position;
+        match &expression.kind {
+            ExpressionKind::Number(value) => Ok(Operand::Constant(*value)),
+            ExpressionKind::Variable(name) => self.scalar(name, position),
+            ExpressionKind::Index(name, index) => {
+                if let Some(element) = self.constant_element(name, index)? {
+                    return Ok(element);
+                }
+                let mark = self.temporaries;
+                let index_operand = self.expression(index)?;
+                self.temporaries = mark;
+                let result = self.temporary();
+                self.patch_element_address(name, index_operand, position, 0)?;
+                self.emit(Opcode::Add, &[Operand::Absolute(0), Operand::Constant(0), result]);
+                Ok(result)
+            },
+            ExpressionKind::Call(name, arguments) => self.call(name, arguments, position),
+            ExpressionKind::Unary(operator, operand) => {
+                let mark = self.temporaries;
+                let operand = self.expression(operand)?;
+                self.temporaries = mark;
+                let result = self.temporary();
+                match operator {
+                    UnaryOperator::Negate => self.emit(Opcode::Multiply, &[operand, Operand::Constant(-1), result]),
+                    UnaryOperator::Not => self.emit(Opcode::Equal, &[operand, Operand::Constant(0), result]),
+                }
+                Ok(result)
+            },
+            ExpressionKind::Binary(BinaryOperator::Divide, left, right) => self.call("__div", &[(**left).clone(), (**right).clone()], position),
+            ExpressionKind::Binary(BinaryOperator::Remainder, left, right) => self.call("__mod", &[(**left).clone(), (**right).clone()], position),
+            ExpressionKind::Binary(operator @ BinaryOperator::And, left, right) |
+            ExpressionKind::Binary(operator @ BinaryOperator::Or, left, right) => self.logical(*operator, left, right),
+            ExpressionKind::Binary(operator, left, right) => {
+                let mark = self.temporaries;
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                self.temporaries = mark;
+                let result = self.temporary();
+                match operator {
+                    BinaryOperator::Add => self.emit(Opcode::Add, &[left, right, result]),
+                    BinaryOperator::Multiply => self.emit(Opcode::Multiply, &[left, right, result]),
+                    BinaryOperator::Subtract => {
+                        // the negated right hand side can't go in `result`, which may hold `left`
+                        let negated = self.temporary();
+                        self.emit(Opcode::Multiply, &[right, Operand::Constant(-1), negated]);
+                        self.emit(Opcode::Add, &[left, negated, result]);
+                        self.temporaries = mark + 1;
+                    },
+                    BinaryOperator::Less => self.emit(Opcode::LessThan, &[left, right, result]),
+                    BinaryOperator::Greater => self.emit(Opcode::LessThan, &[right, left, result]),
+                    BinaryOperator::Equal => self.emit(Opcode::Equal, &[left, right, result]),
+                    BinaryOperator::LessOrEqual | BinaryOperator::GreaterOrEqual | BinaryOperator::NotEqual => {
+                        let (opcode, a, b) = match operator {
+                            BinaryOperator::LessOrEqual => (Opcode::LessThan, right, left),
+                            BinaryOperator::GreaterOrEqual => (Opcode::LessThan, left, right),
+                            _ => (Opcode::Equal, left, right),
+                        };
+                        self.emit(opcode, &[a, b, result]);
+                        self.emit(Opcode::Equal, &[result, Operand::Constant(0), result]);
+                    },
+                    BinaryOperator::Divide | BinaryOperator::Remainder | BinaryOperator::And | BinaryOperator::Or => unreachable!("handled above"),
+                }
+                Ok(result)
+            },
+        }
+    }
+
+    /// `&&` and `||` only evaluate their right hand side if the left doesn't decide the result.
+    fn logical(&mut self, operator: BinaryOperator, left: &Expression, right: &Expression) -> Result<Operand, CompileError> {
+        let mark = self.temporaries;
+        let result = self.temporary();
+        let short_circuit = if operator == BinaryOperator::And { 0 } else { 1 };
+        self.copy(Operand::Constant(short_circuit), result);
+        let left = self.expression(left)?;
+        let end = self.new_label();
+        let opcode = if operator == BinaryOperator::And { Opcode::JumpIfFalse } else { Opcode::JumpIfTrue };
+        self.emit(opcode, &[left, Operand::Label(end)]);
+        self.temporaries = mark + 1;
+        let right = self.expression(right)?;
+        self.emit(Opcode::Equal, &[right, Operand::Constant(0), result]);
+        self.emit(Opcode::Equal, &[result, Operand::Constant(0), result]);
+        self.place_label(end);
+        self.temporaries = mark + 1;
+        Ok(result)
+    }
+
+    fn check_arguments(name: &str, expected: usize, arguments: &[Expression], position: Position) -> Result<(), CompileError> {
+        if arguments.len() == expected {
+            Ok(())
+        } else {
+            Err(CompileError::new(CompileErrorKind::WrongArgumentCount {
+                name: name.to_string(),
+                expected,
+                found: arguments.len(),
+            }, position))
+        }
+    }
+
+    fn call(&mut self, name: &str, arguments: &[Expression], position: Position) -> Result<Operand, CompileError> {
+        match name {
+            "input" => {
+                Generator::check_arguments(name, 0, arguments, position)?;
+                let result = self.temporary();
+                self.emit(Opcode::Input, &[result]);
+                return Ok(result);
+            },
+            "output" => {
+                Generator::check_arguments(name, 1, arguments, position)?;
+                let mark = self.temporaries;
+                let value = self.expression(&arguments[0])?;
+                self.temporaries = mark;
+                self.emit(Opcode::Output, &[value]);
+                return Ok(Operand::Constant(0));
+            },
+            "abort" => {
+                Generator::check_arguments(name, 0, arguments, position)?;
+                // 0 isn't an opcode, so this faults
+                self.words.push(0);
+                return Ok(Operand::Constant(0));
+            },
+            _ => {},
+        }
+
+        let (label, parameters, runtime) = match self.functions.get(name) {
+            Some(function) => (function.label, function.parameters, function.runtime),
+            None => return Err(CompileError::new(CompileErrorKind::UndefinedFunction(name.to_string()), position)),
+        };
+        Generator::check_arguments(name, parameters, arguments, position)?;
+        if runtime {
+            self.runtime_calls.insert(name.to_string());
+        }
+
+        // the callee's frame starts where this one ends: the return address, then the arguments
+        let mark = self.temporaries;
+        let mut values = Vec::new();
+        for argument in arguments.iter() {
+            values.push(self.expression(argument)?);
+        }
+        for (index, value) in values.into_iter().enumerate() {
+            self.copy(value, Operand::Frame(1 + index as MemoryValue));
+        }
+        let return_address = self.new_label();
+        self.copy(Operand::Label(return_address), Operand::Frame(0));
+        self.emit(Opcode::AdjustRelativeBase, &[Operand::FrameSize(1)]);
+        self.jump(label);
+        self.place_label(return_address);
+        self.emit(Opcode::AdjustRelativeBase, &[Operand::FrameSize(-1)]);
+        self.temporaries = mark;
+        let result = self.temporary();
+        self.copy(Operand::Data(RETURN_VALUE), result);
+        Ok(result)
+    }
+
+    fn condition(&mut self, condition: &Expression, otherwise: usize) -> Result<(), CompileError> {
+        let value = self.expression(condition)?;
+        self.emit(Opcode::JumpIfFalse, &[value, Operand::Label(otherwise)]);
+        self.temporaries = 0;
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        for statement in statements.iter() {
+            self.statement(statement)?;
+        }
+        self.close_scope();
+        Ok(())
+    }
+
+    /// Leaves the innermost scope, recording where each of its locals was visible. Their frame
+    /// slots aren't reused, but temporaries below them may have been in the meantime.
+    fn close_scope(&mut self) {
+        let depth = self.scopes.len();
+        self.scopes.pop();
+        let end = self.words.len();
+        while self.locals.last().is_some_and(|local| local.3 == depth) {
+            let (name, offset, start, _) = self.locals.pop().expect("checked there is a local");
+            self.debug_info.add_variable(name, Storage::Relative(offset), Some(start..end));
+        }
+    }
+
+    fn return_with(&mut self, value: Operand) {
+        self.copy(value, Operand::Data(RETURN_VALUE));
+        self.emit(Opcode::JumpIfTrue, &[Operand::Constant(1), Operand::Local(0)]);
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        let position = statement.position;
+        self.mark_line(position.line);
+        match &statement.kind {
+            StatementKind::Var(name, initializer) => {
+                let value = match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => Operand::Constant(0),
+                };
+                let offset = self.declare_local(name, position)?;
+                self.copy(value, Operand::Local(offset));
+            },
+            StatementKind::Assign(target, value) => match &target.kind {
+                ExpressionKind::Variable(name) => {
+                    let target = self.scalar(name, target.position)?;
+                    let value = self.expression(value)?;
+                    self.copy(value, target);
+                },
+                ExpressionKind::Index(name, index) => {
+                    let value = self.expression(value)?;
+                    match self.constant_element(name, index)? {
+                        Some(element) => self.copy(value, element),
+                        None => {
+                            let index_operand = self.expression(index)?;
+                            self.patch_element_address(name, index_operand, target.position, 2)?;
+                            self.emit(Opcode::Add, &[value, Operand::Constant(0), Operand::Absolute(0)]);
+                        },
+                    }
+                },
+                _ => unreachable!("the parser only allows variables and array elements to be assigned"),
+            },
+            StatementKind::If(condition, then, otherwise) => {
+                let else_label = self.new_label();
+                self.condition(condition, else_label)?;
+                self.block(then)?;
+                if otherwise.is_empty() {
+                    self.place_label(else_label);
+                } else {
+                    let end = self.new_label();
+                    self.jump(end);
+                    self.place_label(else_label);
+                    self.block(otherwise)?;
+                    self.place_label(end);
+                }
+            },
+            StatementKind::While(condition, body) => {
+                let (top, end) = (self.new_label(), self.new_label());
+                self.place_label(top);
+                self.condition(condition, end)?;
+                self.loops.push((top, end));
+                self.block(body)?;
+                self.loops.pop();
+                self.mark_line(position.line);
+                self.jump(top);
+                self.place_label(end);
+            },
+            StatementKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expression(value)?,
+                    None => Operand::Constant(0),
+                };
+                self.return_with(value);
+            },
+            StatementKind::Break | StatementKind::Continue => {
+                let keyword = if let StatementKind::Break = statement.kind { "break" } else { "continue" };
+                let (top, end) = *self.loops.last()
+                    .ok_or_else(|| CompileError::new(CompileErrorKind::OutsideLoop(keyword), position))?;
+                self.jump(if keyword == "break" { end } else { top });
+            },
+            StatementKind::Expression(expression) => {
+                self.expression(expression)?;
+            },
+        }
+        self.temporaries = 0;
+        Ok(())
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), CompileError> {
+        let info = &self.functions[&function.name];
+        let (index, label) = (info.index, info.label);
+        self.function = index;
+        self.scopes = vec![HashMap::new()];
+        self.locals = Vec::new();
+        self.next_local = 1;
+        self.temporaries = 0;
+        self.loops = Vec::new();
+
+        let start = self.words.len();
+        self.place_label(label);
+        self.debug_info.add_label(function.name.clone(), start);
+        self.mark_line(function.position.line);
+        for (name, position) in function.parameters.iter() {
+            self.declare_local(name, *position)?;
+        }
+        self.block(&function.body)?;
+        self.return_with(Operand::Constant(0));
+        self.close_scope();
+        Ok(())
+    }
+
+    fn declare_functions(&mut self, items: &[Item], runtime: bool) -> Result<(), CompileError> {
+        for item in items.iter() {
+            if let Item::Function(function) = item {
+                if self.functions.contains_key(&function.name) || ["input", "output", "abort"].contains(&function.name.as_str()) {
+                    return Err(CompileError::new(CompileErrorKind::Redefinition(function.name.clone()), function.position));
+                }
+                let label = self.new_label();
+                self.frame_sizes.push(1);
+                self.functions.insert(function.name.clone(), FunctionInfo {
+                    index: self.frame_sizes.len() - 1,
+                    label,
+                    parameters: function.parameters.len(),
+                    runtime,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_global(&mut self, name: &str, length: Option<MemoryValue>, initializer: Option<&Expression>, position: Position) -> Result<(), CompileError> {
+        if self.globals.contains_key(name) {
+            return Err(CompileError::new(CompileErrorKind::Redefinition(name.to_string()), position));
+        }
+        let value = match initializer {
+            Some(initializer) => initializer.constant_value()
+                .ok_or_else(|| CompileError::new(CompileErrorKind::NonConstantInitializer, initializer.position))?,
+            None => 0,
+        };
+        let slot = self.data.len();
+        match length {
+            Some(length) => {
+                self.data.extend(std::iter::repeat_n(value, length as usize));
+                self.globals.insert(name.to_string(), Global::Array(slot, length as usize));
+            },
+            None => {
+                self.data.push(value);
+                self.globals.insert(name.to_string(), Global::Scalar(slot));
+            },
+        }
+        Ok(())
+    }
+
+    /// Fills in every operand that refers to a label, the data area or a frame size, and
+    /// appends the data area.
+    fn finish(mut self, stack_size: usize) -> (Vec<MemoryValue>, DebugInfo) {
+        let code_end = self.words.len();
+        for (address, fixup) in self.fixups.iter() {
+            self.words[*address] = match *fixup {
+                Fixup::Label(label) => self.labels[label].expect("every label is placed") as MemoryValue,
+                Fixup::Data(slot) => (code_end + slot) as MemoryValue,
+                Fixup::Frame(function, offset) => self.frame_sizes[function] + offset,
+                Fixup::FrameSize(function, sign) => self.frame_sizes[function] * sign,
+                Fixup::StackBase => (code_end + self.data.len()) as MemoryValue,
+            };
+        }
+
+        let mut names = self.globals.iter().collect::<Vec<_>>();
+        names.sort_by_key(|(_, global)| match global {
+            Global::Scalar(slot) | Global::Array(slot, _) => *slot,
+        });
+        for (name, global) in names {
+            let slot = match global {
+                Global::Scalar(slot) | Global::Array(slot, _) => *slot,
+            };
+            self.debug_info.add_variable(name.clone(), Storage::Absolute(code_end + slot), None);
+        }
+        self.debug_info.add_variable("__return_value", Storage::Absolute(code_end + RETURN_VALUE), None);
+
+        let mut memory = self.words;
+        memory.extend(self.data);
+        memory.extend(std::iter::repeat_n(0, stack_size));
+        (memory, self.debug_info)
+    }
+}
+
+/// A compiled program, ready to run, along with debug info mapping it back to the source.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompiledProgram {
+    pub image: ProgramImage,
+    pub debug_info: DebugInfo,
+}
+
+impl CompiledProgram {
+    pub fn memory(&self) -> SimpleMemory {
+        SimpleMemory::from_image(&self.image)
+    }
+}
+
+/// Compiles a small structured language to intcode:
+///
+/// ```text
+/// var calls = 0;          // globals, initialised with constants
+/// var memo[30];           // arrays, which can only be global
+///
+/// fn fib(n) {
+///     calls = calls + 1;
+///     if (n < 2) {
+///         return n;
+///     }
+///     return fib(n - 1) + fib(n - 2);
+/// }
+///
+/// fn main() {
+///     var n = input();
+///     while (n > 0) {
+///         output(fib(n));
+///         n = n - 1;
+///     }
+/// }
+/// ```
+///
+/// Values are integers; `+ - * / %`, comparisons and `&& || !` work on them, with comparisons
+/// and logical operators giving 0 or 1. Statements are `var`, assignment, `if`/`else`, `while`,
+/// `break`, `continue` and `return`. `input()` reads a value, `output(x)` writes one and
+/// `abort()` stops the program with a fault. Running starts at `main` and halts when it returns.
+///
+/// Functions keep their return address, parameters, locals and temporaries in a frame on a
+/// stack addressed through the relative base, so they can recurse. Array elements with indices
+/// that aren't constant are reached with self-modifying code and aren't bounds checked at
+/// runtime; neither is stack overflow, which faults once it runs off the end of memory. The
+/// image marks the globals and stack as no-execute, so a stray jump into them faults as well.
+pub struct Compiler<'a> {
+    source: &'a str,
+    file_name: String,
+    stack_size: usize,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(source: &'a str) -> Compiler<'a> {
+        Compiler {
+            source,
+            file_name: "<source>".into(),
+            stack_size: 1024,
+        }
+    }
+
+    /// The name the source is given in debug info.
+    pub fn with_file_name<S: Into<String>>(mut self, file_name: S) -> Compiler<'a> {
+        self.file_name = file_name.into();
+        self
+    }
+
+    /// How many cells of memory to leave for the stack after the program and its globals.
+    pub fn with_stack_size(mut self, stack_size: usize) -> Compiler<'a> {
+        self.stack_size = stack_size;
+        self
+    }
+
+    pub fn compile(&self) -> Result<CompiledProgram, CompileError> {
+        let items = parse(self.source, false)?;
+        let runtime = parse(RUNTIME, true).expect("the runtime library parses");
+
+        let mut debug_info = DebugInfo::new();
+        let file = debug_info.add_file(self.file_name.clone());
+        let mut generator = Generator {
+            words: Vec::new(),
+            fixups: Vec::new(),
+            labels: Vec::new(),
+            data: vec![0],
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            frame_sizes: Vec::new(),
+            runtime_calls: BTreeSet::new(),
+            debug_info,
+            file,
+            function: 0,
+            scopes: Vec::new(),
+            locals: Vec::new(),
+            next_local: 0,
+            temporaries: 0,
+            loops: Vec::new(),
+        };
+        generator.declare_functions(&items, false)?;
+        generator.declare_functions(&runtime, true)?;
+        for item in items.iter() {
+            if let Item::Global { name, length, initializer, position } = item {
+                generator.declare_global(name, *length, initializer.as_ref(), *position)?;
+            }
+        }
+
+        let end_of_source = Position {
+            line: self.source.lines().count().max(1),
+            column: 1,
+        };
+        match items.iter().find_map(|item| match item {
+            Item::Function(function) if function.name == "main" => Some(function),
+            _ => None,
+        }) {
+            Some(main) if !main.parameters.is_empty() => return Err(CompileError::new(CompileErrorKind::MainWithParameters, main.position)),
+            Some(_) => {},
+            None => return Err(CompileError::new(CompileErrorKind::MissingMain, end_of_source)),
+        }
+
+        // set up the stack and call main, halting when it returns
+        generator.debug_info.add_label("__start", 0);
+        let (main_label, halt) = (generator.functions["main"].label, generator.new_label());
+        generator.emit(Opcode::AdjustRelativeBase, &[Operand::StackBase]);
+        generator.copy(Operand::Label(halt), Operand::Local(0));
+        generator.jump(main_label);
+        generator.place_label(halt);
+        generator.emit(Opcode::Halt, &[]);
+
+        for item in items.iter() {
+            if let Item::Function(function) = item {
+                generator.function(function)?;
+            }
+        }
+
+        // the runtime functions only go in if something calls them, which may be another one
+        generator.file = generator.debug_info.add_file("<runtime>");
+        let mut generated = BTreeSet::new();
+        while let Some(name) = generator.runtime_calls.iter().find(|name| !generated.contains(*name)).cloned() {
+            let function = runtime.iter()
+                .find_map(|item| match item {
+                    Item::Function(function) if function.name == name => Some(function),
+                    _ => None,
+                })
+                .expect("runtime calls are to runtime functions");
+            generator.function(function)?;
+            generated.insert(name);
+        }
+        let code_size = generator.words.len();
+        let (memory, debug_info) = generator.finish(self.stack_size);
+        let line_info = debug_info.lines.iter()
+            .filter(|entry| entry.file == file)
+            .map(|entry| LineInfo { address: entry.address, line: entry.line })
+            .collect();
+        let image = ProgramImage {
+            name: Some(self.file_name.clone()),
+            entry_point: 0,
+            symbols: debug_info.labels.clone(),
+            line_info: Some(line_info),
+            protection: ProtectionMap::new().with_region(code_size..memory.len(), Protection::NoExecute),
+            memory,
+        };
+        Ok(CompiledProgram { image, debug_info })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::intcode::{Computer, ComputerError, RecordedIO, TraceLevel};
+    use crate::intcode::compiler::{CompileError, CompileErrorKind, Compiler};
+
+    fn run(source: &str, inputs: Vec<i32>) -> Result<Vec<i32>, ComputerError> {
+        let program = Compiler::new(source).compile().unwrap_or_else(|error| panic!("{}", error.render(source)));
+        let mut memory = program.memory();
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_debug_info(Arc::new(program.debug_info.clone()))
+            .with_inputs(inputs);
+        computer.run_until_halted().map_err(|fault| fault.error)?;
+        Ok(computer.io_record.iter()
+            .filter_map(|event| match event {
+                RecordedIO::Output(value) => Some(*value),
+                RecordedIO::UserInput(_) => None,
+            })
+            .collect())
+    }
+
+    fn error(source: &str) -> CompileError {
+        Compiler::new(source).compile().unwrap_err()
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let source = "
+            fn main() {
+                var a = input();
+                var b = input();
+                output(a + b * 2 - (a - b));
+                output(-a);
+                output(a / b);
+                output(a % b);
+                output(-a / b);
+                output(a < b);
+                output(a >= b);
+                output(a == 7 && b != 0);
+                output(a == 0 || !b);
+                output(a * -b <= 3 * 4 - 100);
+            }
+        ";
+        assert_eq!(run(source, vec![7, 2]), Ok(vec![6, -7, 3, 1, -3, 0, 1, 1, 0, 0]));
+    }
+
+    #[test]
+    fn runs_loops_and_arrays() {
+        let source = "
+            var squares[10];
+            var count = 10;
+
+            fn main() {
+                var i = 0;
+                while (1) {
+                    if (i == count) {
+                        break;
+                    }
+                    squares[i] = i * i;
+                    i = i + 1;
+                }
+                var total = 0;
+                i = 0;
+                while (i < count) {
+                    i = i + 1;
+                    if (i % 2 == 0) {
+                        continue;
+                    }
+                    total = total + squares[i - 1];
+                }
+                output(total);
+                output(squares[9]);
+            }
+        ";
+        assert_eq!(run(source, vec![]), Ok(vec![4 + 16 + 36 + 64, 81]));
+    }
+
+    #[test]
+    fn supports_recursion() {
+        let source = "
+            fn fib(n) {
+                if (n < 2) {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            fn ackermann(m, n) {
+                if (m == 0) {
+                    return n + 1;
+                } else if (n == 0) {
+                    return ackermann(m - 1, 1);
+                }
+                return ackermann(m - 1, ackermann(m, n - 1));
+            }
+
+            fn main() {
+                output(fib(input()));
+                output(ackermann(2, 3));
+            }
+        ";
+        assert_eq!(run(source, vec![15]), Ok(vec![610, 9]));
+    }
+
+    #[test]
+    fn divides_the_most_negative_value() {
+        let source = "
+            fn main() {
+                var a = input();
+                var b = input();
+                output(a / b);
+                output(a % b);
+            }
+        ";
+        let cases = [(i32::MIN, 1), (i32::MIN, 2), (i32::MIN, -3), (i32::MIN, 7), (i32::MIN, i32::MIN),
+            (i32::MIN, i32::MAX), (i32::MAX, i32::MIN), (5, i32::MIN), (i32::MAX, -1), (-7, 2)];
+        for &(a, b) in cases.iter() {
+            assert_eq!(run(source, vec![a, b]), Ok(vec![a / b, a % b]), "{} / {}", a, b);
+        }
+        // the one quotient that doesn't fit wraps, like the rest of the arithmetic
+        assert_eq!(run(source, vec![i32::MIN, -1]), Ok(vec![i32::MIN, 0]));
+    }
+
+    #[test]
+    fn faults_on_division_by_zero() {
+        assert_eq!(run("fn main() { output(1 / input()); }", vec![0]), Err(ComputerError::UnknownOpcode(0)));
+    }
+
+    #[test]
+    fn only_includes_the_runtime_when_needed() {
+        let small = Compiler::new("fn main() { output(1); }").with_stack_size(0).compile().unwrap();
+        assert!(small.debug_info.labels.iter().all(|label| !label.name.starts_with("__d")));
+        let divides = Compiler::new("fn main() { output(input() % 3); }").compile().unwrap();
+        assert!(divides.debug_info.label_at(0).is_some());
+        assert!(divides.debug_info.labels.iter().any(|label| label.name == "__div"));
+    }
+
+    #[test]
+    fn produces_debug_info() {
+        let source = "var total;\nfn main() {\n    var x = input();\n    total = x + 1;\n}\n";
+        let program = Compiler::new(source).with_file_name("test.ic").compile().unwrap();
+        let info = &program.debug_info;
+        let main = info.labels.iter().find(|label| label.name == "main").unwrap().address;
+        assert_eq!(info.location(main).unwrap().to_string(), "test.ic:3");
+        assert!(info.variables.iter().any(|variable| variable.name == "total"));
+        assert_eq!(info.relative_variable(main + 2, 1).unwrap().name, "x");
+        assert_eq!(program.image.name.as_deref(), Some("test.ic"));
+        assert!(program.image.line_info.as_ref().unwrap().iter().any(|entry| entry.line == 4));
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert_eq!(error("fn main() {\n    var x = 1\n}"), CompileError {
+            kind: CompileErrorKind::Unexpected { expected: "';'".into(), found: "'}'".into() },
+            line: 3,
+            column: 1,
+        });
+        assert_eq!(error("fn main() { output(1 $ 2); }").kind, CompileErrorKind::UnexpectedCharacter('$'));
+        assert_eq!(error("fn main() { output(99999999999); }").kind, CompileErrorKind::NumberTooLarge("99999999999".into()));
+        assert_eq!(error("fn main() { 1 + 2 = 3; }").kind, CompileErrorKind::Unexpected {
+            expected: "a variable to assign to".into(),
+            found: "an expression".into(),
+        });
+        assert_eq!(error("fn main() { var __x; }").kind, CompileErrorKind::ReservedName("__x".into()));
+        assert_eq!(error("fn main() { var a[3]; }").kind, CompileErrorKind::LocalArray);
+        assert_eq!(error("var a[0];").kind, CompileErrorKind::InvalidArrayLength);
+    }
+
+    #[test]
+    fn reports_semantic_errors() {
+        assert_eq!(error("fn main() { output(y); }").kind, CompileErrorKind::UndefinedVariable("y".into()));
+        assert_eq!(error("fn main() { f(); }").kind, CompileErrorKind::UndefinedFunction("f".into()));
+        assert_eq!(error("fn f(a) {} fn main() { f(); }").kind, CompileErrorKind::WrongArgumentCount { name: "f".into(), expected: 1, found: 0 });
+        assert_eq!(error("fn main() { var x; var x; }").kind, CompileErrorKind::Redefinition("x".into()));
+        assert_eq!(error("fn output(x) {} fn main() {}").kind, CompileErrorKind::Redefinition("output".into()));
+        assert_eq!(error("var a[2]; fn main() { output(a); }").kind, CompileErrorKind::ArrayWithoutIndex("a".into()));
+        assert_eq!(error("var a; fn main() { output(a[0]); }").kind, CompileErrorKind::NotAnArray("a".into()));
+        assert_eq!(error("var a[2]; fn main() { a[2] = 1; }").kind, CompileErrorKind::IndexOutOfBounds { name: "a".into(), index: 2, length: 2 });
+        assert_eq!(error("var a = input(); fn main() {}").kind, CompileErrorKind::NonConstantInitializer);
+        assert_eq!(error("fn main() { break; }").kind, CompileErrorKind::OutsideLoop("break"));
+        assert_eq!(error("fn start() {}").kind, CompileErrorKind::MissingMain);
+        assert_eq!(error("fn main(x) {}").kind, CompileErrorKind::MainWithParameters);
+    }
+
+    #[test]
+    fn renders_errors_with_source() {
+        let source = "fn main() {\n\toutput(y);\n}";
+        assert_eq!(error(source).render(source), [
+            "error: 'y' is not defined",
+            " --> line 2, column 9",
+            "  |",
+            "2 | \toutput(y);",
+            "  | \t       ^",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,459 @@
+use failure::{Fail, ResultExt};
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use super::{Address, Instruction, MemoryValue, Parameter, ProgramImage};
+use super::disassemble::DisassemblyLine;
+use super::image::{LineInfo, Symbol};
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum DebugInfoError {
+    #[fail(display = "line {}: unknown directive '{}'", _0, _1)]
+    UnknownDirective(usize, String),
+    #[fail(display = "line {}: malformed {} entry", _0, _1)]
+    Malformed(usize, &'static str),
+    #[fail(display = "line {}: file {} has not been declared", _0, _1)]
+    UnknownFile(usize, usize),
+}
+
+/// The instructions from `address` up to the next entry were produced from `line` (1-based) of
+/// `files[file]`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LineEntry {
+    pub address: Address,
+    pub file: usize,
+    pub line: usize,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SourceLocation<'a> {
+    pub file: &'a str,
+    pub line: usize,
+}
+
+impl<'a> fmt::Display for SourceLocation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Where a variable lives: a fixed address, or an offset from the relative base, as locals in
+/// a stack frame are.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Storage {
+    Absolute(Address),
+    Relative(MemoryValue),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub storage: Storage,
+    /// The code the variable is visible from, or `None` if it is visible everywhere.
+    pub scope: Option<Range<Address>>,
+}
+
+impl Variable {
+    fn in_scope(&self, address: Address) -> bool {
+        self.scope.as_ref().is_none_or(|scope| scope.contains(&address))
+    }
+}
+
+/// Debug info for a program produced from source: which file and line each address came from,
+/// labels, and the names of variables. It is kept in a text file next to the program, named after
+/// it with `.dbg` appended, one entry per line:
+///
+/// ```text
+/// file 0 countdown.ic
+/// line 0 0 3
+/// label 0 main
+/// var counter 20
+/// var i rb+1 4..18
+/// ```
+///
+/// `line` entries give an address, file index and line number, and `var` entries an address or
+/// relative base offset, optionally followed by the range of code the variable is visible from.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub lines: Vec<LineEntry>,
+    pub labels: Vec<Symbol>,
+    pub variables: Vec<Variable>,
+}
+
+impl DebugInfo {
+    pub fn new() -> DebugInfo {
+        DebugInfo::default()
+    }
+
+    /// The symbols and line info of an image, attributed to a file named after the image.
+    pub fn from_image(image: &ProgramImage) -> DebugInfo {
+        let lines = image.line_info.iter()
+            .flatten()
+            .map(|info: &LineInfo| LineEntry { address: info.address, file: 0, line: info.line })
+            .collect();
+        DebugInfo {
+            files: vec![image.name.clone().unwrap_or_else(|| "<image>".into())],
+            lines,
+            labels: image.symbols.clone(),
+            variables: Vec::new(),
+        }
+    }
+
+    /// The path debug info for the program at `program` is kept at.
+    pub fn path_for<P: AsRef<Path>>(program: P) -> PathBuf {
+        let mut path = program.as_ref().as_os_str().to_owned();
+        path.push(".dbg");
+        PathBuf::from(path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DebugInfo, Box<dyn Error>> {
+        Ok(std::fs::read_to_string(path)?.parse::<DebugInfo>().compat()?)
+    }
+
+    /// Loads the debug info kept next to `program`, falling back to the symbols and line info of
+    /// an image. Returns `None` if the program has neither.
+    pub fn load_for<P: AsRef<Path>>(program: P) -> Result<Option<DebugInfo>, Box<dyn Error>> {
+        let path = DebugInfo::path_for(&program);
+        if path.exists() {
+            return Ok(Some(DebugInfo::load(path)?));
+        }
+        let bytes = std::fs::read(program)?;
+        if ProgramImage::is_image(&bytes) {
+            let image = ProgramImage::decode(&bytes).compat()?;
+            if !image.symbols.is_empty() || image.line_info.is_some() {
+                return Ok(Some(DebugInfo::from_image(&image)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Adds a source file, returning its index for line entries.
+    pub fn add_file<S: Into<String>>(&mut self, name: S) -> usize {
+        self.files.push(name.into());
+        self.files.len() - 1
+    }
+
+    pub fn add_line(&mut self, address: Address, file: usize, line: usize) {
+        self.lines.push(LineEntry { address, file, line });
+    }
+
+    pub fn add_label<S: Into<String>>(&mut self, name: S, address: Address) {
+        self.labels.push(Symbol { name: name.into(), address });
+    }
+
+    pub fn add_variable<S: Into<String>>(&mut self, name: S, storage: Storage, scope: Option<Range<Address>>) {
+        self.variables.push(Variable { name: name.into(), storage, scope });
+    }
+
+    /// The source line the code at `address` was produced from.
+    pub fn location(&self, address: Address) -> Option<SourceLocation<'_>> {
+        let entry = self.lines.iter()
+            .filter(|entry| entry.address <= address)
+            .max_by_key(|entry| entry.address)?;
+        Some(SourceLocation {
+            file: self.files.get(entry.file).map_or("?", |file| file.as_str()),
+            line: entry.line,
+        })
+    }
+
+    pub fn label_at(&self, address: Address) -> Option<&str> {
+        self.labels.iter()
+            .find(|label| label.address == address)
+            .map(|label| label.name.as_str())
+    }
+
+    /// Names `address` after the closest label at or before it, like `loop+3`.
+    pub fn symbolize(&self, address: Address) -> Option<String> {
+        let label = self.labels.iter()
+            .filter(|label| label.address <= address)
+            .max_by_key(|label| label.address)?;
+        Some(match address - label.address {
+            0 => label.name.clone(),
+            offset => format!("{}+{}", label.name, offset),
+        })
+    }
+
+    /// The label and source line of `address`, as far as they are known.
+    pub fn describe(&self, address: Address) -> Option<String> {
+        let parts = self.symbolize(address).into_iter()
+            .chain(self.location(address).map(|location| location.to_string()))
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(", "))
+        }
+    }
+
+    /// The variable stored at a fixed address.
+    pub fn variable_at(&self, address: Address) -> Option<&Variable> {
+        self.variables.iter().find(|variable| variable.storage == Storage::Absolute(address))
+    }
+
+    /// The variable at `offset` from the relative base, as seen from code at `address`.
+    pub fn relative_variable(&self, address: Address, offset: MemoryValue) -> Option<&Variable> {
+        self.variables.iter().find(|variable| variable.storage == Storage::Relative(offset) && variable.in_scope(address))
+    }
+
+    fn format_parameter(&self, address: Address, parameter: Parameter, jump_target: bool) -> String {
+        let name = match parameter {
+            Parameter::Position(target) => self.variable_at(target)
+                .map(|variable| variable.name.as_str())
+                .or_else(|| self.label_at(target)),
+            Parameter::Relative(offset) => self.relative_variable(address, offset).map(|variable| variable.name.as_str()),
+            Parameter::Immediate(target) if jump_target && target >= 0 => self.label_at(target as Address),
+            Parameter::Immediate(_) => None,
+        };
+        match (name, parameter) {
+            (Some(name), Parameter::Immediate(_)) => name.to_string(),
+            (Some(name), _) => format!("[{}]", name),
+            (None, _) => parameter.to_string(),
+        }
+    }
+
+    /// Formats `instruction` at `address` with variable names in place of the addresses they
+    /// live at and labels in place of constant jump targets.
+    pub fn format_instruction(&self, address: Address, instruction: &Instruction) -> String {
+        let is_jump = matches!(instruction, Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..));
+        let mut text = instruction.mnemonic().to_string();
+        for (index, parameter) in instruction.parameters().into_iter().enumerate() {
+            text += if index == 0 { " " } else { ", " };
+            text += &self.format_parameter(address, parameter, is_jump && index == 1);
+        }
+        text
+    }
+
+    /// A line of disassembly with symbolic operands and the source line it came from.
+    pub fn format_line(&self, line: &DisassemblyLine) -> String {
+        let text = match &line.instruction {
+            Ok(instruction) => {
+                let words = line.words.iter().map(|word| word.to_string()).collect::<Vec<_>>().join(",");
+                format!("{:>6}: {:<24} {}", line.address, words, self.format_instruction(line.address, instruction))
+            },
+            Err(_) => line.to_string(),
+        };
+        match self.location(line.address) {
+            Some(location) => format!("{:<56} ; {}", text, location),
+            None => text,
+        }
+    }
+
+    /// A disassembly listing with each label on its own line ahead of the code it names.
+    pub fn listing(&self, lines: &[DisassemblyLine]) -> String {
+        let mut listing = Vec::new();
+        for line in lines.iter() {
+            for label in self.labels.iter().filter(|label| (line.address..line.address + line.length()).contains(&label.address)) {
+                listing.push(format!("{}:", label.name));
+            }
+            listing.push(self.format_line(line));
+        }
+        listing.join("\n")
+    }
+}
+
+fn parse_storage(text: &str) -> Option<Storage> {
+    if let Some(offset) = text.strip_prefix("rb") {
+        let offset = offset.strip_prefix('+').unwrap_or(offset);
+        offset.parse().ok().map(Storage::Relative)
+    } else {
+        text.parse().ok().map(Storage::Absolute)
+    }
+}
+
+fn parse_scope(text: &str) -> Option<Range<Address>> {
+    let mut bounds = text.splitn(2, "..");
+    let start = bounds.next()?.parse().ok()?;
+    let end = bounds.next()?.parse().ok()?;
+    Some(start..end)
+}
+
+impl std::str::FromStr for DebugInfo {
+    type Err = DebugInfoError;
+
+    fn from_str(source: &str) -> Result<DebugInfo, DebugInfoError> {
+        let mut info = DebugInfo::new();
+        for (index, text) in source.lines().enumerate() {
+            let number = index + 1;
+            let text = text.split('#').next().unwrap_or("").trim();
+            let mut fields = text.split_whitespace();
+            let directive = match fields.next() {
+                Some(directive) => directive,
+                None => continue,
+            };
+            let fields = fields.collect::<Vec<_>>();
+            match directive {
+                "file" => match fields.as_slice() {
+                    [index, _, ..] if index.parse() == Ok(info.files.len()) => {
+                        // file names may contain spaces, so take everything after the index
+                        let name = text["file".len()..].trim_start()[index.len()..].trim();
+                        info.add_file(name);
+                    },
+                    _ => return Err(DebugInfoError::Malformed(number, "file")),
+                },
+                "line" => {
+                    let values = fields.iter().map(|field| field.parse::<usize>()).collect::<Result<Vec<_>, _>>();
+                    match values.as_ref().map(|values| values.as_slice()) {
+                        Ok(&[_, file, _]) if file >= info.files.len() => return Err(DebugInfoError::UnknownFile(number, file)),
+                        Ok(&[address, file, line]) => info.add_line(address, file, line),
+                        _ => return Err(DebugInfoError::Malformed(number, "line")),
+                    }
+                },
+                "label" => match fields.as_slice() {
+                    [address, name] => {
+                        let address = address.parse().map_err(|_| DebugInfoError::Malformed(number, "label"))?;
+                        info.add_label(*name, address);
+                    },
+                    _ => return Err(DebugInfoError::Malformed(number, "label")),
+                },
+                "var" => {
+                    let (name, storage, scope) = match fields.as_slice() {
+                        [name, storage] => (name, parse_storage(storage), Some(None)),
+                        [name, storage, scope] => (name, parse_storage(storage), parse_scope(scope).map(Some)),
+                        _ => (&"", None, None),
+                    };
+                    match (storage, scope) {
+                        (Some(storage), Some(scope)) => info.add_variable(*name, storage, scope),
+                        _ => return Err(DebugInfoError::Malformed(number, "var")),
+                    }
+                },
+                other => return Err(DebugInfoError::UnknownDirective(number, other.to_string())),
+            }
+        }
+        Ok(info)
+    }
+}
+
+impl fmt::Display for DebugInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# intcode debug info")?;
+        for (index, file) in self.files.iter().enumerate() {
+            writeln!(f, "file {} {}", index, file)?;
+        }
+        for entry in self.lines.iter() {
+            writeln!(f, "line {} {} {}", entry.address, entry.file, entry.line)?;
+        }
+        for label in self.labels.iter() {
+            writeln!(f, "label {} {}", label.address, label.name)?;
+        }
+        for variable in self.variables.iter() {
+            write!(f, "var {} ", variable.name)?;
+            match variable.storage {
+                Storage::Absolute(address) => write!(f, "{}", address)?,
+                Storage::Relative(offset) if offset < 0 => write!(f, "rb{}", offset)?,
+                Storage::Relative(offset) => write!(f, "rb+{}", offset)?,
+            }
+            match &variable.scope {
+                Some(scope) => writeln!(f, " {}..{}", scope.start, scope.end)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::intcode::{disassemble, Computer, ComputerError, ProgramImage, SimpleMemory, TraceLevel};
+    use crate::intcode::debug_info::{DebugInfo, DebugInfoError, Storage};
+    use crate::intcode::image::{LineInfo, Symbol};
+
+    // countdown.ic:
+    //   1: var counter = 3;
+    //   2: while (counter) {
+    //   3:     output(counter);
+    //   4:     counter = counter + -1;
+    //   5: }
+    const PROGRAM: [i32; 15] = [1006, 14, 13, 4, 14, 1001, 14, -1, 14, 1105, 1, 0, 0, 99, 3];
+
+    fn example_info() -> DebugInfo {
+        let mut info = DebugInfo::new();
+        let file = info.add_file("countdown.ic");
+        info.add_line(0, file, 2);
+        info.add_line(3, file, 3);
+        info.add_line(5, file, 4);
+        info.add_line(9, file, 2);
+        info.add_line(13, file, 5);
+        info.add_label("loop", 0);
+        info.add_label("done", 13);
+        info.add_variable("counter", Storage::Absolute(14), None);
+        info.add_variable("local", Storage::Relative(-2), Some(0..13));
+        info
+    }
+
+    #[test]
+    fn looks_up_locations_and_symbols() {
+        let info = example_info();
+        assert_eq!(info.location(4).unwrap().to_string(), "countdown.ic:3");
+        assert_eq!(info.location(10).unwrap().line, 2);
+        assert_eq!(info.symbolize(7), Some("loop+7".to_string()));
+        assert_eq!(info.describe(13), Some("done, countdown.ic:5".to_string()));
+        assert_eq!(DebugInfo::new().describe(13), None);
+        assert_eq!(info.variable_at(14).unwrap().name, "counter");
+        assert_eq!(info.relative_variable(5, -2).unwrap().name, "local");
+        assert!(info.relative_variable(13, -2).is_none());
+    }
+
+    #[test]
+    fn formats_symbolic_disassembly() {
+        let info = example_info();
+        let memory = SimpleMemory::from_literal(&PROGRAM);
+        let listing = info.listing(&disassemble(&memory, 0, 5));
+        assert_eq!(listing.lines().collect::<Vec<_>>(), vec![
+            "loop:",
+            "     0: 1006,14,13               jf [counter], done      ; countdown.ic:2",
+            "     3: 4,14                     out [counter]           ; countdown.ic:3",
+            "     5: 1001,14,-1,14            add [counter], -1, [counter] ; countdown.ic:4",
+            "     9: 1105,1,0                 jt 1, loop              ; countdown.ic:2",
+            "    12: 0                        data 0                  ; countdown.ic:2",
+        ]);
+    }
+
+    #[test]
+    fn text_format_round_trips() {
+        let info = example_info();
+        assert_eq!(info.to_string().parse::<DebugInfo>(), Ok(info));
+
+        let spaced = "file 0 my program.ic\nline 4 0 1 # comment\n".parse::<DebugInfo>().unwrap();
+        assert_eq!(spaced.location(5).unwrap().to_string(), "my program.ic:1");
+
+        assert_eq!("line 0 1 1".parse::<DebugInfo>(), Err(DebugInfoError::UnknownFile(1, 1)));
+        assert_eq!("\nvar x rb+y".parse::<DebugInfo>(), Err(DebugInfoError::Malformed(2, "var")));
+        assert_eq!("func main".parse::<DebugInfo>(), Err(DebugInfoError::UnknownDirective(1, "func".into())));
+    }
+
+    #[test]
+    fn converts_image_metadata() {
+        let mut image = ProgramImage::new(PROGRAM.to_vec());
+        image.name = Some("countdown".into());
+        image.symbols = vec![Symbol { name: "loop".into(), address: 0 }];
+        image.line_info = Some(vec![LineInfo { address: 3, line: 3 }]);
+        let info = DebugInfo::from_image(&image);
+        assert_eq!(info.describe(4), Some("loop+4, countdown:3".to_string()));
+    }
+
+    #[test]
+    fn faults_show_source() {
+        // out [50] reads past the end of memory
+        let mut program = PROGRAM.to_vec();
+        program[4] = 50;
+        let mut memory = SimpleMemory::from_literal(&program);
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_debug_info(Arc::new(example_info()));
+        let fault = computer.run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::MemoryOperationOutOfBounds(50));
+        let text = fault.display_with(computer.debug_info()).to_string();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "attempted to interact with memory with an invalid address (50) at address 3 (loop+3, countdown.ic:3) (cycle 1) while executing `out [50]`");
+        assert_eq!(lines[1], "         0: 1006,14,13               jf [counter], done      ; countdown.ic:2");
+        assert_eq!(lines[2], "  >      3: 4,50                     out [50]                ; countdown.ic:3");
+    }
+}
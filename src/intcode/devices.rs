@@ -0,0 +1,387 @@
+use failure::Fail;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::time::Instant;
+use super::{Address, ComputerError, Memory, MemoryValue};
+
+/// Something that can be attached to a range of addresses in a [`MappedMemory`]. Addresses are
+/// given relative to the start of the range the device is mapped into. Reads take `&mut self` as
+/// many devices (consoles, random number generators) change state when read.
+pub trait Device {
+    fn read(&mut self, offset: Address) -> Result<MemoryValue, ComputerError>;
+    fn write(&mut self, offset: Address, value: MemoryValue) -> Result<(), ComputerError>;
+
+    /// What a read would return, without changing the device's state, or `None` if that can't be
+    /// known without reading (a console can't tell what its next byte of input will be).
+    fn peek(&self, _offset: Address) -> Option<MemoryValue> {
+        None
+    }
+}
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum DeviceMapError {
+    #[fail(display = "cannot map a device into an empty address range")]
+    EmptyRange,
+    #[fail(display = "address range overlaps a device that is already mapped")]
+    OverlapsExistingDevice,
+}
+
+struct MappedDevice {
+    range: Range<Address>,
+    device: RefCell<Box<dyn Device>>,
+}
+
+/// Memory that routes accesses within mapped ranges to devices, and everything else to a
+/// backing store.
+///
+/// Reading a device can change it, so anything that only inspects memory (fault reports,
+/// disassembly, debuggers, analysis) must use [`Memory::peek_slot`] rather than
+/// [`Memory::read_slot`], or it will consume console input and advance generators the running
+/// program expected to see. Peeking a device answers with [`Device::peek`], failing with
+/// [`ComputerError::DeviceCannotBePeeked`] for devices that can't tell.
+pub struct MappedMemory<M: Memory> {
+    backing: M,
+    devices: Vec<MappedDevice>,
+}
+
+impl<M: Memory> MappedMemory<M> {
+    pub fn new(backing: M) -> MappedMemory<M> {
+        MappedMemory {
+            backing,
+            devices: Vec::new(),
+        }
+    }
+
+    pub fn map_device<D: Device + 'static>(&mut self, range: Range<Address>, device: D) -> Result<(), DeviceMapError> {
+        if range.start >= range.end {
+            return Err(DeviceMapError::EmptyRange);
+        }
+        if self.devices.iter().any(|mapped| mapped.range.start < range.end && range.start < mapped.range.end) {
+            return Err(DeviceMapError::OverlapsExistingDevice);
+        }
+        self.devices.push(MappedDevice {
+            range,
+            device: RefCell::new(Box::new(device)),
+        });
+        Ok(())
+    }
+
+    pub fn backing(&self) -> &M {
+        &self.backing
+    }
+
+    pub fn backing_mut(&mut self) -> &mut M {
+        &mut self.backing
+    }
+
+    pub fn into_backing(self) -> M {
+        self.backing
+    }
+
+    fn device_for(&self, slot: Address) -> Option<&MappedDevice> {
+        self.devices.iter().find(|mapped| mapped.range.contains(&slot))
+    }
+}
+
+impl<M: Memory> Memory for MappedMemory<M> {
+    fn read_slot(&self, slot: Address) -> Result<MemoryValue, ComputerError> {
+        match self.device_for(slot) {
+            Some(mapped) => mapped.device.borrow_mut().read(slot - mapped.range.start),
+            None => self.backing.read_slot(slot),
+        }
+    }
+
+    fn write_slot(&mut self, slot: Address, value: MemoryValue) -> Result<(), ComputerError> {
+        match self.device_for(slot) {
+            Some(mapped) => mapped.device.borrow_mut().write(slot - mapped.range.start, value),
+            None => self.backing.write_slot(slot, value),
+        }
+    }
+
+    fn read_stream_from<'a>(&'a self, slot: Address) -> Result<Box<dyn Iterator<Item=MemoryValue> + 'a>, ComputerError> {
+        // the first slot is read up front to report it being out of bounds, and must not be read
+        // twice; the rest are read lazily, so only the slots actually decoded touch their devices
+        let first = self.read_slot(slot)?;
+        Ok(Box::new(std::iter::once(first)
+            .chain((slot + 1..).map_while(move |address| self.read_slot(address).ok()))))
+    }
+
+    fn peek_slot(&self, slot: Address) -> Result<MemoryValue, ComputerError> {
+        match self.device_for(slot) {
+            Some(mapped) => mapped.device.borrow().peek(slot - mapped.range.start)
+                .ok_or(ComputerError::DeviceCannotBePeeked(slot)),
+            None => self.backing.peek_slot(slot),
+        }
+    }
+
+    fn is_device(&self, slot: Address) -> bool {
+        self.device_for(slot).is_some()
+    }
+
+    fn peek_stream_from<'a>(&'a self, slot: Address) -> Result<Box<dyn Iterator<Item=MemoryValue> + 'a>, ComputerError> {
+        self.peek_slot(slot)?;
+        Ok(Box::new((slot..).map_while(move |address| self.peek_slot(address).ok())))
+    }
+}
+
+/// A character console occupying a single address: writing sends a character to the output and
+/// reading takes the next byte of input, or -1 once the input is exhausted.
+pub struct Console<R: Read, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: Read, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Console<R, W> {
+        Console {
+            input,
+            output,
+        }
+    }
+}
+
+impl<R: Read, W: Write> Device for Console<R, W> {
+    fn read(&mut self, _offset: Address) -> Result<MemoryValue, ComputerError> {
+        let mut byte = [0u8];
+        match self.input.read(&mut byte).map_err(|_| ComputerError::FailedToGetInput)? {
+            0 => Ok(-1),
+            _ => Ok(byte[0] as MemoryValue),
+        }
+    }
+
+    fn write(&mut self, _offset: Address, value: MemoryValue) -> Result<(), ComputerError> {
+        let character = std::char::from_u32(value as u32).unwrap_or(std::char::REPLACEMENT_CHARACTER);
+        write!(self.output, "{}", character)
+            .and_then(|_| self.output.flush())
+            .map_err(|_| ComputerError::DeviceFault)
+    }
+}
+
+/// Produces a pseudo-random non-negative value on every read. Writing a value reseeds it.
+pub struct RandomNumberGenerator {
+    state: u64,
+}
+
+impl RandomNumberGenerator {
+    pub fn new(seed: u64) -> RandomNumberGenerator {
+        let mut result = RandomNumberGenerator { state: 0 };
+        result.reseed(seed);
+        result
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        // xorshift gets stuck on a zero state
+        self.state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        if self.state == 0 {
+            self.state = 1;
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = RandomNumberGenerator::successor(self.state);
+        self.state
+    }
+
+    fn successor(mut state: u64) -> u64 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    }
+}
+
+impl Device for RandomNumberGenerator {
+    fn read(&mut self, _offset: Address) -> Result<MemoryValue, ComputerError> {
+        Ok((self.next() >> 33) as MemoryValue)
+    }
+
+    fn write(&mut self, _offset: Address, value: MemoryValue) -> Result<(), ComputerError> {
+        self.reseed(value as u64);
+        Ok(())
+    }
+
+    fn peek(&self, _offset: Address) -> Option<MemoryValue> {
+        Some((RandomNumberGenerator::successor(self.state) >> 33) as MemoryValue)
+    }
+}
+
+/// Reads as the number of milliseconds since the clock was created or last written to.
+pub struct MillisecondClock {
+    epoch: Instant,
+}
+
+impl MillisecondClock {
+    pub fn new() -> MillisecondClock {
+        MillisecondClock {
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl MillisecondClock {
+    fn elapsed(&self) -> MemoryValue {
+        (self.epoch.elapsed().as_millis() % (MemoryValue::MAX as u128 + 1)) as MemoryValue
+    }
+}
+
+impl Default for MillisecondClock {
+    fn default() -> Self {
+        MillisecondClock::new()
+    }
+}
+
+impl Device for MillisecondClock {
+    fn read(&mut self, _offset: Address) -> Result<MemoryValue, ComputerError> {
+        Ok(self.elapsed())
+    }
+
+    fn write(&mut self, _offset: Address, _value: MemoryValue) -> Result<(), ComputerError> {
+        self.epoch = Instant::now();
+        Ok(())
+    }
+
+    fn peek(&self, _offset: Address) -> Option<MemoryValue> {
+        Some(self.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, ComputerError, Memory, MemoryValue, SimpleMemory};
+    use crate::intcode::devices::{Console, Device, DeviceMapError, MappedMemory, MillisecondClock, RandomNumberGenerator};
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Register(MemoryValue);
+
+    impl Device for Register {
+        fn read(&mut self, offset: usize) -> Result<MemoryValue, ComputerError> {
+            Ok(self.0 + offset as MemoryValue)
+        }
+
+        fn write(&mut self, _offset: usize, value: MemoryValue) -> Result<(), ComputerError> {
+            self.0 = value;
+            Ok(())
+        }
+
+        fn peek(&self, offset: usize) -> Option<MemoryValue> {
+            Some(self.0 + offset as MemoryValue)
+        }
+    }
+
+    #[test]
+    fn routes_mapped_addresses_to_devices() {
+        let mut memory = MappedMemory::new(SimpleMemory::from_literal(&[1, 2, 3, 4]));
+        memory.map_device(100..110, Register(50)).unwrap();
+
+        assert_eq!(memory.read_slot(1), Ok(2));
+        assert_eq!(memory.read_slot(100), Ok(50));
+        assert_eq!(memory.read_slot(103), Ok(53));
+        assert_eq!(memory.read_slot(110), Err(ComputerError::MemoryOperationOutOfBounds(110)));
+
+        memory.write_slot(105, 7).unwrap();
+        assert_eq!(memory.read_slot(100), Ok(7));
+        assert_eq!(memory.backing().read_slot(0), Ok(1));
+    }
+
+    #[test]
+    fn rejects_overlapping_devices() {
+        let mut memory = MappedMemory::new(SimpleMemory::from_literal(&[]));
+        memory.map_device(10..20, Register(0)).unwrap();
+        assert_eq!(memory.map_device(15..25, Register(0)), Err(DeviceMapError::OverlapsExistingDevice));
+        assert_eq!(memory.map_device(5..5, Register(0)), Err(DeviceMapError::EmptyRange));
+        assert_eq!(memory.map_device(20..25, Register(0)), Ok(()));
+    }
+
+    #[test]
+    fn programs_can_talk_to_the_console() {
+        let output = SharedBuffer::default();
+        let mut memory = MappedMemory::new(SimpleMemory::from_literal(&[
+            // echo one character of input, then print '!'
+            1001, 1000, 0, 1000,
+            1101, 0, 33, 1000,
+            99,
+        ]));
+        memory.map_device(1000..1001, Console::new("hi".as_bytes(), output.clone())).unwrap();
+
+        let mut computer = Computer::new(&mut memory);
+        computer.run_until_halted().unwrap();
+        assert_eq!(output.0.borrow().as_slice(), b"h!");
+    }
+
+    #[test]
+    fn peeking_leaves_devices_alone() {
+        let mut memory = MappedMemory::new(SimpleMemory::from_literal(&[1, 2]));
+        memory.map_device(100..110, Register(50)).unwrap();
+        memory.map_device(200..201, Console::new("hi".as_bytes(), Vec::new())).unwrap();
+        memory.map_device(300..301, RandomNumberGenerator::new(7)).unwrap();
+
+        assert_eq!(memory.peek_slot(1), Ok(2));
+        assert_eq!(memory.peek_slot(104), Ok(54));
+        assert_eq!(memory.peek_slot(200), Err(ComputerError::DeviceCannotBePeeked(200)));
+        assert_eq!(memory.read_slot(200), Ok('h' as MemoryValue));
+        let next = memory.peek_slot(300).unwrap();
+        assert_eq!(memory.peek_slot(300), Ok(next));
+        assert_eq!(memory.read_slot(300), Ok(next));
+        assert_ne!(memory.peek_slot(300), Ok(next));
+        assert_eq!(memory.peek_stream_from(0).unwrap().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn streams_read_each_device_slot_once() {
+        let mut memory = MappedMemory::new(SimpleMemory::from_literal(&[]));
+        memory.map_device(0..1, Console::new("abc".as_bytes(), Vec::new())).unwrap();
+        let mut stream = memory.read_stream_from(0).unwrap();
+        assert_eq!(stream.next(), Some('a' as MemoryValue));
+        assert_eq!(stream.next(), None);
+        drop(stream);
+        assert_eq!(memory.read_slot(0), Ok('b' as MemoryValue));
+    }
+
+    #[test]
+    fn faults_do_not_consume_device_input() {
+        // jumps to an unknown opcode just ahead of the console, which the fault report disassembles
+        let mut memory = MappedMemory::new(SimpleMemory::from_literal(&[1105, 1, 3, 98]));
+        memory.map_device(4..5, Console::new("c".as_bytes(), Vec::new())).unwrap();
+
+        let fault = Computer::new(&mut memory).run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::UnknownOpcode(98));
+        assert_eq!(memory.read_slot(4), Ok('c' as MemoryValue));
+    }
+
+    #[test]
+    fn random_numbers_are_deterministic_per_seed() {
+        let mut a = RandomNumberGenerator::new(42);
+        let mut b = RandomNumberGenerator::new(0);
+        b.write(0, 42).unwrap();
+        let from_a = (0..10).map(|_| a.read(0).unwrap()).collect::<Vec<_>>();
+        let from_b = (0..10).map(|_| b.read(0).unwrap()).collect::<Vec<_>>();
+        assert_eq!(from_a, from_b);
+        assert!(from_a.iter().all(|value| *value >= 0));
+        assert!(from_a.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn clock_counts_up_from_zero() {
+        let mut clock = MillisecondClock::new();
+        let first = clock.read(0).unwrap();
+        assert!(first >= 0);
+        assert!(clock.read(0).unwrap() >= first);
+    }
+}
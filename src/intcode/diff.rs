@@ -0,0 +1,177 @@
+use std::fmt;
+use std::ops::Range;
+use super::{Address, Memory, MemoryValue, SimpleMemory};
+use super::disassemble::{disassemble, DisassemblyLine};
+
+/// A contiguous run of cells that differ between two memories. A cell only one memory has is
+/// `None` on the other side.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChangedRange {
+    pub start: Address,
+    pub before: Vec<Option<MemoryValue>>,
+    pub after: Vec<Option<MemoryValue>>,
+    /// The instructions overlapping the range, as each memory disassembles from address 0.
+    pub before_disassembly: Vec<DisassemblyLine>,
+    pub after_disassembly: Vec<DisassemblyLine>,
+}
+
+impl ChangedRange {
+    pub fn addresses(&self) -> Range<Address> {
+        self.start..self.start + self.before.len()
+    }
+}
+
+fn format_cell(value: Option<MemoryValue>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+impl fmt::Display for ChangedRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let addresses = self.addresses();
+        write!(f, "@@ {}..{} ({} cell{}) @@", addresses.start, addresses.end, addresses.len(), if addresses.len() == 1 { "" } else { "s" })?;
+        for (offset, (before, after)) in self.before.iter().zip(self.after.iter()).enumerate() {
+            write!(f, "\n{:>8}: {} -> {}", self.start + offset, format_cell(*before), format_cell(*after))?;
+        }
+        for line in self.before_disassembly.iter() {
+            write!(f, "\n- {}", line)?;
+        }
+        for line in self.after_disassembly.iter() {
+            write!(f, "\n+ {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every difference between two memories.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MemoryDiff {
+    pub ranges: Vec<ChangedRange>,
+}
+
+impl MemoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of cells that differ.
+    pub fn changed_cells(&self) -> usize {
+        self.ranges.iter().map(|range| range.before.len()).sum()
+    }
+
+    pub fn is_changed(&self, address: Address) -> bool {
+        self.ranges.iter().any(|range| range.addresses().contains(&address))
+    }
+}
+
+impl fmt::Display for MemoryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} changed cell(s) in {} range(s)", self.changed_cells(), self.ranges.len())?;
+        for range in self.ranges.iter() {
+            write!(f, "\n{}", range)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_all<M: Memory + ?Sized>(memory: &M) -> Vec<MemoryValue> {
+    memory.peek_stream_from(0).map(|stream| stream.collect()).unwrap_or_default()
+}
+
+fn overlapping(lines: &[DisassemblyLine], addresses: &Range<Address>) -> Vec<DisassemblyLine> {
+    lines.iter()
+        .filter(|line| line.address < addresses.end && addresses.start < line.address + line.length())
+        .cloned()
+        .collect()
+}
+
+/// Compares two memories cell by cell, such as a program before and after it ran or the final
+/// memories of two runs with different inputs. Disassembly is linear from address 0, so data
+/// mixed in with code can throw the instruction boundaries off.
+pub fn diff_memory<A: Memory + ?Sized, B: Memory + ?Sized>(before: &A, after: &B) -> MemoryDiff {
+    let before = SimpleMemory::from_literal(&read_all(before));
+    let after = SimpleMemory::from_literal(&read_all(after));
+    let (before_cells, after_cells) = (before.as_slice(), after.as_slice());
+    let before_lines = disassemble(&before, 0, usize::MAX);
+    let after_lines = disassemble(&after, 0, usize::MAX);
+
+    let mut diff = MemoryDiff::default();
+    let mut current: Option<ChangedRange> = None;
+    for address in 0..before_cells.len().max(after_cells.len()) {
+        let (old, new) = (before_cells.get(address).cloned(), after_cells.get(address).cloned());
+        if old == new {
+            diff.ranges.extend(current.take());
+            continue;
+        }
+        let range = current.get_or_insert_with(|| ChangedRange {
+            start: address,
+            before: Vec::new(),
+            after: Vec::new(),
+            before_disassembly: Vec::new(),
+            after_disassembly: Vec::new(),
+        });
+        range.before.push(old);
+        range.after.push(new);
+    }
+    diff.ranges.extend(current);
+
+    for range in diff.ranges.iter_mut() {
+        range.before_disassembly = overlapping(&before_lines, &range.addresses());
+        range.after_disassembly = overlapping(&after_lines, &range.addresses());
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, SimpleMemory, TraceLevel};
+    use crate::intcode::diff::diff_memory;
+
+    #[test]
+    fn groups_changes_into_ranges() {
+        let before = SimpleMemory::from_literal(&[1, 2, 3, 4, 5, 6]);
+        let after = SimpleMemory::from_literal(&[1, 9, 9, 4, 5, 7, 8]);
+        let diff = diff_memory(&before, &after);
+        let ranges = diff.ranges.iter().map(|range| range.addresses()).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![1..3, 5..7]);
+        assert_eq!(diff.ranges[1].before, vec![Some(6), None]);
+        assert_eq!(diff.ranges[1].after, vec![Some(7), Some(8)]);
+        assert_eq!(diff.changed_cells(), 4);
+        assert!(diff.is_changed(2) && !diff.is_changed(3));
+        assert!(diff_memory(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn shows_affected_instructions() {
+        // add 1, 1, [5] rewrites the first operand of the instruction after it
+        let program = [1101, 1, 1, 5, 1101, 99, 0, 9, 99, 0];
+        let before = SimpleMemory::from_literal(&program);
+        let mut after = SimpleMemory::from_literal(&program);
+        Computer::new(&mut after).with_trace_level(TraceLevel::Silent).run_until_halted().unwrap();
+
+        let diff = diff_memory(&before, &after);
+        let ranges = diff.ranges.iter().map(|range| range.addresses()).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![5..6, 9..10]);
+        let range = &diff.ranges[0];
+        assert_eq!(range.before_disassembly.iter().map(|line| line.address).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(range.after_disassembly[0].instruction.as_ref().unwrap().to_string(), "add 2, 0, [9]");
+        assert_eq!(diff.to_string().lines().take(3).collect::<Vec<_>>(), vec![
+            "2 changed cell(s) in 2 range(s)",
+            "@@ 5..6 (1 cell) @@",
+            "       5: 99 -> 2",
+        ]);
+    }
+
+    #[test]
+    fn spots_day5_self_modification() {
+        let before = SimpleMemory::from_memory_file("input/day5.txt").unwrap();
+        let mut after = SimpleMemory::from_memory_file("input/day5.txt").unwrap();
+        Computer::new(&mut after)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![1])
+            .run_until_halted()
+            .unwrap();
+        let diff = diff_memory(&before, &after);
+        assert!(diff.is_changed(6));
+        assert!(diff.is_changed(225));
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+use super::{Address, ComputerError, Instruction, Memory, MemoryValue, Opcode, OpcodeRegistry, Parameter};
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Parameter::Position(address) => write!(f, "[{}]", address),
+            Parameter::Immediate(value) => write!(f, "{}", value),
+            Parameter::Relative(offset) if *offset < 0 => write!(f, "[rb{}]", offset),
+            Parameter::Relative(offset) => write!(f, "[rb+{}]", offset),
+        }
+    }
+}
+
+impl Opcode {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Multiply => "mul",
+            Opcode::Input => "in",
+            Opcode::Output => "out",
+            Opcode::JumpIfTrue => "jt",
+            Opcode::JumpIfFalse => "jf",
+            Opcode::LessThan => "lt",
+            Opcode::Equal => "eq",
+            Opcode::AdjustRelativeBase => "arb",
+            Opcode::Halt => "hlt",
+        }
+    }
+}
+
+impl Instruction {
+    pub fn mnemonic(&self) -> &str {
+        match (self, self.opcode()) {
+            (Instruction::Custom(spec, _), _) => &spec.mnemonic,
+            (_, opcode) => opcode.expect("only custom instructions lack an opcode").mnemonic(),
+        }
+    }
+
+    pub fn parameters(&self) -> Vec<Parameter> {
+        match self {
+            Instruction::Add(a, b, c) |
+            Instruction::Multiply(a, b, c) |
+            Instruction::LessThan(a, b, c) |
+            Instruction::Equal(a, b, c) => vec![*a, *b, *c],
+            Instruction::JumpIfTrue(a, b) |
+            Instruction::JumpIfFalse(a, b) => vec![*a, *b],
+            Instruction::Input(a) |
+            Instruction::Output(a) |
+            Instruction::AdjustRelativeBase(a) => vec![*a],
+            Instruction::Halt => vec![],
+            Instruction::Custom(_, parameters) => parameters.clone(),
+        }
+    }
+
+    /// The number of memory slots the instruction occupies, including its header.
+    pub fn length(&self) -> Address {
+        1 + self.parameters().len()
+    }
+}
+
+/// Position parameters are shown as `[address]`, relative parameters as `[rb+offset]` and
+/// immediate parameters as bare values, so `1001,4,3,4` disassembles to `add [4], 3, [4]`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        for (index, parameter) in self.parameters().iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, parameter)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single line of disassembly: the raw words at an address and what they decode to. Words that
+/// do not decode are shown as data, one word per line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DisassemblyLine {
+    pub address: Address,
+    pub words: Vec<MemoryValue>,
+    pub instruction: Result<Instruction, ComputerError>,
+}
+
+impl DisassemblyLine {
+    pub fn decode_at<M: Memory + ?Sized>(memory: &M, address: Address) -> Option<DisassemblyLine> {
+        DisassemblyLine::decode_at_with(&OpcodeRegistry::builtin(), memory, address)
+    }
+
+    pub fn decode_at_with<M: Memory + ?Sized>(registry: &OpcodeRegistry, memory: &M, address: Address) -> Option<DisassemblyLine> {
+        let first_word = memory.peek_slot(address).ok()?;
+        let instruction = memory.peek_stream_from(address)
+            .and_then(|mut stream| registry.decode(&mut stream));
+        let length = match &instruction {
+            Ok(instruction) => instruction.length(),
+            Err(_) => 1,
+        };
+        let words = match memory.peek_stream_from(address) {
+            Ok(stream) => stream.take(length).collect(),
+            Err(_) => vec![first_word],
+        };
+        Some(DisassemblyLine {
+            address,
+            words,
+            instruction,
+        })
+    }
+
+    pub fn length(&self) -> Address {
+        self.words.len()
+    }
+}
+
+impl fmt::Display for DisassemblyLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = self.words.iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        match &self.instruction {
+            Ok(instruction) => write!(f, "{:>6}: {:<24} {}", self.address, words, instruction),
+            Err(_) => write!(f, "{:>6}: {:<24} data {}", self.address, words, words),
+        }
+    }
+}
+
+/// Linearly disassembles up to `count` instructions starting at `address`, stopping early at the
+/// end of memory.
+pub fn disassemble<M: Memory + ?Sized>(memory: &M, address: Address, count: usize) -> Vec<DisassemblyLine> {
+    disassemble_with(&OpcodeRegistry::builtin(), memory, address, count)
+}
+
+/// As [`disassemble`], but recognising any custom opcodes in `registry`.
+pub fn disassemble_with<M: Memory + ?Sized>(registry: &OpcodeRegistry, memory: &M, address: Address, count: usize) -> Vec<DisassemblyLine> {
+    let mut lines = Vec::new();
+    let mut address = address;
+    while lines.len() < count {
+        match DisassemblyLine::decode_at_with(registry, memory, address) {
+            Some(line) => {
+                address += line.length();
+                lines.push(line);
+            },
+            None => break,
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Instruction, Parameter, SimpleMemory};
+    use crate::intcode::disassemble::disassemble;
+
+    #[test]
+    fn can_format_instructions() {
+        assert_eq!(Instruction::Add(
+            Parameter::Position(4),
+            Parameter::Immediate(-3),
+            Parameter::Position(4),
+        ).to_string(), "add [4], -3, [4]");
+        assert_eq!(Instruction::Output(Parameter::Immediate(7)).to_string(), "out 7");
+        assert_eq!(Instruction::Input(Parameter::Relative(-2)).to_string(), "in [rb-2]");
+        assert_eq!(Instruction::AdjustRelativeBase(Parameter::Relative(3)).to_string(), "arb [rb+3]");
+        assert_eq!(Instruction::Halt.to_string(), "hlt");
+    }
+
+    #[test]
+    fn can_disassemble_memory() {
+        let memory = SimpleMemory::from_literal(&[1001, 4, 3, 4, 104, 7, 42, 99]);
+        let listing = disassemble(&memory, 0, 10)
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(listing, vec![
+            "     0: 1001,4,3,4               add [4], 3, [4]",
+            "     4: 104,7                    out 7",
+            "     6: 42                       data 42",
+            "     7: 99                       hlt",
+        ]);
+    }
+}
@@ -0,0 +1,257 @@
+use failure::Fail;
+use super::{Address, Instruction, MemoryValue, Opcode, Parameter, ParameterMode};
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum EncodeError {
+    #[fail(display = "parameter {} of opcode {} is a write destination and cannot be immediate mode", parameter, opcode)]
+    ImmediateWriteDestination { opcode: MemoryValue, parameter: usize },
+    #[fail(display = "address {} does not fit in a memory slot", _0)]
+    AddressOutOfRange(Address),
+}
+
+impl Opcode {
+    pub fn encode(&self) -> MemoryValue {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Multiply => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equal => 8,
+            Opcode::AdjustRelativeBase => 9,
+            Opcode::Halt => 99,
+        }
+    }
+}
+
+impl ParameterMode {
+    pub fn encode(&self) -> MemoryValue {
+        match self {
+            ParameterMode::Position => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        }
+    }
+}
+
+impl Parameter {
+    pub fn mode(&self) -> ParameterMode {
+        match self {
+            Parameter::Position(_) => ParameterMode::Position,
+            Parameter::Immediate(_) => ParameterMode::Immediate,
+            Parameter::Relative(_) => ParameterMode::Relative,
+        }
+    }
+
+    pub fn encode(&self) -> Result<MemoryValue, EncodeError> {
+        match *self {
+            Parameter::Position(address) if address > MemoryValue::MAX as Address => Err(EncodeError::AddressOutOfRange(address)),
+            Parameter::Position(address) => Ok(address as MemoryValue),
+            Parameter::Immediate(value) | Parameter::Relative(value) => Ok(value),
+        }
+    }
+}
+
+impl Instruction {
+    /// The built-in opcode of the instruction, or `None` for custom instructions.
+    pub fn opcode(&self) -> Option<Opcode> {
+        Some(match self {
+            Instruction::Add(..) => Opcode::Add,
+            Instruction::Multiply(..) => Opcode::Multiply,
+            Instruction::Input(..) => Opcode::Input,
+            Instruction::Output(..) => Opcode::Output,
+            Instruction::JumpIfTrue(..) => Opcode::JumpIfTrue,
+            Instruction::JumpIfFalse(..) => Opcode::JumpIfFalse,
+            Instruction::LessThan(..) => Opcode::LessThan,
+            Instruction::Equal(..) => Opcode::Equal,
+            Instruction::AdjustRelativeBase(..) => Opcode::AdjustRelativeBase,
+            Instruction::Halt => Opcode::Halt,
+            Instruction::Custom(..) => return None,
+        })
+    }
+
+    pub fn opcode_number(&self) -> MemoryValue {
+        match (self, self.opcode()) {
+            (_, Some(opcode)) => opcode.encode(),
+            (Instruction::Custom(spec, _), None) => spec.number,
+            _ => unreachable!("only custom instructions lack a built-in opcode"),
+        }
+    }
+
+    pub fn write_parameters(&self) -> &[usize] {
+        match (self, self.opcode()) {
+            (_, Some(opcode)) => opcode.write_parameters(),
+            (Instruction::Custom(spec, _), None) => &spec.write_parameters,
+            _ => unreachable!("only custom instructions lack a built-in opcode"),
+        }
+    }
+
+    /// Encodes the instruction as its header word followed by its operands. The header is
+    /// canonical: parameter modes past the last non-position parameter are left implicit.
+    pub fn encode(&self) -> Result<Vec<MemoryValue>, EncodeError> {
+        let opcode = self.opcode_number();
+        let parameters = self.parameters();
+
+        for index in self.write_parameters() {
+            if parameters[*index].mode() == ParameterMode::Immediate {
+                return Err(EncodeError::ImmediateWriteDestination { opcode, parameter: *index });
+            }
+        }
+
+        let mut header = opcode;
+        let mut place = 100;
+        for parameter in parameters.iter() {
+            header += parameter.mode().encode() * place;
+            place *= 10;
+        }
+
+        let mut words = vec![header];
+        for parameter in parameters.iter() {
+            words.push(parameter.encode()?);
+        }
+        Ok(words)
+    }
+}
+
+/// Encodes a sequence of instructions, laid out one after another from address 0.
+pub fn encode_program(instructions: &[Instruction]) -> Result<Vec<MemoryValue>, EncodeError> {
+    let mut words = Vec::new();
+    for instruction in instructions {
+        words.extend(instruction.encode()?);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Instruction, InstructionHeader, MemoryValue, Opcode, Parameter, SimpleMemory, Computer, ComputerError, Memory};
+    use crate::intcode::encode::{encode_program, EncodeError};
+    use proptest::prelude::*;
+
+    const OPCODES: [Opcode; 10] = [
+        Opcode::Add,
+        Opcode::Multiply,
+        Opcode::Input,
+        Opcode::Output,
+        Opcode::JumpIfTrue,
+        Opcode::JumpIfFalse,
+        Opcode::LessThan,
+        Opcode::Equal,
+        Opcode::AdjustRelativeBase,
+        Opcode::Halt,
+    ];
+
+    fn decode(words: &[MemoryValue]) -> Instruction {
+        Instruction::decode(&mut words.iter().cloned()).expect("failed to decode encoded instruction")
+    }
+
+    fn parameter() -> impl Strategy<Value=Parameter> {
+        prop_oneof![
+            (0..=MemoryValue::MAX).prop_map(|address| Parameter::Position(address as usize)),
+            any::<MemoryValue>().prop_map(Parameter::Immediate),
+            any::<MemoryValue>().prop_map(Parameter::Relative),
+        ]
+    }
+
+    fn encodable_instruction() -> impl Strategy<Value=Instruction> {
+        (prop::sample::select(&OPCODES[..]), prop::collection::vec(parameter(), 3))
+            .prop_map(|(opcode, mut parameters)| {
+                for index in opcode.write_parameters() {
+                    if let Parameter::Immediate(value) = parameters[*index] {
+                        parameters[*index] = Parameter::Position(value.unsigned_abs() as usize);
+                    }
+                }
+                Instruction::from_parts(opcode, &parameters)
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn decode_inverts_encode(instruction in encodable_instruction()) {
+            let words = instruction.encode().unwrap();
+            prop_assert_eq!(words.len(), instruction.length());
+            prop_assert_eq!(decode(&words), instruction);
+        }
+
+        #[test]
+        fn encode_inverts_decode(header in 0..100_000, operands in prop::collection::vec(any::<MemoryValue>(), 3)) {
+            let mut words = vec![header];
+            words.extend(operands);
+            if let Ok(instruction) = Instruction::decode(&mut words.iter().cloned()) {
+                if let Ok(encoded) = instruction.encode() {
+                    // only canonical headers, with no mode digits beyond the last parameter, survive unchanged
+                    let canonical = header < 100 * 10i32.pow(instruction.parameters().len() as u32);
+                    if canonical {
+                        prop_assert_eq!(&encoded[..], &words[..instruction.length()]);
+                    }
+                    prop_assert_eq!(decode(&encoded), instruction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_every_opcode_and_mode_combination() {
+        for opcode in OPCODES.iter() {
+            let count = opcode.parameter_count();
+            for modes in 0..3usize.pow(count as u32) {
+                let mode_of = |index: usize| modes / 3usize.pow(index as u32) % 3;
+                let parameters = (0..count)
+                    .map(|index| match mode_of(index) {
+                        0 => Parameter::Position(index + 7),
+                        1 => Parameter::Immediate(-(index as MemoryValue) - 5),
+                        _ => Parameter::Relative(index as MemoryValue - 1),
+                    })
+                    .collect::<Vec<_>>();
+                let instruction = Instruction::from_parts(*opcode, &parameters);
+                let writes_immediate = opcode.write_parameters().iter().any(|index| mode_of(*index) == 1);
+
+                match instruction.encode() {
+                    Ok(words) => {
+                        assert!(!writes_immediate);
+                        assert_eq!(InstructionHeader::decode(words[0]).map(|header| header.opcode), Ok(*opcode));
+                        assert_eq!(decode(&words), instruction);
+                    },
+                    Err(EncodeError::ImmediateWriteDestination { .. }) => assert!(writes_immediate),
+                    Err(error) => panic!("unexpected encode error: {}", error),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unencodable_instructions() {
+        assert_eq!(
+            Instruction::Input(Parameter::Immediate(4)).encode(),
+            Err(EncodeError::ImmediateWriteDestination { opcode: 3, parameter: 0 })
+        );
+        assert_eq!(
+            Instruction::Output(Parameter::Position(usize::MAX)).encode(),
+            Err(EncodeError::AddressOutOfRange(usize::MAX))
+        );
+    }
+
+    #[test]
+    fn negative_positions_do_not_decode() {
+        assert_eq!(Instruction::decode(&mut vec![4, -1].into_iter()), Err(ComputerError::NegativeAddress(-1)));
+        assert_eq!(decode(&[204, -1]), Instruction::Output(Parameter::Relative(-1)));
+    }
+
+    #[test]
+    fn encoded_programs_run() {
+        let program = encode_program(&[
+            Instruction::Add(Parameter::Immediate(20), Parameter::Immediate(22), Parameter::Position(9)),
+            Instruction::Output(Parameter::Position(9)),
+            Instruction::Halt,
+        ]).unwrap();
+        assert_eq!(program, vec![1101, 20, 22, 9, 4, 9, 99]);
+
+        let mut padded = program.clone();
+        padded.extend(&[0, 0, 0]);
+        let mut memory = SimpleMemory::from_literal(&padded);
+        Computer::new(&mut memory).run_until_halted().unwrap();
+        assert_eq!(memory.read_slot(9), Ok(42));
+    }
+}
@@ -0,0 +1,110 @@
+use failure::Fail;
+use std::fmt;
+use super::{Address, ComputerError, Instruction, MemoryValue};
+use super::debug_info::DebugInfo;
+use super::disassemble::DisassemblyLine;
+
+/// A [`ComputerError`] raised while stepping a [`Computer`](super::Computer), along with the
+/// state of the machine when it happened.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Fault {
+    pub error: ComputerError,
+    pub instruction_pointer: Address,
+    pub cycle: usize,
+    /// The raw word at the instruction pointer, if it could be read.
+    pub word: Option<MemoryValue>,
+    /// The instruction being executed, if it got as far as being decoded.
+    pub instruction: Option<Instruction>,
+    /// Disassembly of the most recently executed instructions, the faulting one and those after.
+    pub window: Vec<DisassemblyLine>,
+}
+
+impl Fault {
+    /// Displays the fault with the label and source line it happened at, and symbolic operands
+    /// in the disassembly, when there is debug info for the program.
+    pub fn display_with<'a>(&'a self, debug_info: Option<&'a DebugInfo>) -> FaultDisplay<'a> {
+        FaultDisplay { fault: self, debug_info }
+    }
+}
+
+impl Fail for Fault {
+    fn cause(&self) -> Option<&dyn Fail> {
+        Some(&self.error)
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display_with(None))
+    }
+}
+
+pub struct FaultDisplay<'a> {
+    fault: &'a Fault,
+    debug_info: Option<&'a DebugInfo>,
+}
+
+impl<'a> fmt::Display for FaultDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (fault, debug_info) = (self.fault, self.debug_info);
+        write!(f, "{} at address {}", fault.error, fault.instruction_pointer)?;
+        if let Some(description) = debug_info.and_then(|info| info.describe(fault.instruction_pointer)) {
+            write!(f, " ({})", description)?;
+        }
+        write!(f, " (cycle {})", fault.cycle)?;
+        match (&fault.instruction, debug_info) {
+            (Some(instruction), Some(info)) => write!(f, " while executing `{}`", info.format_instruction(fault.instruction_pointer, instruction))?,
+            (Some(instruction), None) => write!(f, " while executing `{}`", instruction)?,
+            (None, _) => {},
+        }
+        for line in fault.window.iter() {
+            let marker = if line.address == fault.instruction_pointer { ">" } else { " " };
+            match debug_info {
+                Some(info) => write!(f, "\n  {} {}", marker, info.format_line(line))?,
+                None => write!(f, "\n  {} {}", marker, line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, ComputerError, Instruction, Parameter, SimpleMemory};
+
+    #[test]
+    fn faults_carry_context() {
+        let mut memory = SimpleMemory::from_literal(&[1101, 1, 2, 9, 1, 9, 50, 0, 99, 0]);
+        let mut computer = Computer::new(&mut memory);
+        let fault = computer.run_until_halted().unwrap_err();
+
+        assert_eq!(fault.error, ComputerError::MemoryOperationOutOfBounds(50));
+        assert_eq!(fault.instruction_pointer, 4);
+        assert_eq!(fault.cycle, 1);
+        assert_eq!(fault.word, Some(1));
+        assert_eq!(fault.instruction, Some(Instruction::Add(
+            Parameter::Position(9),
+            Parameter::Position(50),
+            Parameter::Position(0),
+        )));
+        assert_eq!(fault.window.iter().map(|line| line.address).collect::<Vec<_>>(), vec![0, 4, 8, 9]);
+    }
+
+    #[test]
+    fn faults_report_undecodable_words() {
+        let mut memory = SimpleMemory::from_literal(&[1101, 1, 2, 5, 1142, 0]);
+        let mut computer = Computer::new(&mut memory);
+        let fault = computer.run_until_halted().unwrap_err();
+
+        assert_eq!(fault.error, ComputerError::UnknownOpcode(1142));
+        assert_eq!(fault.instruction_pointer, 4);
+        assert_eq!(fault.word, Some(1142));
+        assert_eq!(fault.instruction, None);
+        assert_eq!(fault.to_string(), [
+            "unknown opcode in word 1142 at address 4 (cycle 1)",
+            "         0: 1101,1,2,5               add 1, 2, [5]",
+            "  >      4: 1142                     data 1142",
+            "         5: 3                        data 3",
+        ].join("\n"));
+    }
+}
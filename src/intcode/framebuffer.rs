@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use super::{Computer, ComputerError, Fault, Memory, MemoryValue, RecordedIO};
+
+type Position = (MemoryValue, MemoryValue);
+
+/// How tile ids are drawn, as characters on a terminal and as colours in images.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    characters: HashMap<MemoryValue, char>,
+    colours: HashMap<MemoryValue, [u8; 3]>,
+}
+
+const UNKNOWN_CHARACTER: char = '?';
+const UNKNOWN_COLOUR: [u8; 3] = [255, 0, 255];
+
+impl Palette {
+    pub fn empty() -> Palette {
+        Palette {
+            characters: HashMap::new(),
+            colours: HashMap::new(),
+        }
+    }
+
+    pub fn with_tile(mut self, tile: MemoryValue, character: char, colour: [u8; 3]) -> Palette {
+        self.characters.insert(tile, character);
+        self.colours.insert(tile, colour);
+        self
+    }
+
+    pub fn character(&self, tile: MemoryValue) -> char {
+        self.characters.get(&tile).cloned().unwrap_or(UNKNOWN_CHARACTER)
+    }
+
+    pub fn colour(&self, tile: MemoryValue) -> [u8; 3] {
+        self.colours.get(&tile).cloned().unwrap_or(UNKNOWN_COLOUR)
+    }
+}
+
+/// The usual arcade tiles: empty, wall, block, paddle and ball.
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::empty()
+            .with_tile(0, ' ', [0, 0, 0])
+            .with_tile(1, '#', [128, 128, 128])
+            .with_tile(2, '=', [64, 96, 224])
+            .with_tile(3, '-', [255, 255, 255])
+            .with_tile(4, 'o', [224, 48, 48])
+    }
+}
+
+/// A sparse screen drawn by a program outputting `x, y, tile` triples. A triple addressed to the
+/// score position sets the score instead of drawing a tile.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    tiles: HashMap<Position, MemoryValue>,
+    score: Option<MemoryValue>,
+    score_position: Position,
+    consumed: usize,
+    pending: Vec<MemoryValue>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            tiles: HashMap::new(),
+            score: None,
+            score_position: (-1, 0),
+            consumed: 0,
+            pending: Vec::with_capacity(3),
+        }
+    }
+
+    pub fn with_score_position(mut self, x: MemoryValue, y: MemoryValue) -> Framebuffer {
+        self.score_position = (x, y);
+        self
+    }
+
+    /// Draws any outputs recorded since the last update. Inputs are skipped, and an incomplete
+    /// triple is held back until the rest of it arrives.
+    pub fn update(&mut self, io_record: &[RecordedIO]) {
+        for entry in io_record.iter().skip(self.consumed) {
+            if let RecordedIO::Output(value) = entry {
+                self.pending.push(*value);
+                if self.pending.len() == 3 {
+                    self.draw(self.pending[0], self.pending[1], self.pending[2]);
+                    self.pending.clear();
+                }
+            }
+        }
+        self.consumed = self.consumed.max(io_record.len());
+    }
+
+    pub fn draw(&mut self, x: MemoryValue, y: MemoryValue, tile: MemoryValue) {
+        if (x, y) == self.score_position {
+            self.score = Some(tile);
+        } else {
+            self.tiles.insert((x, y), tile);
+        }
+    }
+
+    pub fn tile(&self, x: MemoryValue, y: MemoryValue) -> Option<MemoryValue> {
+        self.tiles.get(&(x, y)).cloned()
+    }
+
+    pub fn score(&self) -> Option<MemoryValue> {
+        self.score
+    }
+
+    /// Positions currently showing `tile`, in no particular order.
+    pub fn positions_of(&self, tile: MemoryValue) -> impl Iterator<Item=Position> + '_ {
+        self.tiles.iter()
+            .filter(move |(_, drawn)| **drawn == tile)
+            .map(|(position, _)| *position)
+    }
+
+    pub fn count(&self, tile: MemoryValue) -> usize {
+        self.positions_of(tile).count()
+    }
+
+    /// The smallest and largest corners covering every drawn tile.
+    pub fn bounds(&self) -> Option<(Position, Position)> {
+        let xs = self.tiles.keys().map(|(x, _)| *x);
+        let ys = self.tiles.keys().map(|(_, y)| *y);
+        Some(((xs.clone().min()?, ys.clone().min()?), (xs.max()?, ys.max()?)))
+    }
+
+    /// Renders the screen as lines of text, preceded by the score if there is one. Cells that
+    /// were never drawn are left blank.
+    pub fn render_text(&self, palette: &Palette) -> String {
+        let mut text = String::new();
+        if let Some(score) = self.score {
+            text += &format!("score: {}\n", score);
+        }
+        if let Some(((min_x, min_y), (max_x, max_y))) = self.bounds() {
+            for y in min_y..=max_y {
+                let line = (min_x..=max_x)
+                    .map(|x| self.tile(x, y).map(|tile| palette.character(tile)).unwrap_or(' '))
+                    .collect::<String>();
+                text += line.trim_end();
+                text += "\n";
+            }
+        }
+        text
+    }
+
+    /// Redraws the frame in place on an ANSI terminal.
+    pub fn draw_frame<W: Write>(&self, out: &mut W, palette: &Palette) -> std::io::Result<()> {
+        write!(out, "\x1b[H\x1b[2J{}", self.render_text(palette))?;
+        out.flush()
+    }
+
+    /// Writes the screen as a binary PPM image, with every tile `scale` pixels square.
+    pub fn write_ppm<W: Write>(&self, out: &mut W, palette: &Palette, scale: usize) -> std::io::Result<()> {
+        let ((min_x, min_y), (max_x, max_y)) = self.bounds().unwrap_or(((0, 0), (0, 0)));
+        let width = (max_x - min_x + 1) as usize * scale;
+        let height = (max_y - min_y + 1) as usize * scale;
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+        for y in min_y..=max_y {
+            let row = (min_x..=max_x)
+                .flat_map(|x| {
+                    let colour = self.tile(x, y).map(|tile| palette.colour(tile)).unwrap_or([0, 0, 0]);
+                    std::iter::repeat_n(colour, scale)
+                })
+                .flatten()
+                .collect::<Vec<u8>>();
+            for _ in 0..scale {
+                out.write_all(&row)?;
+            }
+        }
+        out.flush()
+    }
+
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P, palette: &Palette, scale: usize) -> std::io::Result<()> {
+        self.write_ppm(&mut BufWriter::new(File::create(path)?), palette, scale)
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
+
+/// Runs `computer` until it halts, keeping `screen` up to date with its output. Whenever the
+/// program wants input and none is queued, `on_input` is shown the current frame and its answer
+/// is fed to the program, so it can draw the frame, ask a human, or play automatically.
+pub fn run_display<M, F>(computer: &mut Computer<M>, screen: &mut Framebuffer, mut on_input: F) -> Result<(), Fault>
+    where M: Memory, F: FnMut(&Framebuffer) -> MemoryValue
+{
+    computer.set_interactive_input(false);
+    while !computer.halted {
+        match computer.step() {
+            Ok(()) => {},
+            Err(ref fault) if fault.error == ComputerError::NoInputAvailable => {
+                screen.update(&computer.io_record);
+                computer.push_input(on_input(screen));
+            },
+            Err(fault) => {
+                screen.update(&computer.io_record);
+                return Err(fault);
+            },
+        }
+    }
+    screen.update(&computer.io_record);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, RecordedIO, SimpleMemory, TraceLevel};
+    use crate::intcode::framebuffer::{run_display, Framebuffer, Palette};
+
+    #[test]
+    fn draws_output_triples() {
+        let mut screen = Framebuffer::new();
+        screen.update(&[
+            RecordedIO::Output(1),
+            RecordedIO::Output(2),
+            RecordedIO::Output(3),
+            RecordedIO::Output(6),
+            RecordedIO::UserInput(0),
+            RecordedIO::Output(5),
+        ]);
+        assert_eq!(screen.tile(1, 2), Some(3));
+        assert_eq!(screen.tile(6, 5), None);
+
+        screen.update(&[
+            RecordedIO::Output(1),
+            RecordedIO::Output(2),
+            RecordedIO::Output(3),
+            RecordedIO::Output(6),
+            RecordedIO::UserInput(0),
+            RecordedIO::Output(5),
+            RecordedIO::Output(4),
+            RecordedIO::Output(-1),
+            RecordedIO::Output(0),
+            RecordedIO::Output(12345),
+        ]);
+        assert_eq!(screen.tile(6, 5), Some(4));
+        assert_eq!(screen.score(), Some(12345));
+        assert_eq!(screen.bounds(), Some(((1, 2), (6, 5))));
+        assert_eq!(screen.count(4), 1);
+    }
+
+    #[test]
+    fn renders_text_and_images() {
+        let mut screen = Framebuffer::new().with_score_position(9, 9);
+        screen.draw(0, 0, 1);
+        screen.draw(2, 0, 1);
+        screen.draw(1, 1, 4);
+        screen.draw(9, 9, 7);
+        assert_eq!(screen.render_text(&Palette::default()), "score: 7\n# #\n o\n");
+
+        let mut image = Vec::new();
+        screen.write_ppm(&mut image, &Palette::default(), 2).unwrap();
+        let header = b"P6\n6 4\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        let pixels = &image[header.len()..];
+        assert_eq!(pixels.len(), 6 * 4 * 3);
+        assert_eq!(&pixels[0..6], &[128, 128, 128, 128, 128, 128]);
+        assert_eq!(&pixels[6..12], &[0, 0, 0, 0, 0, 0]);
+        assert_eq!(&pixels[2 * 18 + 6..2 * 18 + 9], &[224, 48, 48]);
+    }
+
+    #[test]
+    fn players_see_the_screen() {
+        let mut memory = SimpleMemory::from_literal(&[
+            // draw a ball at (2, 1) and a score, then draw the joystick input at (3, 1)
+            104, 2, 104, 1, 104, 4,
+            104, -1, 104, 0, 104, 10,
+            3, 26,
+            104, 3, 104, 1, 4, 26,
+            99,
+            0, 0, 0, 0, 0, 0,
+        ]);
+        let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let mut screen = Framebuffer::new();
+        let mut frames = 0;
+        run_display(&mut computer, &mut screen, |frame| {
+            frames += 1;
+            let (ball_x, _) = frame.positions_of(4).next().unwrap();
+            assert_eq!(frame.score(), Some(10));
+            ball_x + 1
+        }).unwrap();
+
+        assert_eq!(frames, 1);
+        assert_eq!(screen.tile(3, 1), Some(3));
+        assert_eq!(screen.render_text(&Palette::default()), "score: 10\no-\n");
+    }
+}
@@ -0,0 +1,629 @@
+use failure::Fail;
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use super::{Address, Computer, ComputerError, Fault, Memory, MemoryValue};
+use super::breakpoint::Condition;
+
+/// Each memory cell is presented to the debugger as this many little endian bytes, so cell `n`
+/// lives at byte address `4 * n`. The instruction pointer is reported as a byte address too, so
+/// that it agrees with breakpoint and memory addresses.
+const BYTES_PER_CELL: usize = 4;
+
+/// How many instructions to run while continuing between checks for an interrupt from the client.
+const INTERRUPT_POLL_INTERVAL: usize = 1024;
+
+const PACKET_SIZE: usize = 4000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.intcode.core">
+    <reg name="ip" bitsize="64" type="code_ptr" regnum="0"/>
+    <reg name="rb" bitsize="64" type="int64" regnum="1"/>
+  </feature>
+</target>
+"#;
+
+const SIGINT: u8 = 0x02;
+const SIGILL: u8 = 0x04;
+const SIGTRAP: u8 = 0x05;
+const SIGSEGV: u8 = 0x0b;
+
+#[derive(Debug, Fail)]
+pub enum GdbError {
+    #[fail(display = "connection error: {}", _0)]
+    Io(#[cause] std::io::Error),
+    #[fail(display = "client closed the connection")]
+    ConnectionClosed,
+}
+
+impl From<std::io::Error> for GdbError {
+    fn from(error: std::io::Error) -> Self {
+        GdbError::Io(error)
+    }
+}
+
+/// Why execution stopped, as reported to the debugger.
+enum StopReason {
+    Signal(u8),
+    Exited,
+}
+
+/// Serves a [`Computer`] to debuggers speaking the GDB remote serial protocol. The machine has
+/// two 64 bit registers, `ip` (0) and `rb` (1), and supports software and hardware breakpoints,
+/// single stepping, continuing (interruptible with Ctrl-C) and memory reads and writes.
+///
+/// Conditional breakpoints are set with monitor commands: `monitor break-if ip == 42 && mem[100] > 5`
+/// stops whenever the [`Condition`] holds, `monitor conditions` lists them and
+/// `monitor delete-conditions` removes them all.
+pub struct GdbStub<'c, 'a, M: Memory> {
+    computer: &'c mut Computer<'a, M>,
+    breakpoints: BTreeSet<Address>,
+    conditions: Vec<Condition>,
+    last_fault: Option<Fault>,
+}
+
+impl<'c, 'a, M: Memory> GdbStub<'c, 'a, M> {
+    pub fn new(computer: &'c mut Computer<'a, M>) -> GdbStub<'c, 'a, M> {
+        GdbStub {
+            computer,
+            breakpoints: BTreeSet::new(),
+            conditions: Vec::new(),
+            last_fault: None,
+        }
+    }
+
+    /// Stops execution before any step at which `condition` holds, as `monitor break-if` does.
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+
+    /// The fault that most recently stopped execution, if any.
+    pub fn last_fault(&self) -> Option<&Fault> {
+        self.last_fault.as_ref()
+    }
+
+    /// Waits for a single debugger to connect on `address` and serves it until it detaches.
+    pub fn listen<A: ToSocketAddrs>(&mut self, address: A) -> Result<(), GdbError> {
+        let listener = TcpListener::bind(address)?;
+        self.accept(&listener)
+    }
+
+    pub fn accept(&mut self, listener: &TcpListener) -> Result<(), GdbError> {
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+
+    /// Serves one debugger session over `stream`, returning once the client kills or detaches
+    /// from the machine.
+    pub fn serve(&mut self, mut stream: TcpStream) -> Result<(), GdbError> {
+        stream.set_nodelay(true)?;
+        loop {
+            let packet = match read_packet(&mut stream)? {
+                Some(packet) => packet,
+                None => continue,
+            };
+            match packet.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => return write_packet(&mut stream, "OK"),
+                _ => {},
+            }
+            let reply = self.handle(&packet, &mut stream)?;
+            write_packet(&mut stream, &reply)?;
+        }
+    }
+
+    fn handle(&mut self, packet: &str, stream: &mut TcpStream) -> Result<String, GdbError> {
+        // an empty packet is valid, and gets the same empty reply as anything unsupported
+        let command_length = match packet.chars().next() {
+            Some(command) => command.len_utf8(),
+            None => return Ok(String::new()),
+        };
+        let (command, arguments) = packet.split_at(command_length);
+        let reply = match command {
+            "?" => self.stop_reply(&StopReason::Signal(SIGTRAP)),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(arguments),
+            "p" => self.read_register(arguments),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "c" => {
+                self.resume_at(arguments);
+                let reason = self.continue_execution(stream)?;
+                self.stop_reply(&reason)
+            },
+            "s" => {
+                self.resume_at(arguments);
+                let reason = self.step().unwrap_or(StopReason::Signal(SIGTRAP));
+                self.stop_reply(&reason)
+            },
+            "Z" | "z" => self.update_breakpoint(command == "Z", arguments),
+            "H" => "OK".into(),
+            "q" => self.query(arguments),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn query(&mut self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+        } else if let Some(annex) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            match parse_address_and_length(annex) {
+                Some((offset, length)) => {
+                    let offset = offset.min(TARGET_XML.len());
+                    let end = (offset + length).min(TARGET_XML.len());
+                    let marker = if end == TARGET_XML.len() { "l" } else { "m" };
+                    format!("{}{}", marker, &TARGET_XML[offset..end])
+                },
+                None => error_reply(),
+            }
+        } else if let Some(command) = query.strip_prefix("Rcmd,") {
+            match decode_hex(command).and_then(|command| String::from_utf8(command).ok()) {
+                Some(command) => encode_hex(self.monitor(&command).as_bytes()),
+                None => error_reply(),
+            }
+        } else {
+            match query {
+                "Attached" => "1".into(),
+                "C" => "QC1".into(),
+                "fThreadInfo" => "m1".into(),
+                "sThreadInfo" => "l".into(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    /// Runs a `monitor` command, returning the text to show the user.
+    fn monitor(&mut self, command: &str) -> String {
+        let command = command.trim();
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "break-if" => match argument.parse::<Condition>() {
+                Ok(condition) => {
+                    self.conditions.push(condition);
+                    format!("condition {}: {}\n", self.conditions.len(), argument.trim())
+                },
+                Err(error) => format!("invalid condition: {}\n", error),
+            },
+            "conditions" => self.conditions.iter()
+                .enumerate()
+                .map(|(index, condition)| format!("condition {}: {}\n", index + 1, condition))
+                .collect(),
+            "delete-conditions" => {
+                self.conditions.clear();
+                "deleted all conditions\n".into()
+            },
+            _ => format!("unknown monitor command `{}`; try break-if, conditions or delete-conditions\n", name),
+        }
+    }
+
+    fn stop_reply(&self, reason: &StopReason) -> String {
+        match reason {
+            _ if self.computer.halted => "W00".into(),
+            StopReason::Exited => "W00".into(),
+            StopReason::Signal(signal) => format!("S{:02x}", signal),
+        }
+    }
+
+    fn register(&self, number: usize) -> Option<u64> {
+        match number {
+            0 => Some((self.computer.instruction_pointer() * BYTES_PER_CELL) as u64),
+            1 => Some(self.computer.relative_base() as i64 as u64),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, number: usize, value: u64) -> bool {
+        match number {
+            0 => self.computer.set_instruction_pointer(value as Address / BYTES_PER_CELL),
+            1 => self.computer.set_relative_base(value as i64 as MemoryValue),
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_registers(&self) -> String {
+        (0..2)
+            .filter_map(|number| self.register(number))
+            .map(|value| encode_hex(&value.to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> String {
+        let bytes = match decode_hex(arguments) {
+            Some(bytes) if bytes.len() == 16 => bytes,
+            _ => return error_reply(),
+        };
+        for (number, chunk) in bytes.chunks(8).enumerate() {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(chunk);
+            self.set_register(number, u64::from_le_bytes(value));
+        }
+        "OK".into()
+    }
+
+    fn read_register(&self, arguments: &str) -> String {
+        usize::from_str_radix(arguments, 16).ok()
+            .and_then(|number| self.register(number))
+            .map(|value| encode_hex(&value.to_le_bytes()))
+            .unwrap_or_else(error_reply)
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let mut parts = arguments.splitn(2, '=');
+        let number = parts.next().and_then(|number| usize::from_str_radix(number, 16).ok());
+        let value = parts.next().and_then(decode_hex).filter(|bytes| bytes.len() == 8);
+        match (number, value) {
+            (Some(number), Some(bytes)) => {
+                let mut value = [0u8; 8];
+                value.copy_from_slice(&bytes);
+                if self.set_register(number, u64::from_le_bytes(value)) {
+                    "OK".into()
+                } else {
+                    error_reply()
+                }
+            },
+            _ => error_reply(),
+        }
+    }
+
+    fn read_memory(&self, arguments: &str) -> String {
+        let (address, length) = match parse_address_and_length(arguments) {
+            Some(range) => range,
+            None => return error_reply(),
+        };
+        let memory = self.computer.memory();
+        // a read that runs off the end of memory returns as many bytes as could be read
+        let bytes = (address..address.saturating_add(length))
+            .map_while(|byte| memory.peek_slot(byte / BYTES_PER_CELL).ok()
+                .map(|value| value.to_le_bytes()[byte % BYTES_PER_CELL]))
+            .collect::<Vec<_>>();
+        if bytes.is_empty() && length > 0 {
+            error_reply()
+        } else {
+            encode_hex(&bytes)
+        }
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let mut parts = arguments.splitn(2, ':');
+        let range = parts.next().and_then(parse_address_and_length);
+        let data = parts.next().and_then(decode_hex);
+        let (address, data) = match (range, data) {
+            (Some((address, length)), Some(data)) if data.len() == length => (address, data),
+            _ => return error_reply(),
+        };
+        let memory = self.computer.memory_mut();
+        for (byte, value) in (address..).zip(data) {
+            let cell = byte / BYTES_PER_CELL;
+            let mut bytes = match memory.peek_slot(cell) {
+                Ok(word) => word.to_le_bytes(),
+                Err(_) => return error_reply(),
+            };
+            bytes[byte % BYTES_PER_CELL] = value;
+            if memory.write_slot(cell, MemoryValue::from_le_bytes(bytes)).is_err() {
+                return error_reply();
+            }
+        }
+        "OK".into()
+    }
+
+    fn update_breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let mut parts = arguments.split(',');
+        let kind = parts.next();
+        let address = parts.next().and_then(|address| usize::from_str_radix(address, 16).ok());
+        match (kind, address) {
+            // software and hardware breakpoints are the same thing here
+            (Some("0"), Some(address)) | (Some("1"), Some(address)) => {
+                let cell = address / BYTES_PER_CELL;
+                if insert {
+                    self.breakpoints.insert(cell);
+                } else {
+                    self.breakpoints.remove(&cell);
+                }
+                "OK".into()
+            },
+            (Some(_), Some(_)) => String::new(),
+            _ => error_reply(),
+        }
+    }
+
+    fn resume_at(&mut self, arguments: &str) {
+        if let Ok(address) = usize::from_str_radix(arguments, 16) {
+            self.computer.set_instruction_pointer(address / BYTES_PER_CELL);
+        }
+    }
+
+    /// Executes one instruction, returning why execution has to stop if it can't go on.
+    fn step(&mut self) -> Option<StopReason> {
+        if self.computer.halted {
+            return Some(StopReason::Exited);
+        }
+        match self.computer.step() {
+            Ok(()) if self.computer.halted => Some(StopReason::Exited),
+            Ok(()) => None,
+            Err(fault) => {
+                let signal = match fault.error {
+                    ComputerError::UnknownOpcode(_) | ComputerError::UnknownParameterMode(_) => SIGILL,
+                    _ => SIGSEGV,
+                };
+                self.last_fault = Some(fault);
+                Some(StopReason::Signal(signal))
+            },
+        }
+    }
+
+    fn continue_execution(&mut self, stream: &mut TcpStream) -> Result<StopReason, GdbError> {
+        let mut steps = 0;
+        loop {
+            if let Some(reason) = self.step() {
+                return Ok(reason);
+            }
+            if self.breakpoints.contains(&self.computer.instruction_pointer())
+                || self.conditions.iter().any(|condition| condition.evaluate(self.computer)) {
+                return Ok(StopReason::Signal(SIGTRAP));
+            }
+            steps += 1;
+            if steps % INTERRUPT_POLL_INTERVAL == 0 && interrupt_requested(stream)? {
+                return Ok(StopReason::Signal(SIGINT));
+            }
+        }
+    }
+}
+
+fn error_reply() -> String {
+    "E01".into()
+}
+
+fn interrupt_requested(stream: &mut TcpStream) -> Result<bool, GdbError> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0u8];
+    let result = stream.read(&mut byte);
+    stream.set_nonblocking(false)?;
+    match result {
+        Ok(0) => Err(GdbError::ConnectionClosed),
+        Ok(_) => Ok(byte[0] == 0x03),
+        Err(ref error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn read_byte<R: Read>(stream: &mut R) -> Result<u8, GdbError> {
+    let mut byte = [0u8];
+    match stream.read(&mut byte)? {
+        0 => Err(GdbError::ConnectionClosed),
+        _ => Ok(byte[0]),
+    }
+}
+
+/// Reads the next packet, acknowledging it. Returns `None` for packets with a bad checksum, which
+/// the client is asked to resend.
+fn read_packet<S: Read + Write>(stream: &mut S) -> Result<Option<String>, GdbError> {
+    // skip acknowledgements and stray interrupts between packets
+    while read_byte(stream)? != b'$' {}
+
+    let mut data = Vec::new();
+    let mut checksum = 0u8;
+    loop {
+        match read_byte(stream)? {
+            b'#' => break,
+            b'}' => {
+                checksum = checksum.wrapping_add(b'}');
+                let escaped = read_byte(stream)?;
+                checksum = checksum.wrapping_add(escaped);
+                data.push(escaped ^ 0x20);
+            },
+            byte => {
+                checksum = checksum.wrapping_add(byte);
+                data.push(byte);
+            },
+        }
+    }
+    let expected = [read_byte(stream)?, read_byte(stream)?];
+    let valid = std::str::from_utf8(&expected).ok()
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        == Some(checksum);
+
+    if !valid {
+        stream.write_all(b"-")?;
+        return Ok(None);
+    }
+    stream.write_all(b"+")?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+/// Sends a packet, resending it until the client acknowledges it.
+fn write_packet<S: Read + Write>(stream: &mut S, data: &str) -> Result<(), GdbError> {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    let packet = format!("${}#{:02x}", data, checksum);
+    loop {
+        stream.write_all(packet.as_bytes())?;
+        stream.flush()?;
+        match read_byte(stream)? {
+            b'-' => continue,
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn parse_address_and_length(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let address = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let length = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, length))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| text.get(index..index + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, SimpleMemory};
+    use crate::intcode::gdb::{decode_hex, encode_hex, GdbStub};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+            assert_eq!(self.read_byte(), b'+');
+            self.read_reply()
+        }
+
+        fn read_reply(&mut self) -> String {
+            assert_eq!(self.read_byte(), b'$');
+            let mut reply = Vec::new();
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => reply.push(byte),
+                }
+            }
+            self.read_byte();
+            self.read_byte();
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0u8];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+    }
+
+    fn serve(program: &'static [i32]) -> (Client, thread::JoinHandle<Vec<i32>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut memory = SimpleMemory::from_literal(program);
+            let mut computer = Computer::new(&mut memory);
+            GdbStub::new(&mut computer).accept(&listener).unwrap();
+            memory.as_slice().to_vec()
+        });
+        let client = Client {
+            stream: TcpStream::connect(address).unwrap(),
+        };
+        (client, server)
+    }
+
+    #[test]
+    fn can_inspect_and_modify_the_machine() {
+        let (mut client, server) = serve(&[1101, 20, 22, 7, 109, -3, 99, 0]);
+
+        assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+        assert!(client.request("qXfer:features:read:target.xml:0,fff").contains(r#"name="rb""#));
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("g"), "00000000000000000000000000000000");
+        assert_eq!(client.request("m0,8"), "4d04000014000000");
+        assert_eq!(client.request("m1c,8"), "00000000");
+        assert_eq!(client.request("m40,4"), "E01");
+
+        assert_eq!(client.request("M4,4:15000000"), "OK");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p0"), "1000000000000000");
+        assert_eq!(client.request("m1c,4"), "2b000000");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p1"), "fdffffffffffffff");
+        assert_eq!(client.request("P1=0500000000000000"), "OK");
+        assert_eq!(client.request("g"), "18000000000000000500000000000000");
+        assert_eq!(client.request("s"), "W00");
+        assert_eq!(client.request("D"), "OK");
+
+        assert_eq!(server.join().unwrap(), vec![1101, 21, 22, 7, 109, -3, 99, 43]);
+    }
+
+    #[test]
+    fn survives_empty_and_non_ascii_packets() {
+        let (mut client, server) = serve(&[99]);
+        client.stream.write_all(b"$#00").unwrap();
+        assert_eq!(client.read_byte(), b'+');
+        assert_eq!(client.read_reply(), "");
+        assert_eq!(client.request("\u{fffd}1"), "");
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("D"), "OK");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn can_continue_to_breakpoints() {
+        let (mut client, server) = serve(&[
+            // count down from 3, then halt
+            1101, 0, 3, 13,
+            1001, 13, -1, 13,
+            1005, 13, 4,
+            99,
+            0, 0,
+        ]);
+
+        assert_eq!(client.request("Z0,10,4"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p0"), "1000000000000000");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("m34,4"), "02000000");
+        assert_eq!(client.request("z0,10,4"), "OK");
+        assert_eq!(client.request("c"), "W00");
+        assert_eq!(client.request("m34,4"), "00000000");
+        client.stream.write_all(b"$k#6b").unwrap();
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn can_continue_to_conditions() {
+        let (mut client, server) = serve(&[
+            1101, 0, 3, 13,
+            1001, 13, -1, 13,
+            1005, 13, 4,
+            99,
+            0, 0,
+        ]);
+        let monitor = |client: &mut Client, command: &str| {
+            let reply = client.request(&format!("qRcmd,{}", encode_hex(command.as_bytes())));
+            String::from_utf8(decode_hex(&reply).unwrap()).unwrap()
+        };
+
+        assert_eq!(monitor(&mut client, "break-if opcode == JumpIfTrue && mem[13] == 1"), "condition 1: opcode == JumpIfTrue && mem[13] == 1\n");
+        assert!(monitor(&mut client, "break-if ip ==").starts_with("invalid condition: expected a value"));
+        assert_eq!(monitor(&mut client, "conditions"), "condition 1: opcode == JumpIfTrue && mem[13] == 1\n");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p0"), "2000000000000000");
+        assert_eq!(client.request("m34,4"), "01000000");
+        assert_eq!(monitor(&mut client, "delete-conditions"), "deleted all conditions\n");
+        assert_eq!(client.request("c"), "W00");
+        client.stream.write_all(b"$k#6b").unwrap();
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reports_faults_as_signals() {
+        let (mut client, server) = serve(&[1101, 1, 1, 5, 1142, 0]);
+
+        assert_eq!(client.request("c"), "S04");
+        assert_eq!(client.request("p0"), "1000000000000000");
+        assert_eq!(client.request("D"), "OK");
+
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+use super::{Address, ExecuteResult, Instruction, Memory, MemoryValue, RecordedIO};
+
+/// Whether execution should carry on after a hook has seen an event.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HookAction {
+    Continue,
+    Stop,
+}
+
+/// Where a [`Computer`](super::Computer) is up to at the start of a step.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StepState {
+    pub instruction_pointer: Address,
+    pub relative_base: MemoryValue,
+    pub cycle: usize,
+}
+
+/// Observes the execution of a [`Computer`](super::Computer), for profilers, coverage,
+/// watchpoints and the like. Every method does nothing by default, so a hook only implements the
+/// events it cares about. A computer borrows its hook, given with
+/// [`Computer::with_hook`](super::Computer::with_hook), so whatever the hook collected can be read
+/// once the computer is done with it.
+///
+/// Returning [`HookAction::Stop`] makes the step fail with
+/// [`ComputerError::StoppedByHook`](super::ComputerError::StoppedByHook). Stopping before
+/// `after_execute` abandons the instruction: a stopped write or output doesn't happen and an
+/// input that was read is put back, so stepping again retries it. Stopping from `after_execute`
+/// happens once the instruction has finished.
+pub trait ExecutionHook {
+    fn before_decode(&mut self, _state: StepState, _memory: &dyn Memory) -> HookAction {
+        HookAction::Continue
+    }
+
+    fn after_decode(&mut self, _state: StepState, _instruction: &Instruction) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// A parameter being read from memory; immediate parameters don't count.
+    fn on_read(&mut self, _address: Address, _value: MemoryValue) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Called before `value` is written to `address`.
+    fn on_write(&mut self, _address: Address, _value: MemoryValue) -> HookAction {
+        HookAction::Continue
+    }
+
+    fn on_io(&mut self, _event: RecordedIO) -> HookAction {
+        HookAction::Continue
+    }
+
+    fn after_execute(&mut self, _state: StepState, _instruction: &Instruction, _result: &ExecuteResult) -> HookAction {
+        HookAction::Continue
+    }
+}
+
+/// Runs several hooks as one. Every hook sees each event, even once one of them has asked to
+/// stop.
+impl ExecutionHook for Vec<&mut dyn ExecutionHook> {
+    fn before_decode(&mut self, state: StepState, memory: &dyn Memory) -> HookAction {
+        notify_all(self, |hook| hook.before_decode(state, memory))
+    }
+
+    fn after_decode(&mut self, state: StepState, instruction: &Instruction) -> HookAction {
+        notify_all(self, |hook| hook.after_decode(state, instruction))
+    }
+
+    fn on_read(&mut self, address: Address, value: MemoryValue) -> HookAction {
+        notify_all(self, |hook| hook.on_read(address, value))
+    }
+
+    fn on_write(&mut self, address: Address, value: MemoryValue) -> HookAction {
+        notify_all(self, |hook| hook.on_write(address, value))
+    }
+
+    fn on_io(&mut self, event: RecordedIO) -> HookAction {
+        notify_all(self, |hook| hook.on_io(event))
+    }
+
+    fn after_execute(&mut self, state: StepState, instruction: &Instruction, result: &ExecuteResult) -> HookAction {
+        notify_all(self, |hook| hook.after_execute(state, instruction, result))
+    }
+}
+
+fn notify_all<F>(hooks: &mut [&mut dyn ExecutionHook], mut event: F) -> HookAction
+    where F: FnMut(&mut dyn ExecutionHook) -> HookAction
+{
+    let mut action = HookAction::Continue;
+    for hook in hooks.iter_mut() {
+        if event(&mut **hook) == HookAction::Stop {
+            action = HookAction::Stop;
+        }
+    }
+    action
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::intcode::{Address, Computer, ComputerError, ExecuteResult, Instruction, Memory, MemoryValue, RecordedIO, SimpleMemory, TraceLevel};
+    use crate::intcode::hooks::{ExecutionHook, HookAction, StepState};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl ExecutionHook for Recorder {
+        fn before_decode(&mut self, state: StepState, memory: &dyn Memory) -> HookAction {
+            self.events.push(format!("before {} ({})", state.instruction_pointer, memory.read_slot(state.instruction_pointer).unwrap()));
+            HookAction::Continue
+        }
+
+        fn after_decode(&mut self, _state: StepState, instruction: &Instruction) -> HookAction {
+            self.events.push(format!("decoded {}", instruction));
+            HookAction::Continue
+        }
+
+        fn on_read(&mut self, address: Address, value: MemoryValue) -> HookAction {
+            self.events.push(format!("read [{}] = {}", address, value));
+            HookAction::Continue
+        }
+
+        fn on_write(&mut self, address: Address, value: MemoryValue) -> HookAction {
+            self.events.push(format!("write [{}] = {}", address, value));
+            HookAction::Continue
+        }
+
+        fn on_io(&mut self, event: RecordedIO) -> HookAction {
+            self.events.push(format!("{:?}", event));
+            HookAction::Continue
+        }
+
+        fn after_execute(&mut self, _state: StepState, _instruction: &Instruction, result: &ExecuteResult) -> HookAction {
+            self.events.push(match result {
+                ExecuteResult::AdvanceBy(amount) => format!("advance {}", amount),
+                ExecuteResult::JumpTo(address) => format!("jump {}", address),
+            });
+            HookAction::Continue
+        }
+    }
+
+    /// Stops before anything is written to one address.
+    struct Watchpoint(Address);
+
+    impl ExecutionHook for Watchpoint {
+        fn on_write(&mut self, address: Address, _value: MemoryValue) -> HookAction {
+            if address == self.0 { HookAction::Stop } else { HookAction::Continue }
+        }
+    }
+
+    #[test]
+    fn hooks_see_every_event() {
+        let mut memory = SimpleMemory::from_literal(&[3, 9, 1001, 9, 2, 9, 4, 9, 99, 0]);
+        let mut recorder = Recorder::default();
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![40])
+            .with_hook(&mut recorder);
+        computer.run_until_halted().unwrap();
+        assert_eq!(recorder.events, vec![
+            "before 0 (3)", "decoded in [9]", "UserInput(40)", "write [9] = 40", "advance 2",
+            "before 2 (1001)", "decoded add [9], 2, [9]", "read [9] = 40", "write [9] = 42", "advance 4",
+            "before 6 (4)", "decoded out [9]", "read [9] = 42", "Output(42)", "advance 2",
+            "before 8 (99)", "decoded hlt", "advance 1",
+        ]);
+    }
+
+    #[test]
+    fn stopping_abandons_the_instruction() {
+        let mut memory = SimpleMemory::from_literal(&[3, 7, 4, 7, 1105, 1, 0, 0]);
+        let mut watchpoint = Watchpoint(7);
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![5])
+            .with_hook(&mut watchpoint);
+        let fault = computer.run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::StoppedByHook);
+        assert_eq!(fault.instruction_pointer, 0);
+        assert_eq!(computer.cycle_count(), 0);
+        assert!(computer.io_record.is_empty());
+        assert_eq!(computer.memory().read_slot(7), Ok(0));
+
+        // the input wasn't lost, so without the hook the step goes through
+        computer.set_hook(None);
+        computer.step().unwrap();
+        assert_eq!(computer.io_record, vec![RecordedIO::UserInput(5)]);
+        assert_eq!(computer.memory().read_slot(7), Ok(5));
+    }
+
+    #[test]
+    fn several_hooks_can_run_together() {
+        let mut memory = SimpleMemory::from_literal(&[1101, 1, 2, 5, 99, 0]);
+        let mut recorder = Recorder::default();
+        let mut watchpoint = Watchpoint(5);
+        let mut hooks: Vec<&mut dyn ExecutionHook> = vec![&mut watchpoint, &mut recorder];
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_hook(&mut hooks);
+        assert_eq!(computer.step().unwrap_err().error, ComputerError::StoppedByHook);
+        assert_eq!(computer.memory().read_slot(5), Ok(0));
+        assert_eq!(recorder.events, vec!["before 0 (1101)", "decoded add 1, 2, [5]", "write [5] = 3"]);
+    }
+
+    #[test]
+    fn can_stop_after_executing() {
+        struct StopAfterJumps(BTreeMap<Address, usize>);
+
+        impl ExecutionHook for StopAfterJumps {
+            fn after_execute(&mut self, state: StepState, _instruction: &Instruction, result: &ExecuteResult) -> HookAction {
+                match result {
+                    ExecuteResult::JumpTo(_) => {
+                        let count = self.0.entry(state.instruction_pointer).or_insert(0);
+                        *count += 1;
+                        if *count == 3 { HookAction::Stop } else { HookAction::Continue }
+                    },
+                    ExecuteResult::AdvanceBy(_) => HookAction::Continue,
+                }
+            }
+        }
+
+        let mut memory = SimpleMemory::from_literal(&[104, 1, 1105, 1, 0]);
+        let mut hook = StopAfterJumps(BTreeMap::new());
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_hook(&mut hook);
+        let fault = computer.run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::StoppedByHook);
+        assert_eq!(computer.instruction_pointer(), 0);
+        assert_eq!(computer.io_record.len(), 3);
+        assert_eq!(hook.0.get(&2), Some(&3));
+    }
+}
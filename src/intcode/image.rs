@@ -0,0 +1,419 @@
+use failure::{Fail, ResultExt};
+use std::error::Error;
+use std::path::Path;
+use super::{Address, MemoryValue};
+use super::protection::{Protection, ProtectionMap};
+
+/// Every image starts with these bytes, followed by a single format version byte.
+pub const IMAGE_MAGIC: &[u8; 4] = b"ICIM";
+pub const IMAGE_VERSION: u8 = 1;
+
+const FLAG_CHECKSUM: u8 = 0b001;
+const FLAG_NAME: u8 = 0b010;
+const FLAG_LINE_INFO: u8 = 0b100;
+const FLAG_PROTECTION: u8 = 0b1000;
+const KNOWN_FLAGS: u8 = FLAG_CHECKSUM | FLAG_NAME | FLAG_LINE_INFO | FLAG_PROTECTION;
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum ImageError {
+    #[fail(display = "data does not start with the intcode image magic number")]
+    BadMagic,
+    #[fail(display = "unsupported image version {}", _0)]
+    UnsupportedVersion(u8),
+    #[fail(display = "image header has unknown flags set: {:#b}", _0)]
+    UnknownFlags(u8),
+    #[fail(display = "image ended unexpectedly")]
+    UnexpectedEnd,
+    #[fail(display = "varint is too long for its type")]
+    VarintOverflow,
+    #[fail(display = "string in image is not valid UTF-8")]
+    InvalidString,
+    #[fail(display = "checksum mismatch (stored {:#010x}, computed {:#010x})", stored, computed)]
+    ChecksumMismatch { stored: u32, computed: u32 },
+    #[fail(display = "image has trailing data after its contents")]
+    TrailingData,
+    #[fail(display = "unknown memory protection {} in image", _0)]
+    UnknownProtection(u8),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: Address,
+}
+
+/// Maps the instruction at `address` back to the line of source it was produced from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LineInfo {
+    pub address: Address,
+    pub line: usize,
+}
+
+/// A memory image along with the metadata describing it.
+///
+/// The binary layout is the magic number, version and flags bytes, then (all as LEB128 varints,
+/// with memory values zig-zag encoded): the name if flagged, the entry point, the symbol table,
+/// the line info if flagged, the protected regions (start, end and a protection byte) if flagged
+/// and finally the memory itself. If the checksum flag is set a little
+/// endian CRC-32 of everything before it ends the image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProgramImage {
+    pub name: Option<String>,
+    pub entry_point: Address,
+    pub symbols: Vec<Symbol>,
+    pub line_info: Option<Vec<LineInfo>>,
+    /// Protection for a [`Computer`](super::Computer) running the image to apply. Only stored
+    /// when there is some.
+    pub protection: ProtectionMap,
+    pub memory: Vec<MemoryValue>,
+}
+
+impl ProgramImage {
+    pub fn new(memory: Vec<MemoryValue>) -> ProgramImage {
+        ProgramImage {
+            name: None,
+            entry_point: 0,
+            symbols: Vec::new(),
+            line_info: None,
+            protection: ProtectionMap::new(),
+            memory,
+        }
+    }
+
+    pub fn is_image(bytes: &[u8]) -> bool {
+        bytes.starts_with(IMAGE_MAGIC)
+    }
+
+    pub fn symbol_at(&self, address: Address) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.address == address)
+    }
+
+    pub fn encode(&self, checksum: bool) -> Vec<u8> {
+        let mut flags = 0;
+        if checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        if self.name.is_some() {
+            flags |= FLAG_NAME;
+        }
+        if self.line_info.is_some() {
+            flags |= FLAG_LINE_INFO;
+        }
+        if !self.protection.is_empty() {
+            flags |= FLAG_PROTECTION;
+        }
+
+        let mut writer = ImageWriter { bytes: Vec::new() };
+        writer.bytes.extend_from_slice(IMAGE_MAGIC);
+        writer.bytes.push(IMAGE_VERSION);
+        writer.bytes.push(flags);
+
+        if let Some(name) = &self.name {
+            writer.write_string(name);
+        }
+        writer.write_unsigned(self.entry_point as u64);
+
+        writer.write_unsigned(self.symbols.len() as u64);
+        for symbol in self.symbols.iter() {
+            writer.write_string(&symbol.name);
+            writer.write_unsigned(symbol.address as u64);
+        }
+
+        if let Some(line_info) = &self.line_info {
+            writer.write_unsigned(line_info.len() as u64);
+            for info in line_info.iter() {
+                writer.write_unsigned(info.address as u64);
+                writer.write_unsigned(info.line as u64);
+            }
+        }
+
+        if !self.protection.is_empty() {
+            writer.write_unsigned(self.protection.regions().len() as u64);
+            for region in self.protection.regions() {
+                writer.write_unsigned(region.range.start as u64);
+                writer.write_unsigned(region.range.end as u64);
+                writer.bytes.push(region.protection.to_byte());
+            }
+        }
+
+        writer.write_unsigned(self.memory.len() as u64);
+        for value in self.memory.iter() {
+            writer.write_signed(*value as i64);
+        }
+
+        if checksum {
+            let crc = crc32(&writer.bytes);
+            writer.bytes.extend_from_slice(&crc.to_le_bytes());
+        }
+
+        writer.bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<ProgramImage, ImageError> {
+        if !ProgramImage::is_image(bytes) {
+            return Err(ImageError::BadMagic);
+        }
+
+        let mut reader = ImageReader { bytes, offset: IMAGE_MAGIC.len() };
+        let version = reader.read_byte()?;
+        if version != IMAGE_VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+        let flags = reader.read_byte()?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(ImageError::UnknownFlags(flags & !KNOWN_FLAGS));
+        }
+
+        if flags & FLAG_CHECKSUM != 0 {
+            if bytes.len() < reader.offset + 4 {
+                return Err(ImageError::UnexpectedEnd);
+            }
+            let (contents, stored) = bytes.split_at(bytes.len() - 4);
+            let stored = u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]);
+            let computed = crc32(contents);
+            if stored != computed {
+                return Err(ImageError::ChecksumMismatch { stored, computed });
+            }
+            reader.bytes = contents;
+        }
+
+        let name = if flags & FLAG_NAME != 0 {
+            Some(reader.read_string()?)
+        } else {
+            None
+        };
+        let entry_point = reader.read_address()?;
+
+        let symbol_count = reader.read_unsigned()?;
+        let mut symbols = Vec::new();
+        for _ in 0..symbol_count {
+            let name = reader.read_string()?;
+            let address = reader.read_address()?;
+            symbols.push(Symbol { name, address });
+        }
+
+        let line_info = if flags & FLAG_LINE_INFO != 0 {
+            let count = reader.read_unsigned()?;
+            let mut line_info = Vec::new();
+            for _ in 0..count {
+                let address = reader.read_address()?;
+                let line = reader.read_address()?;
+                line_info.push(LineInfo { address, line });
+            }
+            Some(line_info)
+        } else {
+            None
+        };
+
+        let mut protection = ProtectionMap::new();
+        if flags & FLAG_PROTECTION != 0 {
+            let count = reader.read_unsigned()?;
+            for _ in 0..count {
+                let start = reader.read_address()?;
+                let end = reader.read_address()?;
+                let byte = reader.read_byte()?;
+                protection.protect(start..end, Protection::from_byte(byte).ok_or(ImageError::UnknownProtection(byte))?);
+            }
+        }
+
+        let memory_size = reader.read_unsigned()?;
+        let mut memory = Vec::new();
+        for _ in 0..memory_size {
+            let value = reader.read_signed()?;
+            if value < MemoryValue::MIN as i64 || value > MemoryValue::MAX as i64 {
+                return Err(ImageError::VarintOverflow);
+            }
+            memory.push(value as MemoryValue);
+        }
+
+        if reader.offset != reader.bytes.len() {
+            return Err(ImageError::TrailingData);
+        }
+
+        Ok(ProgramImage {
+            name,
+            entry_point,
+            symbols,
+            line_info,
+            protection,
+            memory,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ProgramImage, Box<dyn Error>> {
+        let bytes = std::fs::read(path)?;
+        Ok(ProgramImage::decode(&bytes).compat()?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, checksum: bool) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.encode(checksum))?;
+        Ok(())
+    }
+}
+
+fn zig_zag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zig_zag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+struct ImageWriter {
+    bytes: Vec<u8>,
+}
+
+impl ImageWriter {
+    fn write_unsigned(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                break;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64) {
+        self.write_unsigned(zig_zag_encode(value));
+    }
+
+    fn write_string(&mut self, value: &str) {
+        self.write_unsigned(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+}
+
+struct ImageReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ImageReader<'a> {
+    fn read_byte(&mut self) -> Result<u8, ImageError> {
+        let byte = *self.bytes.get(self.offset).ok_or(ImageError::UnexpectedEnd)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn read_unsigned(&mut self) -> Result<u64, ImageError> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            if shift >= 64 || (shift == 63 && byte & 0x7e != 0) {
+                return Err(ImageError::VarintOverflow);
+            }
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_signed(&mut self) -> Result<i64, ImageError> {
+        Ok(zig_zag_decode(self.read_unsigned()?))
+    }
+
+    fn read_address(&mut self) -> Result<Address, ImageError> {
+        let value = self.read_unsigned()?;
+        if value > Address::MAX as u64 {
+            return Err(ImageError::VarintOverflow);
+        }
+        Ok(value as Address)
+    }
+
+    fn read_string(&mut self) -> Result<String, ImageError> {
+        let length = self.read_address()?;
+        let end = self.offset.checked_add(length).ok_or(ImageError::UnexpectedEnd)?;
+        let bytes = self.bytes.get(self.offset..end).ok_or(ImageError::UnexpectedEnd)?;
+        self.offset = end;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ImageError::InvalidString)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::image::{ProgramImage, ImageError, Symbol, LineInfo, zig_zag_encode, zig_zag_decode, crc32};
+    use crate::intcode::protection::{Protection, ProtectionMap};
+
+    fn example_image() -> ProgramImage {
+        ProgramImage {
+            name: Some("example".into()),
+            entry_point: 4,
+            symbols: vec![
+                Symbol { name: "start".into(), address: 4 },
+                Symbol { name: "data".into(), address: 0 },
+            ],
+            line_info: Some(vec![
+                LineInfo { address: 4, line: 1 },
+                LineInfo { address: 8, line: 2 },
+            ]),
+            protection: ProtectionMap::new().with_region(4..9, Protection::ReadOnly),
+            memory: vec![10, -20, 0, 0, 1, 0, 1, 2, 99, i32::MIN, i32::MAX],
+        }
+    }
+
+    #[test]
+    fn zig_zag_encoding_round_trips() {
+        assert_eq!(zig_zag_encode(0), 0);
+        assert_eq!(zig_zag_encode(-1), 1);
+        assert_eq!(zig_zag_encode(1), 2);
+        assert_eq!(zig_zag_encode(-2), 3);
+        for value in &[0, 1, -1, 63, -64, 1 << 40, i64::MIN, i64::MAX] {
+            assert_eq!(zig_zag_decode(zig_zag_encode(*value)), *value);
+        }
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let image = example_image();
+        assert_eq!(ProgramImage::decode(&image.encode(false)), Ok(image.clone()));
+        assert_eq!(ProgramImage::decode(&image.encode(true)), Ok(image));
+
+        let bare = ProgramImage::new(vec![1, 0, 0, 0, 99]);
+        assert_eq!(ProgramImage::decode(&bare.encode(false)), Ok(bare));
+    }
+
+    #[test]
+    fn rejects_damaged_images() {
+        let encoded = example_image().encode(true);
+
+        assert_eq!(ProgramImage::decode(b"1,0,0,0,99"), Err(ImageError::BadMagic));
+        assert!(ProgramImage::decode(&encoded[..encoded.len() - 10]).is_err());
+
+        let mut corrupted = encoded.clone();
+        corrupted[10] ^= 0xff;
+        match ProgramImage::decode(&corrupted) {
+            Err(ImageError::ChecksumMismatch { .. }) => {},
+            other => panic!("expected checksum mismatch, got {:?}", other),
+        }
+
+        let mut future = encoded;
+        future[4] = 2;
+        assert_eq!(ProgramImage::decode(&future), Err(ImageError::UnsupportedVersion(2)));
+
+        let unchecked = example_image().encode(false);
+        assert_eq!(ProgramImage::decode(&unchecked[..unchecked.len() - 1]), Err(ImageError::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,417 @@
+use failure::Fail;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use super::{Address, Instruction, MemoryValue, Parameter};
+
+/// Why the optimizer left a whole program alone. Each of these means some instruction could touch
+/// memory or jump somewhere that can't be worked out without running the program.
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum Refusal {
+    #[fail(display = "the jump at address {} has a computed target", _0)]
+    IndirectJump(Address),
+    #[fail(display = "the instruction at address {} uses relative addressing", _0)]
+    RelativeAddressing(Address),
+    #[fail(display = "the instruction at address {} can't be decoded", _0)]
+    UndecodableInstruction(Address),
+}
+
+/// A single rewrite made by [`optimize`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Optimization {
+    /// A run of instructions with no effect, replaced by a jump past them.
+    SkippedNoOps { address: Address, skipped: Vec<Instruction>, jump_to: Address },
+    /// A conditional jump on a freshly computed constant, replaced by an unconditional one.
+    ConstantBranch { address: Address, original: Instruction, jump_to: Address },
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Optimization::SkippedNoOps { address, skipped, jump_to } => {
+                let skipped = skipped.iter()
+                    .map(|instruction| format!("`{}`", instruction))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{:>6}: skipped {}, jumping straight to {}", address, skipped, jump_to)
+            },
+            Optimization::ConstantBranch { address, original, jump_to } => {
+                write!(f, "{:>6}: `{}` is always taken, jumping straight to {}", address, original, jump_to)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OptimizationReport {
+    pub changes: Vec<Optimization>,
+    /// Reachable instructions that were left alone because they may be overwritten at runtime or
+    /// read as data.
+    pub protected: Vec<Address>,
+    /// Set if the program couldn't be analysed, in which case nothing was changed.
+    pub refused: Option<Refusal>,
+}
+
+impl fmt::Display for OptimizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(refusal) = &self.refused {
+            return writeln!(f, "left the program unchanged: {}", refusal);
+        }
+        writeln!(f, "made {} change(s)", self.changes.len())?;
+        for change in self.changes.iter() {
+            writeln!(f, "{}", change)?;
+        }
+        if !self.protected.is_empty() {
+            let protected = self.protected.iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(f, "left alone as possibly self-modified or data: {}", protected)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a linear walk of every path from the entry point found out about the program.
+#[derive(Default)]
+struct Analysis {
+    instructions: BTreeMap<Address, Instruction>,
+    jump_targets: BTreeSet<Address>,
+    written: BTreeSet<Address>,
+    read: BTreeSet<Address>,
+}
+
+impl Analysis {
+    fn of(program: &[MemoryValue], entry_point: Address) -> Result<Analysis, Refusal> {
+        let mut analysis = Analysis::default();
+        let mut pending = vec![entry_point];
+        analysis.jump_targets.insert(entry_point);
+
+        while let Some(address) = pending.pop() {
+            if analysis.instructions.contains_key(&address) {
+                continue;
+            }
+            let instruction = program.get(address..)
+                .and_then(|words| Instruction::decode(&mut words.iter().cloned()).ok())
+                .ok_or(Refusal::UndecodableInstruction(address))?;
+
+            let parameters = instruction.parameters();
+            for (index, parameter) in parameters.iter().enumerate() {
+                match parameter {
+                    Parameter::Relative(_) => return Err(Refusal::RelativeAddressing(address)),
+                    Parameter::Position(cell) if instruction.write_parameters().contains(&index) => {
+                        analysis.written.insert(*cell);
+                    },
+                    Parameter::Position(cell) => {
+                        analysis.read.insert(*cell);
+                    },
+                    Parameter::Immediate(_) => {},
+                }
+            }
+
+            let next = address + instruction.length();
+            match &instruction {
+                Instruction::Halt => {},
+                Instruction::JumpIfTrue(_, target) | Instruction::JumpIfFalse(_, target) => {
+                    let target = match target {
+                        Parameter::Immediate(target) if *target >= 0 => *target as Address,
+                        _ => return Err(Refusal::IndirectJump(address)),
+                    };
+                    match condition_of(&instruction) {
+                        Some(true) => pending.push(target),
+                        Some(false) => pending.push(next),
+                        None => pending.extend(&[target, next]),
+                    }
+                    analysis.jump_targets.insert(target);
+                },
+                _ => pending.push(next),
+            }
+            analysis.instructions.insert(address, instruction);
+        }
+        Ok(analysis)
+    }
+
+    /// Instructions that overlap each other, or whose words are written or read as data, are
+    /// off limits.
+    fn protected(&self) -> BTreeSet<Address> {
+        let mut owners = BTreeMap::new();
+        let mut protected = BTreeSet::new();
+        for (address, instruction) in self.instructions.iter() {
+            for cell in *address..*address + instruction.length() {
+                if self.written.contains(&cell) || self.read.contains(&cell) {
+                    protected.insert(*address);
+                }
+                if let Some(owner) = owners.insert(cell, *address) {
+                    protected.insert(owner);
+                    protected.insert(*address);
+                }
+            }
+        }
+        protected
+    }
+}
+
+/// Whether a conditional jump is always (`Some(true)`) or never (`Some(false)`) taken, judging
+/// only by the instruction itself.
+fn condition_of(instruction: &Instruction) -> Option<bool> {
+    match instruction {
+        Instruction::JumpIfTrue(Parameter::Immediate(value), _) => Some(*value != 0),
+        Instruction::JumpIfFalse(Parameter::Immediate(value), _) => Some(*value == 0),
+        _ => None,
+    }
+}
+
+fn unconditional_target(instruction: &Instruction) -> Option<Address> {
+    match (condition_of(instruction), instruction) {
+        (Some(true), Instruction::JumpIfTrue(_, Parameter::Immediate(target))) |
+        (Some(true), Instruction::JumpIfFalse(_, Parameter::Immediate(target))) => Some(*target as Address),
+        _ => None,
+    }
+}
+
+fn in_bounds(parameter: &Parameter, program: &[MemoryValue]) -> bool {
+    match parameter {
+        Parameter::Position(cell) => *cell < program.len(),
+        Parameter::Immediate(_) => true,
+        Parameter::Relative(_) => false,
+    }
+}
+
+/// Whether executing the instruction at `address` can only move on to the next instruction. Reads
+/// must be in bounds, or removing them would hide a fault.
+fn is_no_op(address: Address, instruction: &Instruction, program: &[MemoryValue]) -> bool {
+    match instruction {
+        Instruction::Add(a, Parameter::Immediate(0), destination) |
+        Instruction::Add(Parameter::Immediate(0), a, destination) |
+        Instruction::Multiply(a, Parameter::Immediate(1), destination) |
+        Instruction::Multiply(Parameter::Immediate(1), a, destination) => {
+            a == destination && in_bounds(a, program)
+        },
+        Instruction::JumpIfTrue(condition, Parameter::Immediate(target)) |
+        Instruction::JumpIfFalse(condition, Parameter::Immediate(target)) => {
+            in_bounds(condition, program)
+                && (*target as Address == address + instruction.length() || condition_of(instruction) == Some(false))
+        },
+        _ => false,
+    }
+}
+
+/// Folds `lt`/`eq` on two constants followed directly by a jump on the result, so long as
+/// nothing else can jump to the branch and see a different value.
+fn fold_branch(compare: &Instruction, branch: &Instruction) -> Option<Instruction> {
+    let (result, cell) = match compare {
+        Instruction::LessThan(Parameter::Immediate(a), Parameter::Immediate(b), Parameter::Position(cell)) => (a < b, cell),
+        Instruction::Equal(Parameter::Immediate(a), Parameter::Immediate(b), Parameter::Position(cell)) => (a == b, cell),
+        _ => return None,
+    };
+    let (taken, target) = match branch {
+        Instruction::JumpIfTrue(Parameter::Position(tested), target) if tested == cell => (result, *target),
+        Instruction::JumpIfFalse(Parameter::Position(tested), target) if tested == cell => (!result, *target),
+        _ => return None,
+    };
+    Some(Instruction::JumpIfTrue(Parameter::Immediate(taken as MemoryValue), target))
+}
+
+/// Rewrites wasteful instructions reachable from `entry_point`, keeping every instruction at its
+/// original address so that jumps and data references stay valid:
+///
+/// - runs of no-ops (adding zero to or multiplying by one a cell in place, jumps to the next
+///   instruction and jumps that are never taken) become a single jump past them, threaded through
+///   any unconditional jump found there;
+/// - a jump on the result of comparing two constants becomes unconditional, or a no-op.
+///
+/// Instructions that any reachable instruction writes to or reads from are left alone, and if
+/// the program jumps to computed addresses or uses relative addressing nothing is changed at all.
+pub fn optimize(program: &[MemoryValue], entry_point: Address) -> (Vec<MemoryValue>, OptimizationReport) {
+    let mut optimized = program.to_vec();
+    let mut report = OptimizationReport::default();
+    let analysis = match Analysis::of(program, entry_point) {
+        Ok(analysis) => analysis,
+        Err(refusal) => {
+            report.refused = Some(refusal);
+            return (optimized, report);
+        },
+    };
+    let protected = analysis.protected();
+    report.protected = protected.iter().cloned().collect();
+
+    // what each unprotected instruction will do once constant branches are folded
+    let mut effective = BTreeMap::new();
+    for (address, instruction) in analysis.instructions.iter() {
+        if !protected.contains(address) {
+            effective.insert(*address, instruction.clone());
+        }
+    }
+    for (address, compare) in analysis.instructions.iter() {
+        let branch_address = address + compare.length();
+        if protected.contains(address) || analysis.jump_targets.contains(&branch_address) {
+            continue;
+        }
+        let folded = match effective.get(&branch_address).and_then(|branch| fold_branch(compare, branch)) {
+            Some(folded) => folded,
+            None => continue,
+        };
+        if let Some(jump_to) = unconditional_target(&folded) {
+            report.changes.push(Optimization::ConstantBranch {
+                address: branch_address,
+                original: analysis.instructions[&branch_address].clone(),
+                jump_to,
+            });
+            write_instruction(&mut optimized, branch_address, &folded);
+        }
+        effective.insert(branch_address, folded);
+    }
+
+    let mut address = 0;
+    while let Some((&start, _)) = effective.range(address..).next() {
+        let mut end = start;
+        let mut skipped = Vec::new();
+        while let Some(instruction) = effective.get(&end).filter(|instruction| is_no_op(end, instruction, program)) {
+            skipped.push(analysis.instructions[&end].clone());
+            end += instruction.length();
+        }
+        if skipped.is_empty() {
+            address = start + 1;
+            continue;
+        }
+        address = end;
+
+        let mut jump_to = end;
+        for _ in 0..effective.len() {
+            match effective.get(&jump_to).and_then(unconditional_target) {
+                Some(target) if target != start => jump_to = target,
+                _ => break,
+            }
+        }
+        let jump = Instruction::JumpIfTrue(Parameter::Immediate(1), Parameter::Immediate(jump_to as MemoryValue));
+        if skipped.len() == 1 && jump_to == end && skipped[0] == jump {
+            continue;
+        }
+        write_instruction(&mut optimized, start, &jump);
+        report.changes.push(Optimization::SkippedNoOps { address: start, skipped, jump_to });
+    }
+    report.changes.sort_by_key(|change| match change {
+        Optimization::SkippedNoOps { address, .. } | Optimization::ConstantBranch { address, .. } => *address,
+    });
+
+    (optimized, report)
+}
+
+fn write_instruction(program: &mut [MemoryValue], address: Address, instruction: &Instruction) {
+    let words = instruction.encode().expect("optimizer only produces encodable instructions");
+    program[address..address + words.len()].copy_from_slice(&words);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, Instruction, Parameter, RecordedIO, SimpleMemory, TraceLevel};
+    use crate::intcode::optimize::{optimize, Optimization, Refusal};
+
+    fn run(program: &[i32], inputs: &[i32]) -> (Vec<i32>, usize) {
+        let mut memory = SimpleMemory::from_literal(program);
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(inputs.iter().cloned());
+        computer.run_until_halted().unwrap();
+        let outputs = computer.io_record.iter()
+            .filter_map(|event| match event {
+                RecordedIO::Output(value) => Some(*value),
+                RecordedIO::UserInput(_) => None,
+            })
+            .collect();
+        (outputs, computer.cycle_count())
+    }
+
+    #[test]
+    fn skips_runs_of_no_ops() {
+        // add [11], 0, [11]; jt 0, 0; out [11]; hlt
+        let program = [1001, 11, 0, 11, 1105, 0, 0, 4, 11, 99, 0, 42];
+        let (optimized, report) = optimize(&program, 0);
+        assert_eq!(optimized, vec![1105, 1, 7, 11, 1105, 0, 0, 4, 11, 99, 0, 42]);
+        assert_eq!(report.changes, vec![Optimization::SkippedNoOps {
+            address: 0,
+            skipped: vec![
+                Instruction::Add(Parameter::Position(11), Parameter::Immediate(0), Parameter::Position(11)),
+                Instruction::JumpIfTrue(Parameter::Immediate(0), Parameter::Immediate(0)),
+            ],
+            jump_to: 7,
+        }]);
+        assert_eq!(run(&program, &[]), (vec![42], 4));
+        assert_eq!(run(&optimized, &[]), (vec![42], 3));
+    }
+
+    #[test]
+    fn threads_jumps_through_unconditional_jumps() {
+        // mul [13], 1, [13]; jt [13], 7; jt 1, 10; hlt; out 5; hlt
+        let program = [1002, 13, 1, 13, 1005, 13, 7, 1105, 1, 10, 104, 5, 99, 0];
+        let (optimized, report) = optimize(&program, 0);
+        assert_eq!(&optimized[..3], &[1105, 1, 10]);
+        assert_eq!(report.changes.len(), 1);
+        assert_eq!(run(&optimized, &[]).0, run(&program, &[]).0);
+    }
+
+    #[test]
+    fn folds_constant_compare_then_branch() {
+        // lt 3, 5, [14]; jt [14], 11; out 0; hlt; out 1; hlt
+        let program = [1107, 3, 5, 14, 1005, 14, 11, 104, 0, 99, 0, 104, 1, 99, 0];
+        let (optimized, report) = optimize(&program, 0);
+        assert_eq!(&optimized[4..7], &[1105, 1, 11]);
+        assert_eq!(report.changes, vec![Optimization::ConstantBranch {
+            address: 4,
+            original: Instruction::JumpIfTrue(Parameter::Position(14), Parameter::Immediate(11)),
+            jump_to: 11,
+        }]);
+        assert_eq!(run(&optimized, &[]).0, vec![1]);
+
+        // eq 3, 5, [14] is never true, so the jump goes
+        let mut program = program;
+        program[0] = 1108;
+        let (optimized, report) = optimize(&program, 0);
+        assert!(matches!(report.changes.as_slice(), [Optimization::SkippedNoOps { address: 4, jump_to: 7, .. }]));
+        assert_eq!(run(&optimized, &[]).0, vec![0]);
+    }
+
+    #[test]
+    fn leaves_branches_other_code_can_jump_to() {
+        // in [17]; jt [17], 9; eq 3, 5, [17]; jt [17], 13; hlt; out 1; hlt - the second jump is
+        // also reached straight after input, when [17] may be anything
+        let program = [3, 17, 1005, 17, 9, 1108, 3, 5, 17, 1005, 17, 13, 99, 104, 1, 99, 0, 0];
+        let (optimized, report) = optimize(&program, 0);
+        assert_eq!(optimized, program.to_vec());
+        assert!(report.changes.is_empty());
+        assert_eq!(run(&optimized, &[1]).0, vec![1]);
+    }
+
+    #[test]
+    fn leaves_self_modified_code_and_data_alone() {
+        // add 0, 0, [7] rewrites the destination of the following no-op
+        let program = [1101, 0, 0, 7, 1001, 9, 0, 9, 99, 5];
+        let (optimized, report) = optimize(&program, 0);
+        assert_eq!(optimized, program.to_vec());
+        assert!(report.changes.is_empty());
+        assert_eq!(report.protected, vec![4]);
+
+        // out [3] reads the no-op at 2 as data
+        let program = [4, 3, 1001, 6, 0, 6, 99];
+        let (optimized, report) = optimize(&program, 0);
+        assert_eq!(optimized, program.to_vec());
+        assert_eq!(report.protected, vec![2, 6]);
+    }
+
+    #[test]
+    fn refuses_programs_it_cannot_follow() {
+        let (optimized, report) = optimize(&[105, 1, 4, 99, 3], 0);
+        assert_eq!(optimized, vec![105, 1, 4, 99, 3]);
+        assert_eq!(report.refused, Some(Refusal::IndirectJump(0)));
+        assert_eq!(optimize(&[204, 0, 99], 0).1.refused, Some(Refusal::RelativeAddressing(0)));
+        assert_eq!(optimize(&[1, 0, 0, 0], 0).1.refused, Some(Refusal::UndecodableInstruction(4)));
+    }
+
+    #[test]
+    fn keeps_day5_behaviour() {
+        let program = SimpleMemory::from_memory_file("input/day5.txt").unwrap();
+        let (optimized, _) = optimize(program.as_slice(), 0);
+        for input in &[1, 5] {
+            assert_eq!(run(&optimized, &[*input]).0, run(program.as_slice(), &[*input]).0);
+        }
+    }
+}
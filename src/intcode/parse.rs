@@ -0,0 +1,212 @@
+use failure::Fail;
+use super::MemoryValue;
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum ProgramParseErrorKind {
+    #[fail(display = "invalid token '{}'", _0)]
+    InvalidToken(String),
+    #[fail(display = "value '{}' does not fit in a memory slot", _0)]
+    ValueOutOfRange(String),
+    #[fail(display = "expected a value")]
+    MissingValue,
+    #[fail(display = "expected ',' between values")]
+    MissingSeparator,
+}
+
+/// A malformed program, along with where in the source it went wrong. `token_index` is the index
+/// of the value being parsed (and so the address it would have been loaded at), `line` and
+/// `column` are 1-based.
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+#[fail(display = "{} at token {} (line {}, column {})", kind, token_index, line, column)]
+pub struct ProgramParseError {
+    pub kind: ProgramParseErrorKind,
+    pub token_index: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+struct Scanner<'a> {
+    source: &'a str,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(source: &'a str) -> Scanner<'a> {
+        Scanner {
+            source,
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while let Some(c) = self.advance() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            } else if c.is_whitespace() {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn take_token(&mut self) -> &'a str {
+        let start = self.offset;
+        while let Some(c) = self.peek() {
+            if c == ',' || c == '#' || c.is_whitespace() {
+                break;
+            }
+            self.advance();
+        }
+        &self.source[start..self.offset]
+    }
+
+    fn error(&self, kind: ProgramParseErrorKind, token_index: usize) -> ProgramParseError {
+        ProgramParseError {
+            kind,
+            token_index,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+/// Parses a comma separated intcode program. Whitespace (including newlines) is permitted around
+/// values and `#` starts a comment running to the end of the line, but every other deviation from
+/// the format is reported rather than skipped, so that no value ends up at the wrong address.
+pub fn parse_program(source: &str) -> Result<Vec<MemoryValue>, ProgramParseError> {
+    let mut scanner = Scanner::new(source);
+    let mut values = Vec::new();
+
+    scanner.skip_whitespace_and_comments();
+    if scanner.peek().is_none() {
+        return Ok(values);
+    }
+
+    loop {
+        scanner.skip_whitespace_and_comments();
+        let (line, column) = (scanner.line, scanner.column);
+        let token = scanner.take_token();
+        if token.is_empty() {
+            return Err(scanner.error(ProgramParseErrorKind::MissingValue, values.len()));
+        }
+
+        let value = token.parse::<MemoryValue>()
+            .map_err(|_| {
+                let digits = token.trim_start_matches(['-', '+']);
+                let kind = if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                    ProgramParseErrorKind::ValueOutOfRange(token.into())
+                } else {
+                    ProgramParseErrorKind::InvalidToken(token.into())
+                };
+                ProgramParseError {
+                    kind,
+                    token_index: values.len(),
+                    line,
+                    column,
+                }
+            })?;
+        values.push(value);
+
+        scanner.skip_whitespace_and_comments();
+        match scanner.peek() {
+            None => break,
+            Some(',') => {
+                scanner.advance();
+            },
+            Some(_) => return Err(scanner.error(ProgramParseErrorKind::MissingSeparator, values.len())),
+        }
+    }
+
+    Ok(values)
+}
+
+/// Formats values in the comma separated form accepted by [`parse_program`].
+pub fn format_program(values: &[MemoryValue]) -> String {
+    values.iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::parse::{parse_program, ProgramParseError, ProgramParseErrorKind};
+
+    #[test]
+    fn can_parse_programs() {
+        assert_eq!(parse_program("1,0,0,3,99"), Ok(vec![1, 0, 0, 3, 99]));
+        assert_eq!(parse_program("1,0,0,3,99\n"), Ok(vec![1, 0, 0, 3, 99]));
+        assert_eq!(parse_program(" -1 , +2,\n\t3 "), Ok(vec![-1, 2, 3]));
+        assert_eq!(parse_program(""), Ok(vec![]));
+        assert_eq!(parse_program("  \n# nothing here\n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn can_parse_programs_with_comments() {
+        let source = "# adds two numbers\n1,5,6,0, # add\n99, # halt\n10,20\n";
+        assert_eq!(parse_program(source), Ok(vec![1, 5, 6, 0, 99, 10, 20]));
+    }
+
+    #[test]
+    fn reports_invalid_tokens_with_position() {
+        assert_eq!(parse_program("1,0,x0,3"), Err(ProgramParseError {
+            kind: ProgramParseErrorKind::InvalidToken("x0".into()),
+            token_index: 2,
+            line: 1,
+            column: 5,
+        }));
+        assert_eq!(parse_program("1,0,\n  0,3,99999999999"), Err(ProgramParseError {
+            kind: ProgramParseErrorKind::ValueOutOfRange("99999999999".into()),
+            token_index: 4,
+            line: 2,
+            column: 7,
+        }));
+    }
+
+    #[test]
+    fn reports_missing_values_and_separators() {
+        assert_eq!(parse_program("1,,2"), Err(ProgramParseError {
+            kind: ProgramParseErrorKind::MissingValue,
+            token_index: 1,
+            line: 1,
+            column: 3,
+        }));
+        assert_eq!(parse_program("1,2,"), Err(ProgramParseError {
+            kind: ProgramParseErrorKind::MissingValue,
+            token_index: 2,
+            line: 1,
+            column: 5,
+        }));
+        assert_eq!(parse_program("1,2\n3"), Err(ProgramParseError {
+            kind: ProgramParseErrorKind::MissingSeparator,
+            token_index: 2,
+            line: 2,
+            column: 1,
+        }));
+    }
+}
@@ -0,0 +1,277 @@
+use failure::Fail;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use super::{Address, ComputerError};
+
+/// What a [`ProtectedRegion`] forbids.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Protection {
+    /// Can be read and executed, but not written.
+    ReadOnly,
+    /// Can be read and written, but not executed.
+    NoExecute,
+    /// Can't be touched at all, to catch anything running off the end of a buffer or the stack.
+    Guard,
+}
+
+impl Protection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Protection::ReadOnly => "ro",
+            Protection::NoExecute => "nx",
+            Protection::Guard => "guard",
+        }
+    }
+
+    /// The byte a protection is stored as in a [`ProgramImage`](super::ProgramImage).
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Protection::ReadOnly => 0,
+            Protection::NoExecute => 1,
+            Protection::Guard => 2,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Protection> {
+        match byte {
+            0 => Some(Protection::ReadOnly),
+            1 => Some(Protection::NoExecute),
+            2 => Some(Protection::Guard),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Protection {
+    type Err = ProtectionParseError;
+
+    fn from_str(text: &str) -> Result<Protection, ProtectionParseError> {
+        match text {
+            "ro" | "read-only" => Ok(Protection::ReadOnly),
+            "nx" | "no-execute" => Ok(Protection::NoExecute),
+            "guard" => Ok(Protection::Guard),
+            _ => Err(ProtectionParseError::UnknownProtection(text.into())),
+        }
+    }
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum ProtectionParseError {
+    #[fail(display = "invalid region '{}', expected START..END:PROTECTION", _0)]
+    InvalidRegion(String),
+    #[fail(display = "unknown protection '{}', expected ro, nx or guard", _0)]
+    UnknownProtection(String),
+    #[fail(display = "region '{}' is empty", _0)]
+    EmptyRegion(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProtectedRegion {
+    pub range: Range<Address>,
+    pub protection: Protection,
+}
+
+impl FromStr for ProtectedRegion {
+    type Err = ProtectionParseError;
+
+    /// Parses `START..END:PROTECTION`, where END can be left off to protect everything from
+    /// START onwards.
+    fn from_str(text: &str) -> Result<ProtectedRegion, ProtectionParseError> {
+        let invalid = || ProtectionParseError::InvalidRegion(text.into());
+        let (range, protection) = text.split_once(':').ok_or_else(invalid)?;
+        let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+        let start = start.trim().parse().map_err(|_| invalid())?;
+        let end = match end.trim() {
+            "" => Address::MAX,
+            end => end.parse().map_err(|_| invalid())?,
+        };
+        if start >= end {
+            return Err(ProtectionParseError::EmptyRegion(text.into()));
+        }
+        Ok(ProtectedRegion {
+            range: start..end,
+            protection: protection.trim().parse()?,
+        })
+    }
+}
+
+impl fmt::Display for ProtectedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.range.end == Address::MAX {
+            write!(f, "{}..:{}", self.range.start, self.protection)
+        } else {
+            write!(f, "{}..{}:{}", self.range.start, self.range.end, self.protection)
+        }
+    }
+}
+
+/// The regions of memory a [`Computer`](super::Computer) refuses to write, execute or touch,
+/// each broken rule faulting with its own [`ComputerError`]. Regions may overlap, in which case
+/// an address gets the restrictions of all of them.
+///
+/// Written as text, a map is a comma separated list of regions such as `0..120:ro,500..:guard`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProtectionMap {
+    regions: Vec<ProtectedRegion>,
+}
+
+impl ProtectionMap {
+    pub fn new() -> ProtectionMap {
+        ProtectionMap::default()
+    }
+
+    pub fn with_region(mut self, range: Range<Address>, protection: Protection) -> ProtectionMap {
+        self.protect(range, protection);
+        self
+    }
+
+    pub fn protect(&mut self, range: Range<Address>, protection: Protection) {
+        self.regions.push(ProtectedRegion { range, protection });
+    }
+
+    /// Adds the regions of `other`, e.g. those given on the command line to those from an image.
+    pub fn extend(&mut self, other: &ProtectionMap) {
+        self.regions.extend(other.regions.iter().cloned());
+    }
+
+    pub fn regions(&self) -> &[ProtectedRegion] {
+        &self.regions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Whether any region covering `address` has the given protection.
+    pub fn is_protected(&self, address: Address, protection: Protection) -> bool {
+        self.regions.iter().any(|region| region.protection == protection && region.range.contains(&address))
+    }
+
+    pub fn check_read(&self, address: Address) -> Result<(), ComputerError> {
+        if self.is_protected(address, Protection::Guard) {
+            return Err(ComputerError::GuardRegionAccessed(address));
+        }
+        Ok(())
+    }
+
+    pub fn check_write(&self, address: Address) -> Result<(), ComputerError> {
+        self.check_read(address)?;
+        if self.is_protected(address, Protection::ReadOnly) {
+            return Err(ComputerError::WriteToReadOnlyMemory(address));
+        }
+        Ok(())
+    }
+
+    pub fn check_execute(&self, address: Address) -> Result<(), ComputerError> {
+        self.check_read(address)?;
+        if self.is_protected(address, Protection::NoExecute) {
+            return Err(ComputerError::ExecuteFromNoExecuteMemory(address));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ProtectionMap {
+    type Err = ProtectionParseError;
+
+    fn from_str(text: &str) -> Result<ProtectionMap, ProtectionParseError> {
+        let regions = text.split(',')
+            .map(str::trim)
+            .filter(|region| !region.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(ProtectionMap { regions })
+    }
+}
+
+impl fmt::Display for ProtectionMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, region) in self.regions.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", region)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, ComputerError, Fault, ProgramImage, SimpleMemory, TraceLevel};
+    use crate::intcode::protection::{Protection, ProtectionMap, ProtectionParseError};
+
+    fn run(program: &[i32], protection: ProtectionMap) -> Result<(), Fault> {
+        let mut memory = SimpleMemory::from_literal(program);
+        Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_protection(protection)
+            .run_until_halted()
+    }
+
+    #[test]
+    fn parses_and_displays_maps() {
+        let map = "0..120:ro, 120..200:nx,500..:guard".parse::<ProtectionMap>().unwrap();
+        assert_eq!(map, ProtectionMap::new()
+            .with_region(0..120, Protection::ReadOnly)
+            .with_region(120..200, Protection::NoExecute)
+            .with_region(500..usize::MAX, Protection::Guard));
+        assert_eq!(map.to_string(), "0..120:ro,120..200:nx,500..:guard");
+        assert_eq!("no-execute".parse(), Ok(Protection::NoExecute));
+
+        assert_eq!("0..10".parse::<ProtectionMap>(), Err(ProtectionParseError::InvalidRegion("0..10".into())));
+        assert_eq!("0..10:rw".parse::<ProtectionMap>(), Err(ProtectionParseError::UnknownProtection("rw".into())));
+        assert_eq!("10..10:ro".parse::<ProtectionMap>(), Err(ProtectionParseError::EmptyRegion("10..10:ro".into())));
+    }
+
+    #[test]
+    fn read_only_memory_cannot_be_written() {
+        // the add writes over the halt instruction that follows it
+        let program = [1101, 1, 2, 4, 99];
+        let fault = run(&program, ProtectionMap::new().with_region(0..5, Protection::ReadOnly)).unwrap_err();
+        assert_eq!(fault.error, ComputerError::WriteToReadOnlyMemory(4));
+        assert_eq!(fault.instruction_pointer, 0);
+        assert_eq!(fault.to_string().lines().next(), Some("attempted to write to read-only memory at 4 at address 0 (cycle 0) while executing `add 1, 2, [4]`"));
+
+        // reading and executing it is fine
+        assert_eq!(run(&[1, 0, 0, 5, 99, 0], ProtectionMap::new().with_region(0..5, Protection::ReadOnly)), Ok(()));
+    }
+
+    #[test]
+    fn no_execute_memory_cannot_be_jumped_into() {
+        let program = [1105, 1, 3, 99];
+        let fault = run(&program, ProtectionMap::new().with_region(3..4, Protection::NoExecute)).unwrap_err();
+        assert_eq!(fault.error, ComputerError::ExecuteFromNoExecuteMemory(3));
+        assert_eq!(fault.instruction_pointer, 3);
+        assert_eq!(fault.cycle, 1);
+
+        // it can still be read and written as data
+        assert_eq!(run(&[1, 5, 5, 5, 99, 7], ProtectionMap::new().with_region(5..6, Protection::NoExecute)), Ok(()));
+    }
+
+    #[test]
+    fn guard_regions_cannot_be_touched() {
+        let guard = || ProtectionMap::new().with_region(6..usize::MAX, Protection::Guard);
+        assert_eq!(run(&[4, 6, 99, 0, 0, 0, 0], guard()).unwrap_err().error, ComputerError::GuardRegionAccessed(6));
+        assert_eq!(run(&[1101, 1, 1, 6, 99, 0, 0], guard()).unwrap_err().error, ComputerError::GuardRegionAccessed(6));
+        assert_eq!(run(&[1106, 0, 6, 0, 0, 0, 99], guard()).unwrap_err().error, ComputerError::GuardRegionAccessed(6));
+        // immediate parameters don't touch memory
+        assert_eq!(run(&[104, 6, 99], guard()), Ok(()));
+    }
+
+    #[test]
+    fn images_carry_protection() {
+        let mut image = ProgramImage::new(vec![1105, 1, 3, 99]);
+        image.protection = "3..4:nx,4..:guard".parse().unwrap();
+        let decoded = ProgramImage::decode(&image.encode(true)).unwrap();
+        assert_eq!(decoded.protection, image.protection);
+        assert_eq!(run(&decoded.memory, decoded.protection).unwrap_err().error, ComputerError::ExecuteFromNoExecuteMemory(3));
+    }
+}
@@ -0,0 +1,347 @@
+use failure::Fail;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use super::{Address, ComputerError, ExecuteResult, Instruction, MemoryValue, Opcode, Parameter, ParameterMode};
+
+/// The view of a running machine given to opcode implementations.
+pub trait Machine {
+    fn read(&mut self, parameter: Parameter) -> Result<MemoryValue, ComputerError>;
+    fn write(&mut self, parameter: Parameter, value: MemoryValue) -> Result<(), ComputerError>;
+    fn input(&mut self) -> Result<MemoryValue, ComputerError>;
+    fn output(&mut self, value: MemoryValue) -> Result<(), ComputerError>;
+    fn halt(&mut self);
+    fn relative_base(&self) -> MemoryValue;
+    fn adjust_relative_base(&mut self, delta: MemoryValue) -> Result<(), ComputerError>;
+    fn instruction_pointer(&self) -> Address;
+    fn cycle_count(&self) -> usize;
+}
+
+pub type ExecuteFn = dyn Fn(&mut dyn Machine, &[Parameter]) -> Result<ExecuteResult, ComputerError> + Send + Sync;
+
+/// Describes how an opcode is laid out in memory and how it should be shown.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OpcodeSpec {
+    pub number: MemoryValue,
+    pub mnemonic: String,
+    pub parameter_count: usize,
+    pub write_parameters: Vec<usize>,
+}
+
+impl OpcodeSpec {
+    /// Whether this is the layout and mnemonic the built-in `opcode` is registered with, so its
+    /// instructions can be decoded as the matching [`Instruction`] variant rather than as
+    /// [`Instruction::Custom`].
+    pub fn is_builtin(&self, opcode: Opcode) -> bool {
+        self.number == opcode.encode()
+            && self.mnemonic == opcode.mnemonic()
+            && self.parameter_count == opcode.parameter_count()
+            && self.write_parameters == opcode.write_parameters()
+    }
+}
+
+#[derive(Clone)]
+pub struct OpcodeDefinition {
+    pub spec: Arc<OpcodeSpec>,
+    execute: Arc<ExecuteFn>,
+}
+
+impl fmt::Debug for OpcodeDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpcodeDefinition")
+            .field("spec", &self.spec)
+            .finish()
+    }
+}
+
+/// The most parameters an opcode can have. A header holds one mode digit per parameter above its
+/// two opcode digits, and eight digits of relative mode would overflow a memory value.
+pub const MAX_PARAMETER_COUNT: usize = 7;
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum RegistryError {
+    #[fail(display = "opcode {} is outside of the range 1-99", _0)]
+    OpcodeOutOfRange(MemoryValue),
+    #[fail(display = "opcode {} is already registered", _0)]
+    AlreadyRegistered(MemoryValue),
+    #[fail(display = "opcode {} has {} parameters, but an instruction header only has room for the modes of 7", _0, _1)]
+    TooManyParameters(MemoryValue, usize),
+    #[fail(display = "opcode {} only has {} parameters but parameter {} is marked as written", opcode, parameter_count, parameter)]
+    InvalidWriteParameter { opcode: MemoryValue, parameter_count: usize, parameter: usize },
+}
+
+/// Maps opcode numbers to their layout and implementation. Computers decode and execute every
+/// instruction, the built-in ones included, through a registry.
+#[derive(Clone, Debug, Default)]
+pub struct OpcodeRegistry {
+    definitions: HashMap<MemoryValue, OpcodeDefinition>,
+}
+
+impl OpcodeRegistry {
+    pub fn empty() -> OpcodeRegistry {
+        OpcodeRegistry::default()
+    }
+
+    /// A registry containing only the built-in opcodes, shared between every computer that does
+    /// not ask for anything else.
+    pub fn builtin() -> Arc<OpcodeRegistry> {
+        static BUILTIN: OnceLock<Arc<OpcodeRegistry>> = OnceLock::new();
+        BUILTIN.get_or_init(|| Arc::new(OpcodeRegistry::with_builtins())).clone()
+    }
+
+    pub fn with_builtins() -> OpcodeRegistry {
+        let mut registry = OpcodeRegistry::empty();
+        registry.register_builtins().expect("built-in opcodes conflict with each other");
+        registry
+    }
+
+    pub fn register<F>(&mut self, number: MemoryValue, mnemonic: &str, parameter_count: usize, write_parameters: &[usize], execute: F) -> Result<(), RegistryError>
+        where F: Fn(&mut dyn Machine, &[Parameter]) -> Result<ExecuteResult, ComputerError> + Send + Sync + 'static {
+        if !(1..=99).contains(&number) {
+            return Err(RegistryError::OpcodeOutOfRange(number));
+        }
+        if self.definitions.contains_key(&number) {
+            return Err(RegistryError::AlreadyRegistered(number));
+        }
+        if parameter_count > MAX_PARAMETER_COUNT {
+            return Err(RegistryError::TooManyParameters(number, parameter_count));
+        }
+        if let Some(parameter) = write_parameters.iter().find(|parameter| **parameter >= parameter_count) {
+            return Err(RegistryError::InvalidWriteParameter { opcode: number, parameter_count, parameter: *parameter });
+        }
+
+        self.definitions.insert(number, OpcodeDefinition {
+            spec: Arc::new(OpcodeSpec {
+                number,
+                mnemonic: mnemonic.into(),
+                parameter_count,
+                write_parameters: write_parameters.to_vec(),
+            }),
+            execute: Arc::new(execute),
+        });
+        Ok(())
+    }
+
+    pub fn get(&self, number: MemoryValue) -> Option<&OpcodeDefinition> {
+        self.definitions.get(&number)
+    }
+
+    pub fn decode<I: Iterator<Item=MemoryValue>>(&self, instruction_stream: &mut I) -> Result<Instruction, ComputerError> {
+        let header = instruction_stream.next().ok_or(ComputerError::InstructionDecodeFailed)?;
+        let number = header % 100;
+        let definition = self.get(number).ok_or(ComputerError::UnknownOpcode(header))?;
+        let modes = ParameterMode::decode_all(header / 100)?;
+
+        let mut parameters = Vec::with_capacity(definition.spec.parameter_count);
+        for index in 0..definition.spec.parameter_count {
+            let value = instruction_stream.next().ok_or(ComputerError::InstructionDecodeFailed)?;
+            let mode = modes.get(index).cloned().unwrap_or(ParameterMode::Position);
+            parameters.push(mode.wrap(value)?);
+        }
+
+        Ok(match Opcode::decode(number) {
+            Ok(opcode) if definition.spec.is_builtin(opcode) => Instruction::from_parts(opcode, &parameters),
+            _ => Instruction::Custom(definition.spec.clone(), parameters),
+        })
+    }
+
+    pub fn execute(&self, machine: &mut dyn Machine, instruction: &Instruction) -> Result<ExecuteResult, ComputerError> {
+        let number = instruction.opcode_number();
+        let definition = self.get(number).ok_or(ComputerError::UnknownOpcode(number))?;
+        (definition.execute)(machine, &instruction.parameters())
+    }
+
+    fn register_builtins(&mut self) -> Result<(), RegistryError> {
+        fn compare(machine: &mut dyn Machine, parameters: &[Parameter], predicate: fn(MemoryValue, MemoryValue) -> bool) -> Result<ExecuteResult, ComputerError> {
+            let outcome = predicate(machine.read(parameters[0])?, machine.read(parameters[1])?);
+            machine.write(parameters[2], if outcome { 1 } else { 0 })?;
+            Ok(ExecuteResult::AdvanceBy(4))
+        }
+
+        fn jump(machine: &mut dyn Machine, parameters: &[Parameter], when: bool) -> Result<ExecuteResult, ComputerError> {
+            if (machine.read(parameters[0])? != 0) == when {
+                Ok(ExecuteResult::JumpTo(machine.read(parameters[1])? as Address))
+            } else {
+                Ok(ExecuteResult::AdvanceBy(3))
+            }
+        }
+
+        let builtin = |opcode: Opcode| (opcode.encode(), opcode.mnemonic(), opcode.parameter_count(), opcode.write_parameters());
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::Add);
+        self.register(number, mnemonic, count, writes, |machine, parameters| {
+            // arithmetic wraps on overflow, as the static analysis assumes
+            let value = machine.read(parameters[0])?.wrapping_add(machine.read(parameters[1])?);
+            machine.write(parameters[2], value)?;
+            Ok(ExecuteResult::AdvanceBy(4))
+        })?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::Multiply);
+        self.register(number, mnemonic, count, writes, |machine, parameters| {
+            let value = machine.read(parameters[0])?.wrapping_mul(machine.read(parameters[1])?);
+            machine.write(parameters[2], value)?;
+            Ok(ExecuteResult::AdvanceBy(4))
+        })?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::Input);
+        self.register(number, mnemonic, count, writes, |machine, parameters| {
+            let value = machine.input()?;
+            machine.write(parameters[0], value)?;
+            Ok(ExecuteResult::AdvanceBy(2))
+        })?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::Output);
+        self.register(number, mnemonic, count, writes, |machine, parameters| {
+            let value = machine.read(parameters[0])?;
+            machine.output(value)?;
+            Ok(ExecuteResult::AdvanceBy(2))
+        })?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::JumpIfTrue);
+        self.register(number, mnemonic, count, writes, |machine, parameters| jump(machine, parameters, true))?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::JumpIfFalse);
+        self.register(number, mnemonic, count, writes, |machine, parameters| jump(machine, parameters, false))?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::LessThan);
+        self.register(number, mnemonic, count, writes, |machine, parameters| compare(machine, parameters, |a, b| a < b))?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::Equal);
+        self.register(number, mnemonic, count, writes, |machine, parameters| compare(machine, parameters, |a, b| a == b))?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::AdjustRelativeBase);
+        self.register(number, mnemonic, count, writes, |machine, parameters| {
+            let delta = machine.read(parameters[0])?;
+            machine.adjust_relative_base(delta)?;
+            Ok(ExecuteResult::AdvanceBy(2))
+        })?;
+
+        let (number, mnemonic, count, writes) = builtin(Opcode::Halt);
+        self.register(number, mnemonic, count, writes, |machine, _| {
+            machine.halt();
+            Ok(ExecuteResult::AdvanceBy(1))
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, ComputerError, ExecuteResult, Instruction, Memory, Parameter, RecordedIO, SimpleMemory, TraceLevel};
+    use crate::intcode::disassemble::disassemble_with;
+    use crate::intcode::registry::{OpcodeRegistry, RegistryError, MAX_PARAMETER_COUNT};
+    use std::sync::Arc;
+
+    fn registry_with_swap() -> OpcodeRegistry {
+        let mut registry = OpcodeRegistry::with_builtins();
+        registry.register(20, "swap", 2, &[0, 1], |machine, parameters| {
+            let a = machine.read(parameters[0])?;
+            let b = machine.read(parameters[1])?;
+            machine.write(parameters[0], b)?;
+            machine.write(parameters[1], a)?;
+            Ok(ExecuteResult::AdvanceBy(3))
+        }).unwrap();
+        registry
+    }
+
+    #[test]
+    fn rejects_invalid_registrations() {
+        let mut registry = OpcodeRegistry::with_builtins();
+        let nop = |_: &mut dyn crate::intcode::registry::Machine, _: &[Parameter]| Ok(ExecuteResult::AdvanceBy(1));
+        assert_eq!(registry.register(1, "dup", 0, &[], nop), Err(RegistryError::AlreadyRegistered(1)));
+        assert_eq!(registry.register(100, "big", 0, &[], nop), Err(RegistryError::OpcodeOutOfRange(100)));
+        assert_eq!(registry.register(0, "zero", 0, &[], nop), Err(RegistryError::OpcodeOutOfRange(0)));
+        assert_eq!(registry.register(30, "bad", 1, &[1], nop), Err(RegistryError::InvalidWriteParameter {
+            opcode: 30,
+            parameter_count: 1,
+            parameter: 1,
+        }));
+        assert_eq!(registry.register(30, "wide", 8, &[], nop), Err(RegistryError::TooManyParameters(30, 8)));
+        assert_eq!(registry.register(30, "nop", 0, &[], nop), Ok(()));
+        assert_eq!(registry.register(31, "widest", MAX_PARAMETER_COUNT, &[], nop), Ok(()));
+
+        let memory = SimpleMemory::from_literal(&[222222231, 1, 2, 3, 4, 5, 6, 7]);
+        let instruction = registry.decode(&mut memory.read_stream_from(0).unwrap()).unwrap();
+        assert_eq!(instruction.encode(), Ok(vec![222222231, 1, 2, 3, 4, 5, 6, 7]));
+    }
+
+    #[test]
+    fn can_execute_custom_opcodes() {
+        let registry = Arc::new(registry_with_swap());
+        let mut memory = SimpleMemory::from_literal(&[20, 4, 5, 99, 7, 9]);
+        let mut computer = Computer::new(&mut memory).with_registry(registry.clone());
+        computer.run_until_halted().unwrap();
+        assert_eq!(memory.read_slot(4), Ok(9));
+        assert_eq!(memory.read_slot(5), Ok(7));
+    }
+
+    #[test]
+    fn custom_opcodes_are_unknown_without_registration() {
+        let mut memory = SimpleMemory::from_literal(&[20, 4, 5, 99, 7, 9]);
+        let mut computer = Computer::new(&mut memory);
+        let fault = computer.run_until_halted().unwrap_err();
+        assert_eq!(fault.error, ComputerError::UnknownOpcode(20));
+    }
+
+    #[test]
+    fn disassembly_uses_custom_mnemonics() {
+        let registry = registry_with_swap();
+        let memory = SimpleMemory::from_literal(&[20, 4, 5, 99]);
+        let lines = disassemble_with(&registry, &memory, 0, 2);
+        assert_eq!(lines[0].instruction.as_ref().map(|instruction| instruction.to_string()), Ok("swap [4], [5]".into()));
+        match &lines[0].instruction {
+            Ok(Instruction::Custom(spec, parameters)) => {
+                assert_eq!(spec.number, 20);
+                assert_eq!(parameters, &vec![Parameter::Position(4), Parameter::Position(5)]);
+            },
+            other => panic!("expected a custom instruction, got {:?}", other),
+        }
+        assert_eq!(lines[1].instruction, Ok(Instruction::Halt));
+    }
+
+    #[test]
+    fn builtin_numbers_can_be_given_other_opcodes() {
+        let mut registry = OpcodeRegistry::empty();
+        registry.register(1, "out2", 2, &[], |machine, parameters| {
+            let value = machine.read(parameters[0])? * 10 + machine.read(parameters[1])?;
+            machine.output(value)?;
+            Ok(ExecuteResult::AdvanceBy(3))
+        }).unwrap();
+        registry.register(99, "hlt", 0, &[], |machine, _| {
+            machine.halt();
+            Ok(ExecuteResult::AdvanceBy(1))
+        }).unwrap();
+
+        let mut memory = SimpleMemory::from_literal(&[1101, 4, 2, 99]);
+        let instruction = registry.decode(&mut memory.read_stream_from(0).unwrap()).unwrap();
+        assert_eq!(instruction.to_string(), "out2 4, 2");
+        assert!(matches!(instruction, Instruction::Custom(..)));
+        assert_eq!(registry.decode(&mut memory.read_stream_from(3).unwrap()), Ok(Instruction::Halt));
+
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_registry(Arc::new(registry));
+        computer.run_until_halted().unwrap();
+        assert_eq!(computer.io_record, vec![RecordedIO::Output(42)]);
+    }
+
+    #[test]
+    fn arithmetic_wraps_on_overflow() {
+        let mut memory = SimpleMemory::from_literal(&[1101, 2147483647, 1, 0, 1102, 65536, 65536, 1, 99]);
+        Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .run_until_halted()
+            .unwrap();
+        assert_eq!(memory.read_slot(0), Ok(i32::MIN));
+        assert_eq!(memory.read_slot(1), Ok(0));
+    }
+
+    #[test]
+    fn custom_instructions_encode() {
+        let registry = registry_with_swap();
+        let memory = SimpleMemory::from_literal(&[20, 4, 5]);
+        let instruction = registry.decode(&mut memory.read_stream_from(0).unwrap()).unwrap();
+        assert_eq!(instruction.encode(), Ok(vec![20, 4, 5]));
+    }
+}
@@ -0,0 +1,252 @@
+use failure::{Fail, ResultExt};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use super::{Address, Computer, ComputerError, Fault, Instruction, Memory, MemoryValue, RecordedIO};
+use super::disassemble::DisassemblyLine;
+
+/// Events are written one per line as `in VALUE` or `out VALUE`.
+impl fmt::Display for RecordedIO {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordedIO::UserInput(value) => write!(f, "in {}", value),
+            RecordedIO::Output(value) => write!(f, "out {}", value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum RecordParseError {
+    #[fail(display = "line {}: expected `in VALUE` or `out VALUE`, found {:?}", line, text)]
+    InvalidEvent { line: usize, text: String },
+}
+
+impl FromStr for RecordedIO {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let event = match (parts.next(), parts.next().map(|value| value.parse::<MemoryValue>())) {
+            (Some("in"), Some(Ok(value))) => RecordedIO::UserInput(value),
+            (Some("out"), Some(Ok(value))) => RecordedIO::Output(value),
+            _ => return Err(()),
+        };
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(event),
+        }
+    }
+}
+
+/// Parses a recording, ignoring blank lines and `#` comments.
+pub fn parse_io_record(text: &str) -> Result<Vec<RecordedIO>, RecordParseError> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| line.parse().map_err(|_| RecordParseError::InvalidEvent {
+            line: index + 1,
+            text: line.into(),
+        }))
+        .collect()
+}
+
+pub fn format_io_record(record: &[RecordedIO]) -> String {
+    record.iter()
+        .map(|event| format!("{}\n", event))
+        .collect()
+}
+
+pub fn load_io_record<P: AsRef<Path>>(path: P) -> Result<Vec<RecordedIO>, Box<dyn Error>> {
+    Ok(parse_io_record(&std::fs::read_to_string(path)?).compat()?)
+}
+
+pub fn save_io_record<P: AsRef<Path>>(path: P, record: &[RecordedIO]) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, format_io_record(record))?;
+    Ok(())
+}
+
+/// The first point at which a replayed run stopped agreeing with its recording.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    /// Index into the recording of the event that didn't match.
+    pub event: usize,
+    pub cycle: usize,
+    pub instruction_pointer: Address,
+    pub instruction: Option<Instruction>,
+    /// The recorded event, or `None` if the recording had already ended.
+    pub expected: Option<RecordedIO>,
+    /// What the program did instead: an output, a request for input, or `None` if it halted.
+    pub actual: Option<ReplayEvent>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReplayEvent {
+    Output(MemoryValue),
+    WantedInput,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replay diverged at event {} (cycle {}, address {}", self.event, self.cycle, self.instruction_pointer)?;
+        if let Some(instruction) = &self.instruction {
+            write!(f, " `{}`", instruction)?;
+        }
+        write!(f, ")")?;
+        match &self.expected {
+            Some(event) => write!(f, "\n  - {}", event)?,
+            None => write!(f, "\n  - (end of recording)")?,
+        }
+        match &self.actual {
+            Some(ReplayEvent::Output(value)) => write!(f, "\n  + out {}", value),
+            Some(ReplayEvent::WantedInput) => write!(f, "\n  + in ?"),
+            None => write!(f, "\n  + (halted)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum ReplayError {
+    #[fail(display = "{}", _0)]
+    Diverged(Divergence),
+    #[fail(display = "{}", _0)]
+    Faulted(#[cause] Fault),
+}
+
+/// Runs `computer` to completion, feeding it the inputs from `record` as it asks for them and
+/// checking every output against it. Stops at the first event that doesn't match, including
+/// the program halting early or carrying on after the recording ends.
+pub fn replay<M: Memory>(computer: &mut Computer<M>, record: &[RecordedIO]) -> Result<(), ReplayError> {
+    computer.set_interactive_input(false);
+    let mut event = 0;
+    let mut checked = computer.io_record.len();
+
+    while !computer.halted {
+        let cycle = computer.cycle_count();
+        let instruction_pointer = computer.instruction_pointer();
+        let result = computer.step();
+        let diverged = |event, actual| {
+            let memory: &M = computer.memory();
+            ReplayError::Diverged(Divergence {
+                event,
+                cycle,
+                instruction_pointer,
+                instruction: DisassemblyLine::decode_at_with(computer.registry(), memory, instruction_pointer)
+                    .and_then(|line| line.instruction.ok()),
+                expected: record.get(event).cloned(),
+                actual,
+            })
+        };
+
+        for happened in computer.io_record[checked..].iter() {
+            // inputs came from the recording, so only outputs need checking
+            match happened {
+                RecordedIO::Output(value) if record.get(event) != Some(happened) => {
+                    return Err(diverged(event, Some(ReplayEvent::Output(*value))));
+                },
+                _ => event += 1,
+            }
+        }
+        checked = computer.io_record.len();
+
+        match result {
+            Ok(()) => {},
+            Err(ref fault) if fault.error == ComputerError::NoInputAvailable => match record.get(event) {
+                Some(RecordedIO::UserInput(value)) => computer.push_input(*value),
+                _ => return Err(diverged(event, Some(ReplayEvent::WantedInput))),
+            },
+            Err(fault) => return Err(ReplayError::Faulted(fault)),
+        }
+    }
+
+    if event < record.len() {
+        let cycle = computer.cycle_count();
+        return Err(ReplayError::Diverged(Divergence {
+            event,
+            cycle,
+            instruction_pointer: computer.instruction_pointer(),
+            instruction: None,
+            expected: record.get(event).cloned(),
+            actual: None,
+        }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, Instruction, Parameter, RecordedIO, SimpleMemory, TraceLevel};
+    use crate::intcode::replay::{format_io_record, load_io_record, parse_io_record, replay, RecordParseError, ReplayError, ReplayEvent};
+
+    /// Doubles each input until it reads a zero.
+    fn doubler() -> SimpleMemory {
+        SimpleMemory::from_literal(&[
+            3, 15,
+            1006, 15, 14,
+            102, 2, 15, 15,
+            4, 15,
+            1105, 1, 0,
+            99,
+            0,
+        ])
+    }
+
+    #[test]
+    fn can_save_and_load_records() {
+        let record = vec![RecordedIO::UserInput(5), RecordedIO::Output(-10)];
+        let text = format_io_record(&record);
+        assert_eq!(text, "in 5\nout -10\n");
+        assert_eq!(parse_io_record(&format!("# a session\n\n{}", text)), Ok(record));
+        assert_eq!(parse_io_record("in 5\nout\n"), Err(RecordParseError::InvalidEvent { line: 2, text: "out".into() }));
+    }
+
+    #[test]
+    fn replays_matching_sessions() {
+        let mut memory = doubler();
+        let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        replay(&mut computer, &parse_io_record("in 3\nout 6\nin 21\nout 42\nin 0\n").unwrap()).unwrap();
+        assert!(computer.halted);
+    }
+
+    #[test]
+    fn stops_at_the_first_divergence() {
+        let mut memory = doubler();
+        let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let error = replay(&mut computer, &parse_io_record("in 3\nout 6\nin 21\nout 41\nin 0\n").unwrap()).unwrap_err();
+        let divergence = match error {
+            ReplayError::Diverged(divergence) => divergence,
+            other => panic!("unexpected replay error: {}", other),
+        };
+        assert_eq!(divergence.event, 3);
+        assert_eq!(divergence.instruction_pointer, 9);
+        assert_eq!(divergence.instruction, Some(Instruction::Output(Parameter::Position(15))));
+        assert_eq!(divergence.actual, Some(ReplayEvent::Output(42)));
+        assert_eq!(divergence.to_string(), [
+            format!("replay diverged at event 3 (cycle {}, address 9 `out [15]`)", divergence.cycle),
+            "  - out 41".into(),
+            "  + out 42".into(),
+        ].join("\n"));
+
+        let mut memory = doubler();
+        let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let error = replay(&mut computer, &parse_io_record("in 3\nin 4\n").unwrap()).unwrap_err();
+        assert!(error.to_string().ends_with("  - in 4\n  + out 6"));
+
+        let mut memory = doubler();
+        let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let error = replay(&mut computer, &parse_io_record("in 0\nout 0\n").unwrap()).unwrap_err();
+        assert!(error.to_string().ends_with("  - out 0\n  + (halted)"));
+    }
+
+    #[test]
+    fn replays_recorded_day5_sessions() {
+        for (session, input) in &[("input/day5_part1.io", 1), ("input/day5_part2.io", 5)] {
+            let record = load_io_record(session).unwrap();
+            assert_eq!(record.first(), Some(&RecordedIO::UserInput(*input)));
+            let mut memory = SimpleMemory::from_memory_file("input/day5.txt").unwrap();
+            let mut computer = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+            replay(&mut computer, &record).unwrap();
+        }
+    }
+}
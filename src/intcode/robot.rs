@@ -0,0 +1,252 @@
+use failure::Fail;
+use std::collections::{HashMap, HashSet};
+use crate::grid::{Direction, Point};
+use super::{Computer, ComputerError, Fault, Memory, MemoryValue, RecordedIO};
+use super::framebuffer::{Framebuffer, Palette};
+
+pub const BLACK: MemoryValue = 0;
+pub const WHITE: MemoryValue = 1;
+
+/// What the second value of each `(paint, turn)` pair means.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TurnConvention {
+    /// 0 turns left and 1 turns right.
+    ZeroIsLeft,
+    /// 0 turns right and 1 turns left.
+    ZeroIsRight,
+}
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum RobotError {
+    #[fail(display = "brain asked the robot to turn {}, which is neither left nor right", _0)]
+    InvalidTurn(MemoryValue),
+    #[fail(display = "brain faulted: {}", _0)]
+    BrainFault(#[cause] Fault),
+}
+
+/// The panels of a hull, all black until painted.
+#[derive(Clone, Debug, Default)]
+pub struct Hull {
+    panels: HashMap<Point, MemoryValue>,
+    painted: HashSet<Point>,
+}
+
+impl Hull {
+    pub fn colour_at(&self, position: Point) -> MemoryValue {
+        self.panels.get(&position).cloned().unwrap_or(BLACK)
+    }
+
+    pub fn paint(&mut self, position: Point, colour: MemoryValue) {
+        self.panels.insert(position, colour);
+        self.painted.insert(position);
+    }
+
+    /// How many panels have been painted at least once, whatever colour they are now.
+    pub fn painted_count(&self) -> usize {
+        self.painted.len()
+    }
+
+    /// The hull as a [`Framebuffer`], flipped so that up is towards the top of the screen.
+    pub fn to_framebuffer(&self) -> Framebuffer {
+        let mut screen = Framebuffer::new().with_score_position(MemoryValue::MIN, MemoryValue::MIN);
+        for ((x, y), colour) in self.panels.iter() {
+            screen.draw(*x, -*y, *colour);
+        }
+        screen
+    }
+
+    /// Renders the hull with `#` for white panels and spaces for black ones.
+    pub fn render_text(&self) -> String {
+        self.to_framebuffer().render_text(&hull_palette())
+    }
+
+    pub fn save_ppm<P: AsRef<std::path::Path>>(&self, path: P, scale: usize) -> std::io::Result<()> {
+        self.to_framebuffer().save_ppm(path, &hull_palette(), scale)
+    }
+}
+
+pub fn hull_palette() -> Palette {
+    Palette::empty()
+        .with_tile(BLACK, ' ', [0, 0, 0])
+        .with_tile(WHITE, '#', [255, 255, 255])
+}
+
+/// A robot that walks a hull under the control of an intcode brain. Each time the brain wants
+/// input it is given the colour of the panel under the robot; it answers with a colour to paint
+/// that panel and a direction to turn, after which the robot moves forward one panel.
+pub struct HullRobot {
+    position: Point,
+    facing: Direction,
+    turns: TurnConvention,
+    starting_colour: Option<MemoryValue>,
+    pub hull: Hull,
+}
+
+impl HullRobot {
+    pub fn new() -> HullRobot {
+        HullRobot {
+            position: (0, 0),
+            facing: Direction::Up,
+            turns: TurnConvention::ZeroIsLeft,
+            starting_colour: None,
+            hull: Hull::default(),
+        }
+    }
+
+    pub fn with_origin(mut self, origin: Point) -> HullRobot {
+        self.position = origin;
+        self
+    }
+
+    pub fn with_facing(mut self, facing: Direction) -> HullRobot {
+        self.facing = facing;
+        self
+    }
+
+    pub fn with_turn_convention(mut self, turns: TurnConvention) -> HullRobot {
+        self.turns = turns;
+        self
+    }
+
+    /// Sets the colour of the panel the robot starts on, wherever its origin is, once it starts
+    /// running. This doesn't count as painting it.
+    pub fn with_starting_colour(mut self, colour: MemoryValue) -> HullRobot {
+        self.starting_colour = Some(colour);
+        self
+    }
+
+    pub fn position(&self) -> Point {
+        self.position
+    }
+
+    pub fn facing(&self) -> Direction {
+        self.facing
+    }
+
+    /// Runs the brain until it halts.
+    pub fn run<M: Memory>(&mut self, brain: &mut Computer<M>) -> Result<(), RobotError> {
+        if let Some(colour) = self.starting_colour.take() {
+            self.hull.panels.insert(self.position, colour);
+        }
+        brain.set_interactive_input(false);
+        let mut consumed = brain.io_record.len();
+        let mut pending = Vec::with_capacity(2);
+        while !brain.halted {
+            let result = brain.step();
+            for entry in brain.io_record[consumed..].iter() {
+                if let RecordedIO::Output(value) = entry {
+                    pending.push(*value);
+                    if pending.len() == 2 {
+                        self.act(pending[0], pending[1])?;
+                        pending.clear();
+                    }
+                }
+            }
+            consumed = brain.io_record.len();
+            match result {
+                Ok(()) => {},
+                Err(ref fault) if fault.error == ComputerError::NoInputAvailable => {
+                    brain.push_input(self.hull.colour_at(self.position));
+                },
+                Err(fault) => return Err(RobotError::BrainFault(fault)),
+            }
+        }
+        Ok(())
+    }
+
+    fn act(&mut self, paint: MemoryValue, turn: MemoryValue) -> Result<(), RobotError> {
+        let turn_left = match (self.turns, turn) {
+            (TurnConvention::ZeroIsLeft, 0) | (TurnConvention::ZeroIsRight, 1) => true,
+            (TurnConvention::ZeroIsLeft, 1) | (TurnConvention::ZeroIsRight, 0) => false,
+            _ => return Err(RobotError::InvalidTurn(turn)),
+        };
+        self.hull.paint(self.position, paint);
+        self.facing = if turn_left { self.facing.turn_left() } else { self.facing.turn_right() };
+        self.position = self.facing.step(self.position);
+        Ok(())
+    }
+}
+
+impl Default for HullRobot {
+    fn default() -> Self {
+        HullRobot::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::Direction;
+    use crate::intcode::{Computer, RecordedIO, SimpleMemory, TraceLevel};
+    use crate::intcode::robot::{HullRobot, RobotError, TurnConvention, BLACK, WHITE};
+
+    /// A brain that replays a fixed list of (paint, turn) pairs, reading a colour before each and
+    /// once more at the end.
+    fn scripted_brain(moves: &[(i32, i32)]) -> SimpleMemory {
+        let mut program = Vec::new();
+        for (paint, turn) in moves {
+            program.extend(&[3, 0, 104, *paint, 104, *turn]);
+        }
+        program.extend(&[3, 0, 99]);
+        SimpleMemory::from_literal(&program)
+    }
+
+    #[test]
+    fn paints_the_example_hull() {
+        let mut memory = scripted_brain(&[(1, 0), (0, 0), (1, 0), (1, 0), (0, 1), (1, 0), (1, 0)]);
+        let mut brain = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let mut robot = HullRobot::new();
+        robot.run(&mut brain).unwrap();
+
+        assert_eq!(robot.hull.painted_count(), 6);
+        assert_eq!(robot.position(), (0, 1));
+        assert_eq!(robot.facing(), Direction::Left);
+        assert_eq!(robot.hull.render_text(), "  #\n  #\n##\n");
+    }
+
+    #[test]
+    fn starting_colour_applies_to_the_origin_given_later() {
+        let mut memory = SimpleMemory::from_literal(&[3, 0, 99]);
+        let mut brain = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let mut robot = HullRobot::new()
+            .with_starting_colour(WHITE)
+            .with_origin((2, 3));
+        robot.run(&mut brain).unwrap();
+
+        assert_eq!(brain.io_record, vec![RecordedIO::UserInput(WHITE)]);
+        assert_eq!(robot.hull.colour_at((2, 3)), WHITE);
+        assert_eq!(robot.hull.colour_at((0, 0)), BLACK);
+        assert_eq!(robot.hull.painted_count(), 0);
+    }
+
+    #[test]
+    fn feeds_the_current_panel_colour() {
+        // paint white, then come back to the start by turning right four times
+        let mut memory = scripted_brain(&[(1, 0), (0, 0), (0, 0), (0, 0)]);
+        let mut brain = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        let mut robot = HullRobot::new()
+            .with_origin((5, 5))
+            .with_starting_colour(WHITE)
+            .with_turn_convention(TurnConvention::ZeroIsRight);
+        robot.run(&mut brain).unwrap();
+
+        assert_eq!(robot.position(), (5, 5));
+        assert_eq!(robot.facing(), Direction::Up);
+        assert_eq!(robot.hull.colour_at((5, 5)), WHITE);
+        assert_eq!(robot.hull.colour_at((4, 5)), BLACK);
+        assert_eq!(robot.hull.painted_count(), 4);
+        let inputs = brain.io_record.iter()
+            .filter_map(|entry| match entry {
+                RecordedIO::UserInput(value) => Some(*value),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(inputs, vec![WHITE, BLACK, BLACK, BLACK, WHITE]);
+    }
+
+    #[test]
+    fn rejects_invalid_turns() {
+        let mut memory = scripted_brain(&[(1, 2)]);
+        let mut brain = Computer::new(&mut memory).with_trace_level(TraceLevel::Silent);
+        assert_eq!(HullRobot::new().run(&mut brain), Err(RobotError::InvalidTurn(2)));
+    }
+}
@@ -0,0 +1,413 @@
+use failure::Fail;
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use super::{Address, Computer, ExecutionBudget, Fault, Memory, MemoryValue, RecordedIO, SimpleMemory, TraceLevel};
+use super::hooks::{ExecutionHook, HookAction, StepState};
+
+#[derive(Copy, Clone, Debug, Fail, Eq, PartialEq)]
+pub enum SearchError {
+    #[fail(display = "the search space has more candidates than can be indexed")]
+    SpaceTooLarge,
+}
+
+/// A space of candidate assignments, each a list of values. Spaces are indexed rather than
+/// iterated so that threads can share one out without coordinating beyond a counter.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SearchSpace {
+    /// Each value in the range, as a single-value candidate.
+    Range(RangeInclusive<MemoryValue>),
+    /// Each of the given values, as a single-value candidate.
+    Values(Vec<MemoryValue>),
+    /// Every ordering of the given values, in lexicographic order of position.
+    Permutations(Vec<MemoryValue>),
+    /// Every combination of one candidate from each space, concatenated. The last space varies
+    /// fastest, like nested loops.
+    Product(Vec<SearchSpace>),
+}
+
+impl SearchSpace {
+    pub fn range(range: RangeInclusive<MemoryValue>) -> SearchSpace {
+        SearchSpace::Range(range)
+    }
+
+    pub fn values<I: IntoIterator<Item=MemoryValue>>(values: I) -> SearchSpace {
+        SearchSpace::Values(values.into_iter().collect())
+    }
+
+    pub fn permutations<I: IntoIterator<Item=MemoryValue>>(values: I) -> SearchSpace {
+        SearchSpace::Permutations(values.into_iter().collect())
+    }
+
+    pub fn product<I: IntoIterator<Item=SearchSpace>>(spaces: I) -> SearchSpace {
+        SearchSpace::Product(spaces.into_iter().collect())
+    }
+
+    /// The number of candidates in the space, or an error for spaces too large to index, such as
+    /// the permutations of more than 20 values on a 64-bit machine.
+    pub fn len(&self) -> Result<usize, SearchError> {
+        match self {
+            SearchSpace::Range(range) if range.is_empty() => Ok(0),
+            SearchSpace::Range(range) => usize::try_from(*range.end() as i64 - *range.start() as i64 + 1)
+                .map_err(|_| SearchError::SpaceTooLarge),
+            SearchSpace::Values(values) => Ok(values.len()),
+            SearchSpace::Permutations(values) => checked_product((1..=values.len()).map(Ok)),
+            SearchSpace::Product(spaces) => checked_product(spaces.iter().map(SearchSpace::len)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Ok(0)
+    }
+
+    /// The candidate at `index`, which must be less than [`len`](SearchSpace::len), so the space
+    /// can't be too large to index.
+    pub fn candidate(&self, index: usize) -> Vec<MemoryValue> {
+        let mut candidate = Vec::new();
+        self.append_candidate(index, &mut candidate);
+        candidate
+    }
+
+    fn append_candidate(&self, mut index: usize, candidate: &mut Vec<MemoryValue>) {
+        match self {
+            SearchSpace::Range(range) => candidate.push((*range.start() as i64 + index as i64) as MemoryValue),
+            SearchSpace::Values(values) => candidate.push(values[index]),
+            SearchSpace::Permutations(values) => {
+                // read the index as a factorial-base number, each digit picking from what's left
+                let mut remaining = values.clone();
+                for position in 0..values.len() {
+                    let block = (1..values.len() - position).product::<usize>();
+                    candidate.push(remaining.remove(index / block));
+                    index %= block;
+                }
+            },
+            SearchSpace::Product(spaces) => {
+                let start = candidate.len();
+                for space in spaces.iter().rev() {
+                    // no part of a space that can be indexed is too large to index
+                    let length = space.len().expect("search space too large to index");
+                    let mut part = Vec::new();
+                    space.append_candidate(index % length, &mut part);
+                    index /= length;
+                    candidate.splice(start..start, part);
+                }
+            },
+        }
+    }
+
+    pub fn candidates(&self) -> Result<impl Iterator<Item=Vec<MemoryValue>> + '_, SearchError> {
+        Ok((0..self.len()?).map(move |index| self.candidate(index)))
+    }
+
+    /// Evaluates `evaluate` on every candidate across `threads` threads, returning the results it
+    /// gives `Some` for in candidate order. With `first_only`, threads stop picking up candidates
+    /// once one past a match would be needed, and only the earliest match is returned, so the
+    /// result is the same whatever the number of threads. Evaluations still going once their
+    /// result can't be needed are told so through their [`Cancellation`], and should give up.
+    pub fn search<R, F>(&self, threads: usize, first_only: bool, evaluate: F) -> Result<Vec<(Vec<MemoryValue>, R)>, SearchError>
+        where R: Send, F: Fn(&[MemoryValue], &Cancellation) -> Option<R> + Sync
+    {
+        let total = self.len()?;
+        let next = AtomicUsize::new(0);
+        let first_match = AtomicUsize::new(usize::MAX);
+        let matches = Mutex::new(Vec::new());
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= total || (first_only && index > first_match.load(Ordering::Relaxed)) {
+                        break;
+                    }
+                    let candidate = self.candidate(index);
+                    let cancellation = Cancellation {
+                        index,
+                        first_match: if first_only { Some(&first_match) } else { None },
+                    };
+                    if let Some(result) = evaluate(&candidate, &cancellation) {
+                        first_match.fetch_min(index, Ordering::Relaxed);
+                        matches.lock().unwrap().push((index, candidate, result));
+                    }
+                });
+            }
+        });
+
+        let mut matches = matches.into_inner().unwrap();
+        matches.sort_by_key(|(index, _, _)| *index);
+        if first_only {
+            matches.truncate(1);
+        }
+        Ok(matches.into_iter()
+            .map(|(_, candidate, result)| (candidate, result))
+            .collect())
+    }
+}
+
+fn checked_product<I: Iterator<Item=Result<usize, SearchError>>>(mut lengths: I) -> Result<usize, SearchError> {
+    lengths.try_fold(1usize, |total, length| total.checked_mul(length?).ok_or(SearchError::SpaceTooLarge))
+}
+
+/// Tells an evaluation in [`SearchSpace::search`] whether its result is still wanted, which stops
+/// being the case once an earlier candidate has settled the first match.
+pub struct Cancellation<'s> {
+    index: usize,
+    first_match: Option<&'s AtomicUsize>,
+}
+
+impl<'s> Cancellation<'s> {
+    pub fn is_cancelled(&self) -> bool {
+        self.first_match.is_some_and(|first_match| self.index > first_match.load(Ordering::Relaxed))
+    }
+}
+
+/// Stops a run once its [`Cancellation`] says the result isn't wanted.
+struct StopWhenCancelled<'c, 's>(&'c Cancellation<'s>);
+
+impl<'c, 's> ExecutionHook for StopWhenCancelled<'c, 's> {
+    fn before_decode(&mut self, _state: StepState, _memory: &dyn Memory) -> HookAction {
+        if self.0.is_cancelled() { HookAction::Stop } else { HookAction::Continue }
+    }
+}
+
+/// Where a candidate value goes before the program runs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Binding {
+    Patch(Address),
+    Input,
+}
+
+/// The machine after running one candidate, for the goal to judge.
+pub struct Outcome {
+    pub memory: SimpleMemory,
+    pub outputs: Vec<MemoryValue>,
+    pub result: Result<(), Fault>,
+    pub cycles: usize,
+}
+
+pub struct SearchMatch {
+    pub candidate: Vec<MemoryValue>,
+    pub outcome: Outcome,
+}
+
+/// Runs a program once per candidate in a [`SearchSpace`], looking for runs that satisfy a goal.
+/// This generalises loops like day 2's search for a noun and verb:
+///
+/// ```ignore
+/// Search::new(program, SearchSpace::product(vec![SearchSpace::range(0..=99), SearchSpace::range(0..=99)]))
+///     .with_bindings(vec![Binding::Patch(1), Binding::Patch(2)])
+///     .find_first(|outcome| outcome.memory.read_slot(0) == Ok(19690720))?
+/// ```
+pub struct Search<'p> {
+    program: &'p [MemoryValue],
+    space: SearchSpace,
+    bindings: Vec<Binding>,
+    inputs: Vec<MemoryValue>,
+    budget: ExecutionBudget,
+    threads: usize,
+}
+
+impl<'p> Search<'p> {
+    pub fn new(program: &'p [MemoryValue], space: SearchSpace) -> Search<'p> {
+        Search {
+            program,
+            space,
+            bindings: Vec::new(),
+            inputs: Vec::new(),
+            budget: ExecutionBudget::unlimited(),
+            threads: std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+        }
+    }
+
+    /// Says where each value of a candidate goes, in order. Values without a binding are queued
+    /// as input, so with no bindings at all every candidate is a list of inputs.
+    pub fn with_bindings(mut self, bindings: Vec<Binding>) -> Search<'p> {
+        self.bindings = bindings;
+        self
+    }
+
+    /// Inputs queued after any taken from the candidate.
+    pub fn with_inputs<I: IntoIterator<Item=MemoryValue>>(mut self, inputs: I) -> Search<'p> {
+        self.inputs = inputs.into_iter().collect();
+        self
+    }
+
+    /// Limits each run, so candidates that send the program into a loop can't stall the search.
+    /// Without one, [`find_first`](Search::find_first) still stops runs once an earlier candidate
+    /// has matched, but a candidate before the first match that never halts stalls it forever.
+    pub fn with_budget(mut self, budget: ExecutionBudget) -> Search<'p> {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Search<'p> {
+        self.threads = threads;
+        self
+    }
+
+    /// Runs the program with `candidate` applied.
+    pub fn run(&self, candidate: &[MemoryValue]) -> Outcome {
+        self.run_cancellable(candidate, None)
+    }
+
+    fn run_cancellable(&self, candidate: &[MemoryValue], cancellation: Option<&Cancellation>) -> Outcome {
+        let mut stop = cancellation.map(StopWhenCancelled);
+        let mut memory = SimpleMemory::from_literal(self.program);
+        let mut inputs = Vec::new();
+        let mut patch_result = Ok(());
+        for (index, value) in candidate.iter().enumerate() {
+            match self.bindings.get(index).cloned().unwrap_or(Binding::Input) {
+                Binding::Patch(address) => patch_result = patch_result.and(memory.write_slot(address, *value)),
+                Binding::Input => inputs.push(*value),
+            }
+        }
+        inputs.extend(self.inputs.iter().cloned());
+
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_budget(self.budget)
+            .with_inputs(inputs);
+        if let Some(stop) = &mut stop {
+            computer.set_hook(Some(stop));
+        }
+        let result = match patch_result {
+            Ok(()) => computer.run_until_halted(),
+            // a patch outside memory faults before the first instruction
+            Err(error) => Err(Fault {
+                error,
+                instruction_pointer: 0,
+                cycle: 0,
+                word: None,
+                instruction: None,
+                window: Vec::new(),
+            }),
+        };
+        let cycles = computer.cycle_count();
+        let outputs = computer.io_record.iter()
+            .filter_map(|event| match event {
+                RecordedIO::Output(value) => Some(*value),
+                RecordedIO::UserInput(_) => None,
+            })
+            .collect();
+        Outcome {
+            memory,
+            outputs,
+            result,
+            cycles,
+        }
+    }
+
+    fn search<G: Fn(&Outcome) -> bool + Sync>(&self, first_only: bool, goal: G) -> Result<Vec<SearchMatch>, SearchError> {
+        let found = self.space.search(self.threads, first_only, |candidate, cancellation| {
+            let outcome = self.run_cancellable(candidate, Some(cancellation));
+            Some(outcome).filter(|outcome| !cancellation.is_cancelled() && goal(outcome))
+        })?;
+        Ok(found.into_iter()
+            .map(|(candidate, outcome)| SearchMatch { candidate, outcome })
+            .collect())
+    }
+
+    /// The first candidate, in search space order, whose run satisfies `goal`. Candidates are
+    /// tried in parallel and the search stops, abandoning runs of later candidates, as soon as the
+    /// answer is settled.
+    pub fn find_first<G: Fn(&Outcome) -> bool + Sync>(&self, goal: G) -> Result<Option<SearchMatch>, SearchError> {
+        Ok(self.search(true, goal)?.into_iter().next())
+    }
+
+    /// Every candidate whose run satisfies `goal`, in search space order.
+    pub fn find_all<G: Fn(&Outcome) -> bool + Sync>(&self, goal: G) -> Result<Vec<SearchMatch>, SearchError> {
+        self.search(false, goal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::intcode::{Memory, MemoryValue, SimpleMemory};
+    use crate::intcode::search::{Binding, Search, SearchError, SearchSpace};
+
+    #[test]
+    fn enumerates_candidates() {
+        assert_eq!(SearchSpace::range(3..=5).candidates().unwrap().collect::<Vec<_>>(), vec![vec![3], vec![4], vec![5]]);
+        assert!(SearchSpace::values(vec![]).is_empty());
+
+        let product = SearchSpace::product(vec![SearchSpace::range(0..=1), SearchSpace::values(vec![7, 8, 9])]);
+        assert_eq!(product.len(), Ok(6));
+        assert_eq!(product.candidates().unwrap().collect::<Vec<_>>(), vec![
+            vec![0, 7], vec![0, 8], vec![0, 9], vec![1, 7], vec![1, 8], vec![1, 9],
+        ]);
+
+        let permutations = SearchSpace::permutations(vec![1, 2, 3]);
+        assert_eq!(permutations.candidates().unwrap().collect::<Vec<_>>(), vec![
+            vec![1, 2, 3], vec![1, 3, 2], vec![2, 1, 3], vec![2, 3, 1], vec![3, 1, 2], vec![3, 2, 1],
+        ]);
+        assert_eq!(SearchSpace::permutations(0..5).len(), Ok(120));
+
+        let nested = SearchSpace::product(vec![SearchSpace::permutations(vec![1, 2]), SearchSpace::range(0..=1)]);
+        assert_eq!(nested.candidate(3), vec![2, 1, 1]);
+    }
+
+    #[test]
+    fn refuses_spaces_too_large_to_index() {
+        assert_eq!(SearchSpace::permutations(0..30).len(), Err(SearchError::SpaceTooLarge));
+        let huge = SearchSpace::product((0..3).map(|_| SearchSpace::range(MemoryValue::MIN..=MemoryValue::MAX)));
+        assert_eq!(huge.len(), Err(SearchError::SpaceTooLarge));
+        assert!(!huge.is_empty());
+        assert!(huge.candidates().is_err());
+        assert_eq!(Search::new(&[99], huge).find_first(|_| true).err(), Some(SearchError::SpaceTooLarge));
+    }
+
+    #[test]
+    fn finds_the_day2_noun_and_verb() {
+        let program = SimpleMemory::from_memory_file("input/day2.txt").unwrap();
+        let space = SearchSpace::product(vec![SearchSpace::range(0..=99), SearchSpace::range(0..=99)]);
+        let found = Search::new(program.as_slice(), space)
+            .with_bindings(vec![Binding::Patch(1), Binding::Patch(2)])
+            .find_first(|outcome| outcome.memory.read_slot(0) == Ok(19690720))
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.candidate.len(), 2);
+        assert_eq!(found.outcome.memory.read_slot(1), Ok(found.candidate[0]));
+        assert!(found.outcome.result.is_ok());
+    }
+
+    #[test]
+    fn searches_input_permutations() {
+        // in [30]; in [31]; in [32]; then outputs 100 * [30] + 10 * [31] + [32]
+        let program = [
+            3, 30, 3, 31, 3, 32,
+            1002, 30, 10, 30, 1, 30, 31, 30,
+            1002, 30, 10, 30, 1, 30, 32, 30,
+            4, 30, 99,
+            0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let search = Search::new(&program, SearchSpace::permutations(vec![0, 1, 2])).with_threads(3);
+        let found = search.find_first(|outcome| outcome.outputs == vec![210]).unwrap().unwrap();
+        assert_eq!(found.candidate, vec![2, 1, 0]);
+
+        let all = search.find_all(|outcome| outcome.outputs[0] % 2 == 0).unwrap();
+        let candidates = all.iter().map(|found| found.candidate.clone()).collect::<Vec<_>>();
+        assert_eq!(candidates, vec![vec![0, 1, 2], vec![1, 0, 2], vec![1, 2, 0], vec![2, 1, 0]]);
+    }
+
+    #[test]
+    fn abandons_runs_that_cannot_matter() {
+        // outputs its input if it's non-zero, and loops forever otherwise
+        let program = [3, 20, 1005, 20, 8, 1105, 1, 5, 4, 20, 99, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let search = Search::new(&program, SearchSpace::values(vec![1, 0, 0, 0])).with_threads(4);
+        let found = search.find_first(|outcome| outcome.outputs == vec![1]).unwrap().unwrap();
+        assert_eq!(found.candidate, vec![1]);
+    }
+
+    #[test]
+    fn stops_early_once_the_first_match_is_settled() {
+        let evaluated = AtomicUsize::new(0);
+        let space = SearchSpace::range(0..=99_999);
+        let found = space.search(4, true, |candidate, _| {
+            evaluated.fetch_add(1, Ordering::Relaxed);
+            Some(candidate[0]).filter(|value| *value >= 10)
+        }).unwrap();
+        assert_eq!(found, vec![(vec![10], 10)]);
+        assert!(evaluated.load(Ordering::Relaxed) < 1000);
+    }
+}
@@ -0,0 +1,251 @@
+use failure::{Fail, ResultExt};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use super::{Address, Compiler, Computer, ComputerError, ExecutionBudget, Memory, MemoryValue, ProtectionMap, RecordedIO, SimpleMemory, TraceLevel};
+
+/// A test case for an intcode program, written in TOML:
+///
+/// ```toml
+/// description = "adds 1 and 1"
+/// program = "1,0,0,0,99"     # or program_file = "path/relative/to/this/file.txt"
+///                            # or source/source_file, to compile with the `Compiler`
+/// memory_size = 100          # pad memory with zeros up to this many cells
+/// inputs = [1, 2]
+/// max_cycles = 1000
+/// outputs = [42]             # every value output, in order
+/// error = "unknown opcode"   # expect the run to fail with an error containing this
+///
+/// [memory]                   # expected final memory at chosen addresses
+/// 0 = 2
+/// ```
+///
+/// Anything left out isn't checked. A run that fails when no `error` is expected is always a
+/// mismatch.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TestSpec {
+    pub description: Option<String>,
+    pub program: Option<String>,
+    pub program_file: Option<PathBuf>,
+    pub source: Option<String>,
+    pub source_file: Option<PathBuf>,
+    pub memory_size: Option<usize>,
+    #[serde(default)]
+    pub inputs: Vec<MemoryValue>,
+    pub max_cycles: Option<usize>,
+    pub outputs: Option<Vec<MemoryValue>>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, MemoryValue>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Fail, Eq, PartialEq)]
+pub enum SpecError {
+    #[fail(display = "invalid test spec: {}", _0)]
+    Invalid(String),
+    #[fail(display = "test spec needs exactly one of `program`, `program_file`, `source` and `source_file`")]
+    ProgramMissingOrAmbiguous,
+    #[fail(display = "memory key {:?} is not an address", _0)]
+    InvalidAddress(String),
+}
+
+/// One way in which a run didn't match its spec.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Mismatch {
+    UnexpectedError(String),
+    MissingError { expected: String },
+    WrongError { expected: String, actual: String },
+    Outputs { expected: Vec<MemoryValue>, actual: Vec<MemoryValue> },
+    Memory { address: Address, expected: MemoryValue, actual: Result<MemoryValue, ComputerError> },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::UnexpectedError(actual) => write!(f, "unexpected error: {}", actual),
+            Mismatch::MissingError { expected } => write!(f, "expected an error containing {:?} but the program halted", expected),
+            Mismatch::WrongError { expected, actual } => write!(f, "expected an error containing {:?}, got: {}", expected, actual),
+            Mismatch::Outputs { expected, actual } => write!(f, "expected outputs {:?}, got {:?}", expected, actual),
+            Mismatch::Memory { address, expected, actual: Ok(actual) } => write!(f, "expected {} at address {}, found {}", expected, address, actual),
+            Mismatch::Memory { address, expected, actual: Err(error) } => write!(f, "expected {} at address {}, but {}", expected, address, error),
+        }
+    }
+}
+
+impl TestSpec {
+    pub fn parse(text: &str) -> Result<TestSpec, SpecError> {
+        let spec = toml::from_str::<TestSpec>(text)
+            .map_err(|error| SpecError::Invalid(error.message().into()))?;
+        let programs = [spec.program.is_some(), spec.program_file.is_some(), spec.source.is_some(), spec.source_file.is_some()];
+        if programs.iter().filter(|given| **given).count() != 1 {
+            return Err(SpecError::ProgramMissingOrAmbiguous);
+        }
+        spec.expected_memory()?;
+        Ok(spec)
+    }
+
+    /// Loads a spec from a file, resolving any `program_file` or `source_file` relative to the
+    /// spec's directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TestSpec, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut spec = TestSpec::parse(&std::fs::read_to_string(path)?).compat()?;
+        if let Some(directory) = path.parent() {
+            spec.program_file = spec.program_file.map(|program_file| directory.join(program_file));
+            spec.source_file = spec.source_file.map(|source_file| directory.join(source_file));
+        }
+        Ok(spec)
+    }
+
+    fn expected_memory(&self) -> Result<Vec<(Address, MemoryValue)>, SpecError> {
+        self.memory.iter()
+            .map(|(key, value)| key.parse::<Address>()
+                .map(|address| (address, *value))
+                .map_err(|_| SpecError::InvalidAddress(key.clone())))
+            .collect()
+    }
+
+    fn load_memory(&self) -> Result<(SimpleMemory, Address, ProtectionMap), Box<dyn Error>> {
+        let (memory, entry_point, protection) = match (&self.program, &self.program_file, &self.source, &self.source_file) {
+            (Some(program), None, None, None) => (program.parse::<SimpleMemory>().compat()?, 0, ProtectionMap::new()),
+            (None, Some(path), None, None) => SimpleMemory::load_program(path)?,
+            (None, None, Some(source), None) => (compile(source, "<spec>")?, 0, ProtectionMap::new()),
+            (None, None, None, Some(path)) => (compile(&std::fs::read_to_string(path)?, &path.to_string_lossy())?, 0, ProtectionMap::new()),
+            _ => return Err(SpecError::ProgramMissingOrAmbiguous.compat().into()),
+        };
+        match self.memory_size {
+            Some(size) if size > memory.as_slice().len() => {
+                let mut cells = memory.as_slice().to_vec();
+                cells.resize(size, 0);
+                Ok((SimpleMemory::from_literal(&cells), entry_point, protection))
+            },
+            _ => Ok((memory, entry_point, protection)),
+        }
+    }
+
+    /// Runs the program and compares what happened against the spec. The outer error is for
+    /// specs that can't be run at all, such as a missing program file.
+    pub fn run(&self) -> Result<Vec<Mismatch>, Box<dyn Error>> {
+        let (mut memory, entry_point, protection) = self.load_memory()?;
+        let mut budget = ExecutionBudget::unlimited();
+        if let Some(max_cycles) = self.max_cycles {
+            budget = budget.with_max_cycles(max_cycles);
+        }
+        let mut computer = Computer::new(&mut memory)
+            .with_entry_point(entry_point)
+            .with_protection(protection)
+            .with_trace_level(TraceLevel::Silent)
+            .with_budget(budget)
+            .with_inputs(self.inputs.iter().cloned());
+        let result = computer.run_until_halted();
+        let outputs = computer.io_record.iter()
+            .filter_map(|event| match event {
+                RecordedIO::Output(value) => Some(*value),
+                RecordedIO::UserInput(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut mismatches = Vec::new();
+        match (&self.error, result) {
+            (None, Ok(())) => {},
+            (None, Err(fault)) => mismatches.push(Mismatch::UnexpectedError(fault.to_string())),
+            (Some(expected), Ok(())) => mismatches.push(Mismatch::MissingError { expected: expected.clone() }),
+            // only the error itself, not the disassembly around where it happened
+            (Some(expected), Err(fault)) => if !fault.error.to_string().contains(expected.as_str()) {
+                mismatches.push(Mismatch::WrongError { expected: expected.clone(), actual: fault.to_string() });
+            },
+        }
+        if let Some(expected) = &self.outputs {
+            if *expected != outputs {
+                mismatches.push(Mismatch::Outputs { expected: expected.clone(), actual: outputs });
+            }
+        }
+        for (address, expected) in self.expected_memory().compat()? {
+            let actual = memory.read_slot(address);
+            if actual != Ok(expected) {
+                mismatches.push(Mismatch::Memory { address, expected, actual });
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+fn compile(source: &str, file_name: &str) -> Result<SimpleMemory, Box<dyn Error>> {
+    match Compiler::new(source).with_file_name(file_name).compile() {
+        Ok(program) => Ok(program.memory()),
+        Err(error) => Err(format!("{}: {}", file_name, error.render(source)).into()),
+    }
+}
+
+/// Loads and runs the spec at `path`, panicking with every mismatch if it fails. This is what
+/// the generated test for each file under `specs/` calls.
+pub fn check_spec_file<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    let spec = TestSpec::load(path)
+        .unwrap_or_else(|error| panic!("failed to load {}: {}", path.display(), error));
+    let mismatches = spec.run()
+        .unwrap_or_else(|error| panic!("failed to run {}: {}", path.display(), error));
+    if !mismatches.is_empty() {
+        let details = mismatches.iter()
+            .map(|mismatch| format!("\n  - {}", mismatch))
+            .collect::<String>();
+        panic!("{} ({}) failed:{}", path.display(), spec.description.as_deref().unwrap_or("no description"), details);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::spec::{Mismatch, SpecError, TestSpec};
+
+    // one test per file under `specs/`, generated by the build script
+    include!(concat!(env!("OUT_DIR"), "/spec_tests.rs"));
+
+    #[test]
+    fn can_parse_specs() {
+        let spec = TestSpec::parse(r#"
+            program = "3,0,4,0,99"
+            inputs = [7]
+            outputs = [7]
+
+            [memory]
+            0 = 7
+        "#).unwrap();
+        assert_eq!(spec.inputs, vec![7]);
+        assert_eq!(spec.memory.get("0"), Some(&7));
+
+        assert_eq!(TestSpec::parse("inputs = [1]"), Err(SpecError::ProgramMissingOrAmbiguous));
+        assert_eq!(TestSpec::parse("program = \"99\"\nsource = \"fn main() {}\""), Err(SpecError::ProgramMissingOrAmbiguous));
+        assert_eq!(TestSpec::parse("program = \"99\"\n[memory]\nfirst = 1"), Err(SpecError::InvalidAddress("first".into())));
+        assert!(TestSpec::parse("program = \"99\"\nimputs = [1]").is_err());
+    }
+
+    #[test]
+    fn reports_every_mismatch() {
+        let spec = TestSpec::parse(r#"
+            program = "3,0,4,0,99"
+            inputs = [7]
+            outputs = [8]
+            error = "unknown opcode"
+
+            [memory]
+            0 = 7
+            4 = 98
+        "#).unwrap();
+        assert_eq!(spec.run().unwrap(), vec![
+            Mismatch::MissingError { expected: "unknown opcode".into() },
+            Mismatch::Outputs { expected: vec![8], actual: vec![7] },
+            Mismatch::Memory { address: 4, expected: 98, actual: Ok(99) },
+        ]);
+
+        let spec = TestSpec::parse("program = \"1105,1,0\"\nmax_cycles = 10\nerror = \"unknown opcode\"").unwrap();
+        match spec.run().unwrap().as_slice() {
+            [Mismatch::WrongError { actual, .. }] => assert!(actual.starts_with("exceeded the cycle budget")),
+            other => panic!("unexpected mismatches: {:?}", other),
+        }
+
+        let spec = TestSpec::parse("program = \"1105,1,0\"\nmax_cycles = 10\nerror = \"jt 1, 0\"").unwrap();
+        assert!(matches!(spec.run().unwrap().as_slice(), [Mismatch::WrongError { .. }]));
+    }
+}
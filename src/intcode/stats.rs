@@ -0,0 +1,283 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use super::{Address, ExecuteResult, Instruction, Memory, MemoryValue, Opcode, ParameterMode, RecordedIO};
+use super::analysis::{Analyzer, StaticAnalysis};
+use super::hooks::{ExecutionHook, HookAction, StepState};
+
+/// An opcode along with the modes of its parameters, e.g. `add` reading one position and one
+/// immediate parameter and writing to a relative one.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct InstructionForm {
+    /// The opcode number, which forms are sorted by.
+    pub opcode: MemoryValue,
+    pub mnemonic: String,
+    pub modes: Vec<ParameterMode>,
+}
+
+impl InstructionForm {
+    pub fn of(instruction: &Instruction) -> InstructionForm {
+        InstructionForm {
+            opcode: instruction.opcode_number(),
+            mnemonic: instruction.mnemonic().into(),
+            modes: instruction.parameters().iter().map(|parameter| parameter.mode()).collect(),
+        }
+    }
+
+    /// The modes as one letter each: `p` for position, `i` for immediate and `r` for relative.
+    pub fn modes_text(&self) -> String {
+        self.modes.iter()
+            .map(|mode| match mode {
+                ParameterMode::Position => 'p',
+                ParameterMode::Immediate => 'i',
+                ParameterMode::Relative => 'r',
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for InstructionForm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modes.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.modes_text())
+        }
+    }
+}
+
+/// What only running a program can tell.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RunStatistics {
+    pub cycles: usize,
+    /// Conditional jumps executed, and how many of them jumped.
+    pub conditional_jumps: usize,
+    pub jumps_taken: usize,
+    pub io_operations: usize,
+}
+
+/// Counts of the instructions in a program, by [`InstructionForm`]. Static statistics count the
+/// instructions an [`Analyzer`] finds reachable once each, so they can miss code it couldn't
+/// follow. Dynamic statistics count every instruction executed: they're collected by giving a
+/// `Statistics` to [`Computer::with_hook`](super::Computer::with_hook), and also have
+/// [`RunStatistics`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Statistics {
+    pub forms: BTreeMap<InstructionForm, usize>,
+    pub instructions: usize,
+    /// Memory words taken up by the instructions counted, headers included.
+    pub words: usize,
+    pub run: Option<RunStatistics>,
+}
+
+impl Statistics {
+    pub fn new() -> Statistics {
+        Statistics::default()
+    }
+
+    pub fn from_static<M: Memory + ?Sized>(memory: &M, entry_point: Address) -> Statistics {
+        Statistics::from_analysis(&Analyzer::new(memory).with_entry_point(entry_point).run())
+    }
+
+    pub fn from_analysis(analysis: &StaticAnalysis) -> Statistics {
+        let mut statistics = Statistics::new();
+        for instruction in analysis.reachable_instructions().values() {
+            statistics.count(instruction);
+        }
+        statistics
+    }
+
+    fn count(&mut self, instruction: &Instruction) {
+        *self.forms.entry(InstructionForm::of(instruction)).or_insert(0) += 1;
+        self.instructions += 1;
+        self.words += instruction.length();
+    }
+
+    /// Counts by opcode alone, summing over parameter modes.
+    pub fn opcodes(&self) -> BTreeMap<(MemoryValue, &str), usize> {
+        let mut opcodes = BTreeMap::new();
+        for (form, count) in self.forms.iter() {
+            *opcodes.entry((form.opcode, form.mnemonic.as_str())).or_insert(0) += count;
+        }
+        opcodes
+    }
+
+    pub fn average_length(&self) -> Option<f64> {
+        match self.instructions {
+            0 => None,
+            instructions => Some(self.words as f64 / instructions as f64),
+        }
+    }
+
+    /// The fraction of conditional jumps that jumped, for dynamic statistics with any.
+    pub fn jump_taken_ratio(&self) -> Option<f64> {
+        match self.run {
+            Some(run) if run.conditional_jumps > 0 => Some(run.jumps_taken as f64 / run.conditional_jumps as f64),
+            _ => None,
+        }
+    }
+
+    pub fn io_per_thousand_cycles(&self) -> Option<f64> {
+        match self.run {
+            Some(run) if run.cycles > 0 => Some(run.io_operations as f64 * 1000.0 / run.cycles as f64),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let forms = self.forms.iter()
+            .map(|(form, count)| json!({
+                "opcode": form.opcode,
+                "mnemonic": form.mnemonic,
+                "modes": form.modes_text(),
+                "count": count,
+            }))
+            .collect::<Vec<_>>();
+        let mut report = json!({
+            "instructions": self.instructions,
+            "words": self.words,
+            "average_length": self.average_length(),
+            "forms": forms,
+        });
+        if let Some(run) = self.run {
+            report["cycles"] = json!(run.cycles);
+            report["conditional_jumps"] = json!(run.conditional_jumps);
+            report["jumps_taken"] = json!(run.jumps_taken);
+            report["jump_taken_ratio"] = json!(self.jump_taken_ratio());
+            report["io_operations"] = json!(run.io_operations);
+            report["io_per_thousand_cycles"] = json!(self.io_per_thousand_cycles());
+        }
+        report
+    }
+}
+
+impl ExecutionHook for Statistics {
+    fn on_io(&mut self, _event: RecordedIO) -> HookAction {
+        self.run.get_or_insert_with(RunStatistics::default).io_operations += 1;
+        HookAction::Continue
+    }
+
+    fn after_execute(&mut self, _state: StepState, instruction: &Instruction, result: &ExecuteResult) -> HookAction {
+        self.count(instruction);
+        let run = self.run.get_or_insert_with(RunStatistics::default);
+        run.cycles += 1;
+        if let Some(Opcode::JumpIfTrue) | Some(Opcode::JumpIfFalse) = instruction.opcode() {
+            run.conditional_jumps += 1;
+            if let ExecuteResult::JumpTo(_) = result {
+                run.jumps_taken += 1;
+            }
+        }
+        HookAction::Continue
+    }
+}
+
+/// A table of counts by form, most frequent first, followed by the totals and ratios.
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut forms = self.forms.iter().collect::<Vec<_>>();
+        forms.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        writeln!(f, "{:<12} {:>10} {:>7}", "instruction", "count", "share")?;
+        for (form, count) in forms {
+            let share = *count as f64 * 100.0 / self.instructions as f64;
+            writeln!(f, "{:<12} {:>10} {:>6.1}%", form.to_string(), count, share)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:<24} {}", "instructions", self.instructions)?;
+        if let Some(average_length) = self.average_length() {
+            writeln!(f, "{:<24} {:.2}", "average length", average_length)?;
+        }
+        if let Some(run) = self.run {
+            writeln!(f, "{:<24} {}", "cycles", run.cycles)?;
+            match self.jump_taken_ratio() {
+                Some(ratio) => writeln!(f, "{:<24} {:.1}% ({} of {})", "jumps taken", ratio * 100.0, run.jumps_taken, run.conditional_jumps)?,
+                None => writeln!(f, "{:<24} none executed", "jumps taken")?,
+            }
+            match self.io_per_thousand_cycles() {
+                Some(rate) => writeln!(f, "{:<24} {:.2} ({} in total)", "io per 1000 cycles", rate, run.io_operations)?,
+                None => writeln!(f, "{:<24} {}", "io operations", run.io_operations)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, ParameterMode, SimpleMemory, TraceLevel};
+    use crate::intcode::stats::{InstructionForm, RunStatistics, Statistics};
+
+    /// Reads a count and outputs it, counting down to zero.
+    const COUNTDOWN: &[i32] = &[3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0];
+
+    fn form(mnemonic: &str, opcode: i32, modes: &[ParameterMode]) -> InstructionForm {
+        InstructionForm { opcode, mnemonic: mnemonic.into(), modes: modes.to_vec() }
+    }
+
+    #[test]
+    fn counts_instructions_executed() {
+        let mut memory = SimpleMemory::from_literal(COUNTDOWN);
+        let mut statistics = Statistics::new();
+        Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![3])
+            .with_hook(&mut statistics)
+            .run_until_halted()
+            .unwrap();
+
+        use ParameterMode::*;
+        assert_eq!(statistics.forms.iter().map(|(form, count)| (form.clone(), *count)).collect::<Vec<_>>(), vec![
+            (form("add", 1, &[Position, Immediate, Position]), 3),
+            (form("in", 3, &[Position]), 1),
+            (form("out", 4, &[Position]), 3),
+            (form("jt", 5, &[Position, Immediate]), 3),
+            (form("hlt", 99, &[]), 1),
+        ]);
+        assert_eq!(statistics.instructions, 11);
+        assert_eq!(statistics.words, 2 + 3 * (2 + 4 + 3) + 1);
+        assert_eq!(statistics.run, Some(RunStatistics { cycles: 11, conditional_jumps: 3, jumps_taken: 2, io_operations: 4 }));
+        assert_eq!(statistics.jump_taken_ratio(), Some(2.0 / 3.0));
+        assert_eq!(statistics.io_per_thousand_cycles(), Some(4000.0 / 11.0));
+        assert_eq!(statistics.opcodes().get(&(1, "add")), Some(&3));
+    }
+
+    #[test]
+    fn counts_reachable_instructions_in_an_image() {
+        let statistics = Statistics::from_static(&SimpleMemory::from_literal(COUNTDOWN), 0);
+        assert_eq!(statistics.instructions, 5);
+        assert_eq!(statistics.words, 12);
+        assert_eq!(statistics.average_length(), Some(2.4));
+        assert_eq!(statistics.run, None);
+        assert_eq!(statistics.jump_taken_ratio(), None);
+        assert_eq!(statistics.forms.keys().map(|form| form.to_string()).collect::<Vec<_>>(), vec!["add pip", "in p", "out p", "jt pi", "hlt"]);
+    }
+
+    #[test]
+    fn formats_tables_and_json() {
+        let mut memory = SimpleMemory::from_literal(&[104, 7, 1105, 0, 0, 99]);
+        let mut statistics = Statistics::new();
+        Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_hook(&mut statistics)
+            .run_until_halted()
+            .unwrap();
+        assert_eq!(statistics.to_string(), [
+            "instruction       count   share",
+            "out i                 1   33.3%",
+            "jt ii                 1   33.3%",
+            "hlt                   1   33.3%",
+            "",
+            "instructions             3",
+            "average length           2.00",
+            "cycles                   3",
+            "jumps taken              0.0% (0 of 1)",
+            "io per 1000 cycles       333.33 (1 in total)",
+            "",
+        ].join("\n"));
+
+        let json = statistics.to_json();
+        assert_eq!(json["forms"][1]["modes"], "ii");
+        assert_eq!(json["jumps_taken"], 0);
+        assert_eq!(json["jump_taken_ratio"], 0.0);
+        assert_eq!(Statistics::from_static(&memory, 0).to_json().get("cycles"), None);
+    }
+}
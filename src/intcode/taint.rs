@@ -0,0 +1,209 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use super::{Address, ExecuteResult, Instruction, MemoryValue, Opcode, RecordedIO};
+use super::hooks::{ExecutionHook, HookAction, StepState};
+
+/// The indices of the inputs (counting from 0 in the order the program read them) that a value
+/// depends on.
+pub type Taint = BTreeSet<usize>;
+
+/// An output, and the inputs that influenced it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TaintedOutput {
+    pub value: MemoryValue,
+    pub inputs: Taint,
+}
+
+impl fmt::Display for TaintedOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.inputs.is_empty() {
+            return write!(f, "{} <- no inputs", self.value);
+        }
+        let inputs = self.inputs.iter()
+            .map(|index| index.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{} <- inputs {}", self.value, inputs)
+    }
+}
+
+/// Tracks which inputs every memory cell depends on as a program runs, to find out which inputs
+/// each output depends on. Add it to a [`Computer`](super::Computer) with
+/// [`Computer::with_hook`](super::Computer::with_hook).
+///
+/// A cell written by an instruction depends on everything the instruction read, so taint flows
+/// through arithmetic and comparisons, and a cell written by `Input` depends on that input. The
+/// words of the instruction itself count as read, so an instruction an input has modified taints
+/// what it writes. Otherwise taint follows values but not addresses: reading a cell through a
+/// relative base an input has adjusted doesn't taint the result.
+///
+/// With control dependence, values also depend on the inputs that decided any jump taken or not
+/// taken before they were written. That's conservative: a branch on an input taints everything
+/// after it, not just the code it guards.
+#[derive(Clone, Debug, Default)]
+pub struct TaintTracker {
+    control_dependence: bool,
+    cells: HashMap<Address, Taint>,
+    /// What the instruction being executed has read so far.
+    read: Taint,
+    /// What has decided the path taken through the program so far.
+    control: Taint,
+    inputs_read: usize,
+    outputs: Vec<TaintedOutput>,
+}
+
+impl TaintTracker {
+    pub fn new() -> TaintTracker {
+        TaintTracker::default()
+    }
+
+    pub fn with_control_dependence(mut self) -> TaintTracker {
+        self.control_dependence = true;
+        self
+    }
+
+    pub fn outputs(&self) -> &[TaintedOutput] {
+        &self.outputs
+    }
+
+    /// The inputs the value in a cell depends on, leaving out control dependence.
+    pub fn taint_of(&self, address: Address) -> Taint {
+        self.cells.get(&address).cloned().unwrap_or_default()
+    }
+
+    /// The inputs that have decided jumps so far. Always empty without control dependence.
+    pub fn control_taint(&self) -> &Taint {
+        &self.control
+    }
+
+    /// What a value written or output now depends on, given what was read to produce it.
+    fn current_taint(&self) -> Taint {
+        self.read.union(&self.control).cloned().collect()
+    }
+}
+
+impl ExecutionHook for TaintTracker {
+    fn after_decode(&mut self, state: StepState, instruction: &Instruction) -> HookAction {
+        self.read.clear();
+        let words = state.instruction_pointer..state.instruction_pointer + instruction.length();
+        for address in words {
+            if let Some(taint) = self.cells.get(&address) {
+                self.read.extend(taint.iter().cloned());
+            }
+        }
+        HookAction::Continue
+    }
+
+    fn on_read(&mut self, address: Address, _value: MemoryValue) -> HookAction {
+        if let Some(taint) = self.cells.get(&address) {
+            self.read.extend(taint.iter().cloned());
+        }
+        HookAction::Continue
+    }
+
+    fn on_write(&mut self, address: Address, _value: MemoryValue) -> HookAction {
+        let taint = self.current_taint();
+        if taint.is_empty() {
+            self.cells.remove(&address);
+        } else {
+            self.cells.insert(address, taint);
+        }
+        HookAction::Continue
+    }
+
+    fn on_io(&mut self, event: RecordedIO) -> HookAction {
+        match event {
+            // the input is read before being written, so it counts as part of what was read
+            RecordedIO::UserInput(_) => {
+                self.read.insert(self.inputs_read);
+                self.inputs_read += 1;
+            },
+            RecordedIO::Output(value) => {
+                let inputs = self.current_taint();
+                self.outputs.push(TaintedOutput { value, inputs });
+            },
+        }
+        HookAction::Continue
+    }
+
+    fn after_execute(&mut self, _state: StepState, instruction: &Instruction, _result: &ExecuteResult) -> HookAction {
+        if self.control_dependence {
+            if let Some(Opcode::JumpIfTrue) | Some(Opcode::JumpIfFalse) = instruction.opcode() {
+                self.control.extend(self.read.iter().cloned());
+            }
+        }
+        HookAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, SimpleMemory, TraceLevel};
+    use crate::intcode::taint::{TaintTracker, TaintedOutput};
+
+    fn run(program: &[i32], inputs: Vec<i32>, mut tracker: TaintTracker) -> TaintTracker {
+        let mut memory = SimpleMemory::from_literal(program);
+        Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(inputs)
+            .with_hook(&mut tracker)
+            .run_until_halted()
+            .unwrap();
+        tracker
+    }
+
+    fn depends_on(tracker: &TaintTracker) -> Vec<Vec<usize>> {
+        tracker.outputs().iter().map(|output| output.inputs.iter().cloned().collect()).collect()
+    }
+
+    #[test]
+    fn taint_flows_through_arithmetic_and_comparisons() {
+        // reads a, b and c, then outputs a + b, b * 2, a < c, 7 and c == c
+        let program = [
+            3, 100, 3, 101, 3, 102,
+            1, 100, 101, 103, 4, 103,
+            1002, 101, 2, 104, 4, 104,
+            7, 100, 102, 105, 4, 105,
+            104, 7,
+            8, 102, 102, 106, 4, 106,
+            99,
+        ];
+        let mut memory = program.to_vec();
+        memory.resize(107, 0);
+        let tracker = run(&memory, vec![1, 2, 3], TaintTracker::new());
+        assert_eq!(tracker.outputs().iter().map(|output| output.value).collect::<Vec<_>>(), vec![3, 4, 1, 7, 1]);
+        assert_eq!(depends_on(&tracker), vec![vec![0, 1], vec![1], vec![0, 2], vec![], vec![2]]);
+        assert_eq!(tracker.taint_of(103).into_iter().collect::<Vec<_>>(), vec![0, 1]);
+        assert!(tracker.taint_of(0).is_empty());
+    }
+
+    #[test]
+    fn overwriting_a_cell_replaces_its_taint() {
+        // reads into 9, overwrites it with a constant and outputs it
+        let program = [3, 9, 1101, 1, 1, 9, 4, 9, 99, 0];
+        let tracker = run(&program, vec![5], TaintTracker::new());
+        assert_eq!(tracker.outputs(), &[TaintedOutput { value: 2, inputs: Default::default() }]);
+        assert_eq!(tracker.outputs()[0].to_string(), "2 <- no inputs");
+    }
+
+    #[test]
+    fn modified_instructions_carry_taint() {
+        // reads a value into the immediate parameter of the output that follows
+        let program = [3, 3, 104, 0, 99];
+        let tracker = run(&program, vec![6], TaintTracker::new());
+        assert_eq!(tracker.outputs()[0].to_string(), "6 <- inputs 0");
+    }
+
+    #[test]
+    fn jumps_can_add_control_dependence() {
+        // reads a flag and b, then outputs 1 if the flag is set and 0 otherwise, and then b
+        let program = [3, 15, 3, 16, 1005, 15, 10, 104, 0, 99, 104, 1, 4, 16, 99, 0, 0];
+        let tracker = run(&program, vec![1, 8], TaintTracker::new());
+        assert_eq!(depends_on(&tracker), vec![vec![], vec![1]]);
+
+        let tracker = run(&program, vec![1, 8], TaintTracker::new().with_control_dependence());
+        assert_eq!(depends_on(&tracker), vec![vec![0], vec![0, 1]]);
+        assert_eq!(tracker.outputs()[1].to_string(), "8 <- inputs 0, 1");
+        assert_eq!(tracker.control_taint().iter().cloned().collect::<Vec<_>>(), vec![0]);
+    }
+}
@@ -0,0 +1,632 @@
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{stdout, Write};
+use std::time::{Duration, Instant};
+use super::{Address, Computer, ComputerError, Memory, MemoryValue, RecordedIO};
+use super::breakpoint::Condition;
+use super::disassemble::{disassemble_with, DisassemblyLine};
+
+/// How many of the most recent writes are highlighted in the memory grid.
+const RECENT_WRITES: usize = 16;
+
+/// How many already executed instructions are shown above the instruction pointer.
+const DISASSEMBLY_HISTORY: usize = 4;
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Run speeds in instructions per second, selected with `+` and `-`. `None` runs flat out.
+const SPEEDS: [Option<u32>; 12] = [
+    Some(1), Some(2), Some(5), Some(10), Some(50), Some(100),
+    Some(500), Some(1_000), Some(10_000), Some(100_000), Some(1_000_000), None,
+];
+
+const HELP: &str = "space run/pause  s step  +/- speed  g go to address  f follow ip  i input  b break when  c clear  q quit";
+
+/// Memory that remembers which cells were written most recently, so the visualizer can
+/// highlight them.
+pub struct WriteTracker<M: Memory> {
+    inner: M,
+    recent: VecDeque<Address>,
+}
+
+impl<M: Memory> WriteTracker<M> {
+    pub fn new(inner: M) -> WriteTracker<M> {
+        WriteTracker {
+            inner,
+            recent: VecDeque::with_capacity(RECENT_WRITES),
+        }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Addresses written recently, oldest first.
+    pub fn recent_writes(&self) -> impl Iterator<Item=Address> + '_ {
+        self.recent.iter().cloned()
+    }
+}
+
+impl<M: Memory> Memory for WriteTracker<M> {
+    fn read_slot(&self, slot: Address) -> Result<MemoryValue, ComputerError> {
+        self.inner.read_slot(slot)
+    }
+
+    fn write_slot(&mut self, slot: Address, value: MemoryValue) -> Result<(), ComputerError> {
+        self.inner.write_slot(slot, value)?;
+        self.recent.retain(|address| *address != slot);
+        if self.recent.len() == RECENT_WRITES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(slot);
+        Ok(())
+    }
+
+    fn read_stream_from<'a>(&'a self, slot: Address) -> Result<Box<dyn Iterator<Item=MemoryValue> + 'a>, ComputerError> {
+        self.inner.read_stream_from(slot)
+    }
+
+    fn peek_slot(&self, slot: Address) -> Result<MemoryValue, ComputerError> {
+        self.inner.peek_slot(slot)
+    }
+
+    fn peek_stream_from<'a>(&'a self, slot: Address) -> Result<Box<dyn Iterator<Item=MemoryValue> + 'a>, ComputerError> {
+        self.inner.peek_stream_from(slot)
+    }
+
+    fn is_device(&self, slot: Address) -> bool {
+        self.inner.is_device(slot)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Style {
+    Plain,
+    Heading,
+    Current,
+    RecentWrite,
+    LatestWrite,
+}
+
+/// A line of text made up of differently styled pieces.
+type Line = Vec<(String, Style)>;
+
+/// A fully laid out frame, kept separate from the terminal so layout can be tested.
+struct Screen {
+    lines: Vec<Line>,
+}
+
+impl Screen {
+    fn draw<W: Write>(&self, out: &mut W, width: usize) -> Result<(), Box<dyn Error>> {
+        for (row, line) in self.lines.iter().enumerate() {
+            queue!(out, MoveTo(0, row as u16))?;
+            let mut remaining = width;
+            for (text, style) in line.iter() {
+                let text = text.chars().take(remaining).collect::<String>();
+                remaining -= text.chars().count();
+                match style {
+                    Style::Plain => {},
+                    Style::Heading => queue!(out, SetAttribute(Attribute::Reverse))?,
+                    Style::Current => queue!(out, SetAttribute(Attribute::Bold), SetForegroundColor(Color::Yellow))?,
+                    Style::RecentWrite => queue!(out, SetForegroundColor(Color::Red))?,
+                    Style::LatestWrite => queue!(out, SetForegroundColor(Color::White), SetBackgroundColor(Color::Red))?,
+                }
+                queue!(out, Print(text), SetAttribute(Attribute::Reset), ResetColor)?;
+            }
+            queue!(out, Clear(ClearType::UntilNewLine))?;
+        }
+        queue!(out, Clear(ClearType::FromCursorDown))?;
+        out.flush()?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn text(&self) -> Vec<String> {
+        self.lines.iter()
+            .map(|line| line.iter().map(|(text, _)| text.as_str()).collect())
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum PromptKind {
+    Input,
+    GoTo,
+    Condition,
+}
+
+/// A full screen terminal viewer that drives a [`Computer`] one [`Computer::step`] at a time,
+/// showing memory (with recent writes highlighted), disassembly around the instruction pointer,
+/// the IO record, and cycle and instruction rate counters. A [`Condition`] entered with `b`
+/// pauses the program whenever it holds while running.
+///
+/// The computer should be created with a [`TraceLevel::Silent`](super::TraceLevel::Silent)
+/// trace level so nothing else writes to the terminal. When a program runs out of queued input
+/// the viewer pauses and asks for more.
+pub struct Visualizer<'c, 'a, M: Memory> {
+    computer: &'c mut Computer<'a, WriteTracker<M>>,
+    running: bool,
+    speed: usize,
+    step_credit: f64,
+    follow: bool,
+    memory_view: Address,
+    prompt: Option<(PromptKind, String)>,
+    status: Option<String>,
+    condition: Option<Condition>,
+    /// Whether the condition is checked before the next step; not straight after resuming, so
+    /// that a hit doesn't stop the program again where it already is.
+    condition_armed: bool,
+    history: VecDeque<Address>,
+    rate: f64,
+    rate_started_at: Instant,
+    rate_started_cycle: usize,
+}
+
+impl<'c, 'a, M: Memory> Visualizer<'c, 'a, M> {
+    pub fn new(computer: &'c mut Computer<'a, WriteTracker<M>>) -> Visualizer<'c, 'a, M> {
+        let rate_started_cycle = computer.cycle_count();
+        Visualizer {
+            computer,
+            running: false,
+            speed: 5,
+            step_credit: 0.0,
+            follow: true,
+            memory_view: 0,
+            prompt: None,
+            status: None,
+            condition: None,
+            condition_armed: false,
+            history: VecDeque::with_capacity(DISASSEMBLY_HISTORY + 1),
+            rate: 0.0,
+            rate_started_at: Instant::now(),
+            rate_started_cycle,
+        }
+    }
+
+    /// Takes over the terminal until the user quits.
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut out = stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, Hide)?;
+        let result = self.event_loop(&mut out);
+        execute!(out, Show, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn event_loop<W: Write>(&mut self, out: &mut W) -> Result<(), Box<dyn Error>> {
+        let mut last_frame = Instant::now();
+        loop {
+            let (width, height) = terminal::size()?;
+            self.render(width as usize, height as usize).draw(out, width as usize)?;
+
+            let deadline = last_frame + FRAME_INTERVAL;
+            loop {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if !event::poll(timeout)? {
+                    break;
+                }
+                if let Event::Key(key) = event::read()? {
+                    if key.kind != KeyEventKind::Release && !self.handle_key(key.code) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            let elapsed = last_frame.elapsed();
+            last_frame = Instant::now();
+            if self.running {
+                self.run_for(elapsed);
+            }
+            self.update_rate();
+        }
+    }
+
+    /// Returns false once the user asks to quit.
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        if let Some((kind, text)) = &mut self.prompt {
+            match key {
+                KeyCode::Char(c) if *kind == PromptKind::Condition => text.push(c),
+                KeyCode::Char(c) if c.is_ascii_digit() || (c == '-' && text.is_empty()) => text.push(c),
+                KeyCode::Backspace => { text.pop(); },
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Enter if *kind == PromptKind::Condition => {
+                    match text.parse::<Condition>() {
+                        Ok(condition) => {
+                            self.status = Some(format!("break when {}", condition));
+                            self.condition = Some(condition);
+                        },
+                        Err(error) => self.status = Some(format!("invalid condition: {}", error)),
+                    }
+                    self.prompt = None;
+                },
+                KeyCode::Enter if *kind == PromptKind::Input => {
+                    match text.parse::<MemoryValue>() {
+                        Ok(value) => {
+                            self.computer.push_input(value);
+                            self.status = None;
+                        },
+                        Err(error) => self.status = Some(format!("invalid input: {}", error)),
+                    }
+                    self.prompt = None;
+                },
+                KeyCode::Enter => {
+                    match text.parse::<Address>() {
+                        Ok(address) => {
+                            self.follow = false;
+                            self.memory_view = address;
+                        },
+                        Err(error) => self.status = Some(format!("invalid address: {}", error)),
+                    }
+                    self.prompt = None;
+                },
+                _ => {},
+            }
+            return true;
+        }
+
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char(' ') => {
+                self.running = !self.running && !self.computer.halted;
+                self.step_credit = 0.0;
+                self.condition_armed = false;
+            },
+            KeyCode::Char('s') | KeyCode::Char('n') => {
+                self.running = false;
+                self.step_once();
+            },
+            KeyCode::Char('+') | KeyCode::Char('=') => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            KeyCode::Char('-') => self.speed = self.speed.saturating_sub(1),
+            KeyCode::Char('g') => self.prompt = Some((PromptKind::GoTo, String::new())),
+            KeyCode::Char('i') => self.prompt = Some((PromptKind::Input, String::new())),
+            KeyCode::Char('f') => self.follow = true,
+            KeyCode::Char('b') => self.prompt = Some((PromptKind::Condition, String::new())),
+            KeyCode::Char('c') => {
+                self.condition = None;
+                self.status = None;
+            },
+            _ => {},
+        }
+        true
+    }
+
+    /// Steps while running, unless the breakpoint condition holds first.
+    fn run_step(&mut self) {
+        if let Some(condition) = self.condition.as_ref().filter(|_| self.condition_armed) {
+            if condition.evaluate(self.computer) {
+                self.running = false;
+                self.condition_armed = false;
+                self.status = Some(format!("hit {}", condition));
+                return;
+            }
+        }
+        self.step_once();
+    }
+
+    fn step_once(&mut self) {
+        self.condition_armed = true;
+        if self.computer.halted {
+            self.running = false;
+            return;
+        }
+        let address = self.computer.instruction_pointer();
+        match self.computer.step() {
+            Ok(()) => {
+                if self.history.len() == DISASSEMBLY_HISTORY {
+                    self.history.pop_front();
+                }
+                self.history.push_back(address);
+                if self.computer.halted {
+                    self.running = false;
+                    self.status = Some("halted".into());
+                }
+            },
+            Err(fault) => {
+                self.running = false;
+                if fault.error == ComputerError::NoInputAvailable {
+                    self.prompt = Some((PromptKind::Input, String::new()));
+                } else {
+                    self.status = Some(format!("{} at address {}", fault.error, fault.instruction_pointer));
+                }
+            },
+        }
+    }
+
+    fn run_for(&mut self, elapsed: Duration) {
+        match SPEEDS[self.speed] {
+            Some(speed) => {
+                self.step_credit += speed as f64 * elapsed.as_secs_f64();
+                while self.running && self.step_credit >= 1.0 {
+                    self.step_credit -= 1.0;
+                    self.run_step();
+                }
+            },
+            None => {
+                let started_at = Instant::now();
+                while self.running && started_at.elapsed() < FRAME_INTERVAL {
+                    for _ in 0..256 {
+                        if !self.running {
+                            break;
+                        }
+                        self.run_step();
+                    }
+                }
+            },
+        }
+    }
+
+    fn update_rate(&mut self) {
+        let elapsed = self.rate_started_at.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let cycles = self.computer.cycle_count() - self.rate_started_cycle;
+            self.rate = cycles as f64 / elapsed.as_secs_f64();
+            self.rate_started_at = Instant::now();
+            self.rate_started_cycle = self.computer.cycle_count();
+        }
+    }
+
+    fn render(&self, width: usize, height: usize) -> Screen {
+        let mut lines = vec![vec![(self.status_line(), Style::Heading)]];
+
+        let remaining = height.saturating_sub(3);
+        let memory_rows = (remaining / 2).max(1);
+        lines.extend(self.memory_grid(width, memory_rows));
+
+        let half = width / 2;
+        lines.push(vec![
+            (format!("{:<1$}", " disassembly", half), Style::Heading),
+            (format!("{:<1$}", " io", width - half), Style::Heading),
+        ]);
+        let pane_rows = remaining.saturating_sub(memory_rows);
+        let disassembly = self.disassembly(pane_rows);
+        let io = self.io_lines(pane_rows);
+        for row in 0..pane_rows {
+            let mut line = match disassembly.get(row) {
+                Some((text, style)) => vec![(format!("{:<1$.1$}", text, half), *style)],
+                None => vec![(" ".repeat(half), Style::Plain)],
+            };
+            if let Some(text) = io.get(row) {
+                line.push((text.clone(), Style::Plain));
+            }
+            lines.push(line);
+        }
+
+        let footer = match &self.prompt {
+            Some((PromptKind::Input, text)) => format!("input value> {}", text),
+            Some((PromptKind::GoTo, text)) => format!("go to address> {}", text),
+            Some((PromptKind::Condition, text)) => format!("break when> {}", text),
+            None => HELP.into(),
+        };
+        lines.push(vec![(footer, Style::Plain)]);
+        lines.truncate(height);
+        Screen { lines }
+    }
+
+    fn status_line(&self) -> String {
+        let state = match (&self.status, self.running) {
+            (Some(status), _) => status.as_str(),
+            (None, true) => "running",
+            (None, false) => "paused",
+        };
+        let speed = match SPEEDS[self.speed] {
+            Some(speed) => format!("{}/s", speed),
+            None => "max".into(),
+        };
+        format!(
+            " {} | cycle {} | ip {} | rb {} | {:.0} instr/s | speed {} ",
+            state,
+            self.computer.cycle_count(),
+            self.computer.instruction_pointer(),
+            self.computer.relative_base(),
+            self.rate,
+            speed,
+        )
+    }
+
+    fn memory_grid(&self, width: usize, rows: usize) -> Vec<Line> {
+        let columns = (width.saturating_sub(8) / 7).max(1);
+        let memory = self.computer.memory();
+        let recent = memory.recent_writes().collect::<Vec<_>>();
+        let ip = self.computer.instruction_pointer();
+
+        let focus = if self.follow { ip } else { self.memory_view };
+        // keep a row of context above the focused address when following the instruction pointer
+        let first_row = (focus / columns).saturating_sub(if self.follow { 1 } else { 0 });
+
+        (first_row..first_row + rows)
+            .map(|row| {
+                let start = row * columns;
+                let mut line = vec![(format!("{:>6}: ", start), Style::Heading)];
+                for address in start..start + columns {
+                    let value = match memory.peek_slot(address) {
+                        Ok(value) => value,
+                        Err(_) => break,
+                    };
+                    let style = if address == ip {
+                        Style::Current
+                    } else if recent.last() == Some(&address) {
+                        Style::LatestWrite
+                    } else if recent.contains(&address) {
+                        Style::RecentWrite
+                    } else {
+                        Style::Plain
+                    };
+                    line.push((format!("{:>6}", value), style));
+                    line.push((" ".into(), Style::Plain));
+                }
+                line
+            })
+            .collect()
+    }
+
+    fn disassembly(&self, rows: usize) -> Vec<(String, Style)> {
+        let memory: &WriteTracker<M> = self.computer.memory();
+        let registry = self.computer.registry();
+        let ip = self.computer.instruction_pointer();
+        let history = self.history.iter()
+            .filter(|address| **address != ip)
+            .filter_map(|address| DisassemblyLine::decode_at_with(registry, memory, *address))
+            .map(|line| (format!("  {}", line), Style::Plain));
+        let upcoming = disassemble_with(registry, memory, ip, rows)
+            .into_iter()
+            .map(|line| {
+                let current = line.address == ip;
+                (format!("{} {}", if current { ">" } else { " " }, line), if current { Style::Current } else { Style::Plain })
+            });
+        history.chain(upcoming).take(rows).collect()
+    }
+
+    fn io_lines(&self, rows: usize) -> Vec<String> {
+        let record = &self.computer.io_record;
+        record[record.len().saturating_sub(rows)..].iter()
+            .map(|entry| {
+                let (direction, value) = match entry {
+                    RecordedIO::UserInput(value) => ("in ", *value),
+                    RecordedIO::Output(value) => ("out", *value),
+                };
+                match (value as u8 as MemoryValue == value, value as u8 as char) {
+                    (true, c) if c.is_ascii_graphic() => format!(" {} {:>11} '{}'", direction, value, c),
+                    _ => format!(" {} {:>11}", direction, value),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, Memory, SimpleMemory, TraceLevel};
+    use crate::intcode::tui::{Style, Visualizer, WriteTracker};
+    use crossterm::event::KeyCode;
+
+    const PROGRAM: [i32; 12] = [3, 11, 1001, 11, 1, 11, 4, 11, 99, 0, 0, 0];
+
+    fn style_of(line: &[(String, Style)], text: &str) -> Option<Style> {
+        line.iter().find(|(span, _)| span.trim() == text).map(|(_, style)| *style)
+    }
+
+    #[test]
+    fn tracks_recent_writes() {
+        let mut memory = WriteTracker::new(SimpleMemory::from_literal(&[0; 20]));
+        for address in (0..20).chain(vec![3]) {
+            memory.write_slot(address, 1).unwrap();
+        }
+        let recent = memory.recent_writes().collect::<Vec<_>>();
+        assert_eq!(recent.len(), 16);
+        assert_eq!(recent.first(), Some(&5));
+        assert_eq!(recent.last(), Some(&3));
+    }
+
+    #[test]
+    fn renders_memory_disassembly_and_io() {
+        let mut memory = WriteTracker::new(SimpleMemory::from_literal(&PROGRAM));
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![41]);
+        let mut visualizer = Visualizer::new(&mut computer);
+        visualizer.step_once();
+        visualizer.step_once();
+
+        let screen = visualizer.render(80, 24);
+        let text = screen.text();
+        assert_eq!(text.len(), 24);
+        assert!(text[0].contains("cycle 2 | ip 6 | rb 0"));
+        assert!(text[1].starts_with("     0:      3     11   1001     11      1     11      4     11     99      0"));
+        assert_eq!(style_of(&screen.lines[1], "4"), Some(Style::Current));
+        assert_eq!(style_of(&screen.lines[2], "42"), Some(Style::LatestWrite));
+        assert!(text.iter().any(|line| line.contains(">      6: 4,11")));
+        assert!(text.iter().any(|line| line.contains("in           41 ')'")));
+        assert!(text[23].starts_with("space run/pause"));
+    }
+
+    #[test]
+    fn keys_control_execution() {
+        let mut memory = WriteTracker::new(SimpleMemory::from_literal(&PROGRAM));
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![]);
+        let mut visualizer = Visualizer::new(&mut computer);
+
+        assert!(visualizer.handle_key(KeyCode::Char(' ')));
+        assert!(visualizer.running);
+        let speed = visualizer.speed;
+        visualizer.handle_key(KeyCode::Char('+'));
+        assert_eq!(visualizer.speed, speed + 1);
+
+        // stepping pauses, and running out of input asks for more
+        visualizer.handle_key(KeyCode::Char('s'));
+        assert!(!visualizer.running);
+        assert!(visualizer.render(80, 24).text()[23].starts_with("input value>"));
+        for c in "99999999999".chars() {
+            visualizer.handle_key(KeyCode::Char(c));
+        }
+        visualizer.handle_key(KeyCode::Enter);
+        assert_eq!(visualizer.status.as_deref(), Some("invalid input: number too large to fit in target type"));
+        visualizer.handle_key(KeyCode::Char('s'));
+        assert_eq!(visualizer.computer.instruction_pointer(), 0);
+        assert!(visualizer.render(80, 24).text()[23].starts_with("input value>"));
+        for key in &[KeyCode::Char('4'), KeyCode::Char('1'), KeyCode::Enter] {
+            visualizer.handle_key(*key);
+        }
+        visualizer.handle_key(KeyCode::Char('s'));
+        assert_eq!(visualizer.computer.instruction_pointer(), 2);
+
+        for key in &[KeyCode::Char('g'), KeyCode::Char('1'), KeyCode::Char('6'), KeyCode::Enter] {
+            visualizer.handle_key(*key);
+        }
+        assert!(!visualizer.follow);
+        assert_eq!(visualizer.memory_view, 16);
+
+        visualizer.running = true;
+        visualizer.run_for(std::time::Duration::from_secs(10));
+        assert!(visualizer.computer.halted);
+        assert!(visualizer.render(80, 24).text()[0].starts_with(" halted"));
+        assert!(!visualizer.handle_key(KeyCode::Char('q')));
+    }
+
+    #[test]
+    fn conditions_pause_the_run() {
+        let mut memory = WriteTracker::new(SimpleMemory::from_literal(&PROGRAM));
+        let mut computer = Computer::new(&mut memory)
+            .with_trace_level(TraceLevel::Silent)
+            .with_inputs(vec![41]);
+        let mut visualizer = Visualizer::new(&mut computer);
+
+        visualizer.handle_key(KeyCode::Char('b'));
+        for c in "ip = = 6".chars().filter(|c| *c != ' ') {
+            visualizer.handle_key(KeyCode::Char(c));
+        }
+        assert!(visualizer.render(80, 24).text()[23].starts_with("break when> ip==6"));
+        visualizer.handle_key(KeyCode::Enter);
+        assert!(visualizer.condition.is_some());
+
+        visualizer.handle_key(KeyCode::Char(' '));
+        visualizer.run_for(std::time::Duration::from_secs(10));
+        assert!(!visualizer.running);
+        assert_eq!(visualizer.computer.instruction_pointer(), 6);
+
+        // resuming doesn't stop straight away at the same place
+        visualizer.handle_key(KeyCode::Char(' '));
+        visualizer.run_for(std::time::Duration::from_secs(10));
+        assert!(visualizer.computer.halted);
+
+        visualizer.handle_key(KeyCode::Char('b'));
+        for c in "ip=".chars() {
+            visualizer.handle_key(KeyCode::Char(c));
+        }
+        visualizer.handle_key(KeyCode::Enter);
+        assert!(visualizer.status.as_ref().unwrap().starts_with("invalid condition"));
+        visualizer.handle_key(KeyCode::Char('c'));
+        assert!(visualizer.condition.is_none());
+    }
+}
@@ -2,6 +2,7 @@ use std::io::{BufRead, Read};
 use std::error::Error;
 use std::path::Path;
 
+pub mod grid;
 pub mod intcode;
 
 pub fn fuel_for_mass(mass: i32) -> i32 {
@@ -28,8 +29,7 @@ pub fn load_lines_from<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Box<dyn E
     let buf_reader = std::io::BufReader::new(file);
     Ok(buf_reader.
         lines()
-        .filter_map(|line| line.ok())
-        .collect())
+        .collect::<Result<Vec<_>, _>>()?)
 }
 
 pub fn load_file<P: AsRef<Path>>(path: P) -> Result<String, Box<dyn Error>> {